@@ -1,18 +1,21 @@
 use pyo3::exceptions::{PyIOError, PyIndexError};
 use pyo3::prelude::*;
-use quick_xml::events::Event;
+use quick_xml::events::{BytesStart, Event};
 use quick_xml::Reader;
 use std::fs::File;
-use std::io::{Read, Write};
+use std::io::{BufRead, BufReader, Read, Write};
 use std::path::Path;
 use std::sync::{Arc, Mutex};
 use zip::write::FileOptions;
+use zip::result::ZipError;
 use zip::{CompressionMethod, ZipArchive, ZipWriter};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use base64::Engine;
+use std::borrow::Cow;
 use indexmap::IndexMap;
+use std::collections::HashMap;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
 pub struct TextStyle {
     pub bold: bool,
     pub italic: bool,
@@ -20,88 +23,750 @@ pub struct TextStyle {
     pub font_size: Option<String>,
     pub font_family: Option<String>,
     pub color: Option<String>,
+    #[serde(default)]
+    pub hidden: bool,
+    /// Background (highlight) color as `#rrggbb`.
+    #[serde(default)]
+    pub highlight: Option<String>,
 }
 
-impl Default for TextStyle {
-    fn default() -> Self {
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TextRun {
+    /// A `\n` here is a manual line break within the block (`w:br`,
+    /// `text:line-break`, `<br>`); paragraph breaks separate elements.
+    pub text: String,
+    pub style: TextStyle,
+    #[serde(default)]
+    pub link: Option<Link>,
+    /// Language of the run's text as a BCP 47 tag, when it is marked.
+    #[serde(default)]
+    pub lang: Option<String>,
+    /// Names of the bookmarks (named anchors) that start at this run.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub bookmarks: Vec<String>,
+}
+
+impl TextRun {
+    /// An unformatted run.
+    pub fn plain(text: impl Into<String>) -> Self {
+        Self {
+            text: text.into(),
+            style: TextStyle::default(),
+            link: None,
+            lang: None,
+            bookmarks: Vec::new(),
+        }
+    }
+
+    /// A bold run.
+    pub fn bold(text: impl Into<String>) -> Self {
         Self {
-            bold: false,
-            italic: false,
-            underline: false,
-            font_size: None,
-            font_family: None,
-            color: None,
+            style: TextStyle { bold: true, ..Default::default() },
+            ..Self::plain(text)
         }
     }
 }
 
+/// A hyperlink target; `title` is the tooltip shown on hover.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Link {
+    pub href: String,
+    #[serde(default)]
+    pub title: Option<String>,
+}
+
+fn default_span() -> usize {
+    1
+}
+
+/// A table cell; merged cells span several columns and/or rows, and the
+/// positions they cover are simply absent from the following cells and rows.
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct TextRun {
-    pub text: String,
-    pub style: TextStyle,
+pub struct TableCell {
+    pub runs: Vec<TextRun>,
+    #[serde(default = "default_span")]
+    pub colspan: usize,
+    #[serde(default = "default_span")]
+    pub rowspan: usize,
+}
+
+impl TableCell {
+    pub fn new(runs: Vec<TextRun>) -> Self {
+        Self {
+            runs,
+            colspan: 1,
+            rowspan: 1,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum DocumentElement {
-    Paragraph { runs: Vec<TextRun> },
-    Heading { level: u8, runs: Vec<TextRun> },
-    List { items: Vec<Vec<TextRun>>, ordered: bool },
-    Table { rows: Vec<Vec<Vec<TextRun>>> },
+    Paragraph {
+        runs: Vec<TextRun>,
+        /// Block shading as `#rrggbb`.
+        #[serde(default)]
+        background: Option<String>,
+        /// Left indentation in points, apart from any list nesting.
+        #[serde(default)]
+        indent: Option<f64>,
+        /// Horizontal alignment as a CSS `text-align` value: `left`, `right`,
+        /// `center`, `justify`, or the direction-relative `start` / `end`.
+        #[serde(default)]
+        alignment: Option<String>,
+        /// Writing direction, `"rtl"` or `"ltr"`, when set explicitly.
+        #[serde(default)]
+        direction: Option<String>,
+    },
+    Heading {
+        level: u8,
+        runs: Vec<TextRun>,
+        #[serde(default)]
+        background: Option<String>,
+        #[serde(default)]
+        direction: Option<String>,
+    },
+    List {
+        /// Block content of each item, normally one or more paragraphs.
+        items: Vec<Vec<DocumentElement>>,
+        ordered: bool,
+        /// Nesting depth of each item (0 = top level); empty for a flat list.
+        #[serde(default)]
+        levels: Vec<u8>,
+        /// How many numbers an ordered item at each depth shows, so a 2 at
+        /// depth 1 renders "1.1"; depths not listed show a single number.
+        #[serde(default)]
+        display_levels: Vec<u8>,
+    },
+    Table { rows: Vec<Vec<TableCell>> },
     LineBreak,
+    Image {
+        name: String,
+        mime_type: String,
+        #[serde(with = "base64_bytes")]
+        data: Vec<u8>,
+        alt: Option<String>,
+    },
+}
+
+impl DocumentElement {
+    /// Snake-case name of the element's variant, e.g. `line_break`.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            DocumentElement::Paragraph { .. } => "paragraph",
+            DocumentElement::Heading { .. } => "heading",
+            DocumentElement::List { .. } => "list",
+            DocumentElement::Table { .. } => "table",
+            DocumentElement::LineBreak => "line_break",
+            DocumentElement::Image { .. } => "image",
+        }
+    }
+
+    /// The inline runs of a paragraph or heading; other elements have none.
+    pub fn runs(&self) -> &[TextRun] {
+        match self {
+            DocumentElement::Paragraph { runs, .. } | DocumentElement::Heading { runs, .. } => runs,
+            _ => &[],
+        }
+    }
+
+    pub fn runs_mut(&mut self) -> Option<&mut Vec<TextRun>> {
+        match self {
+            DocumentElement::Paragraph { runs, .. } | DocumentElement::Heading { runs, .. } => Some(runs),
+            _ => None,
+        }
+    }
+}
+
+/// Serializes binary payloads as base64 strings so JSON output stays compact.
+mod base64_bytes {
+    use base64::engine::general_purpose::STANDARD;
+    use base64::Engine;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&STANDARD.encode(bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let encoded = String::deserialize(deserializer)?;
+        STANDARD.decode(encoded).map_err(serde::de::Error::custom)
+    }
+}
+
+/// Stylesheet embedded in standalone HTML pages.
+const HTML_DOCUMENT_CSS: &str = "body { font-family: sans-serif; line-height: 1.5; max-width: 48em; margin: 2em auto; padding: 0 1em; }
+table { border-collapse: collapse; }
+td { border: 1px solid #ccc; padding: 0.25em 0.5em; vertical-align: top; }
+img { max-width: 100%; }
+";
+
+/// Wraps an HTML fragment in a complete page. With `stylesheet` it carries
+/// `HTML_DOCUMENT_CSS`, with the body set in `default_style`'s font when
+/// there is one.
+fn html_page(
+    title: Option<&str>,
+    language: Option<&str>,
+    default_style: Option<&TextStyle>,
+    stylesheet: bool,
+    body: &str,
+) -> String {
+    let mut css = HTML_DOCUMENT_CSS.to_string();
+    if let Some(style) = default_style.filter(|_| stylesheet) {
+        // Entities aren't decoded inside <style>, so characters that could end
+        // the declaration or the element are dropped instead
+        let clean = |value: &str| value.replace(['"', '\\', ';', '{', '}', '<', '>'], "");
+        let mut declarations = String::new();
+        if let Some(family) = &style.font_family {
+            declarations.push_str(&format!(" font-family: \"{}\";", clean(family)));
+        }
+        if let Some(size) = &style.font_size {
+            let size = clean(size);
+            let unit = if size.parse::<f32>().is_ok() { "pt" } else { "" };
+            declarations.push_str(&format!(" font-size: {}{};", size, unit));
+        }
+        if !declarations.is_empty() {
+            css.push_str(&format!("body {{{} }}\n", declarations));
+        }
+    }
+    let lang = match language {
+        Some(language) => format!(" lang=\"{}\"", xml_escape(language)),
+        None => String::new(),
+    };
+    let style = if stylesheet { format!("<style>\n{}</style>\n", css) } else { String::new() };
+    format!(
+        "<!DOCTYPE html>\n<html{}>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n{}</head>\n<body>\n{}</body>\n</html>\n",
+        lang,
+        xml_escape(title.unwrap_or("")),
+        style,
+        body
+    )
+}
+
+/// Images larger than this are referenced by name instead of inlined in HTML.
+pub const DEFAULT_MAX_EMBEDDED_IMAGE_BYTES: usize = 1024 * 1024;
+
+/// A run (or separator, with no style) located in the plain text.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RunSpan {
+    pub element_index: usize,
+    pub start: usize,
+    pub end: usize,
+    pub text: String,
+    pub style: Option<TextStyle>,
+}
+
+/// Where a plain-text character offset falls in the element tree. `run_index`
+/// counts the element's runs in text order (across list items and cells); it
+/// is `None` for separators such as line breaks, and `offset_in_run` is then
+/// relative to the separator.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TextLocation {
+    pub element_index: usize,
+    pub run_index: Option<usize>,
+    pub offset_in_run: usize,
+}
+
+struct PlainTextSpan<'a> {
+    element_index: usize,
+    run: Option<&'a TextRun>,
+    text: Cow<'a, str>,
+}
+
+/// Formatting knobs for plain-text output.
+#[derive(Debug, Clone)]
+pub struct PlainTextOptions {
+    /// Inserted between consecutive runs of the same block.
+    pub run_separator: String,
+    /// Prefix for unordered list items; `None` leaves them unprefixed.
+    /// Ordered items are always numbered, `1.2. ` style where the list
+    /// shows several levels.
+    pub bullet: Option<String>,
+    pub include_headings: bool,
+    /// Written for a manual line break inside a block, e.g. `"\u{2028}"` to
+    /// tell it apart from a paragraph break. `None` writes `\n` for both.
+    pub soft_break: Option<String>,
+}
+
+impl Default for PlainTextOptions {
+    fn default() -> Self {
+        Self {
+            run_separator: String::new(),
+            bullet: None,
+            include_headings: true,
+            soft_break: None,
+        }
+    }
+}
+
+/// Numbering style of ordered lists in rendered output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ListNumbering {
+    #[default]
+    Decimal,
+    LowerAlpha,
+    LowerRoman,
+}
+
+impl ListNumbering {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "decimal" => Some(Self::Decimal),
+            "lower-alpha" => Some(Self::LowerAlpha),
+            "lower-roman" => Some(Self::LowerRoman),
+            _ => None,
+        }
+    }
+
+    fn css_name(self) -> &'static str {
+        match self {
+            Self::Decimal => "decimal",
+            Self::LowerAlpha => "lower-alpha",
+            Self::LowerRoman => "lower-roman",
+        }
+    }
+}
+
+/// Formatting knobs for HTML and Markdown output.
+#[derive(Debug, Clone)]
+pub struct RenderOptions {
+    /// Produce a complete HTML page rather than a fragment.
+    pub standalone: bool,
+    /// Page title of a standalone page.
+    pub title: Option<String>,
+    /// Carry the document's own formatting (shading, indents, alignment,
+    /// highlights) as inline CSS, and give standalone pages a stylesheet.
+    /// List markers chosen here are kept either way.
+    pub include_styles: bool,
+    /// Give headings `id`s for linking to, as listed by `headings`.
+    pub heading_anchors: bool,
+    /// Images larger than this are referenced by name instead of inlined.
+    pub max_image_bytes: usize,
+    /// Glyph for unordered list items; `None` keeps the browser's disc in
+    /// HTML and `-` in Markdown.
+    pub unordered_bullet: Option<String>,
+    /// Number of the first item of each ordered list.
+    pub ordered_start: usize,
+    /// Markdown only has decimal numbering, so this applies to HTML alone.
+    pub ordered_format: ListNumbering,
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        Self {
+            standalone: false,
+            title: None,
+            include_styles: true,
+            heading_anchors: true,
+            max_image_bytes: DEFAULT_MAX_EMBEDDED_IMAGE_BYTES,
+            unordered_bullet: None,
+            ordered_start: 1,
+            ordered_format: ListNumbering::Decimal,
+        }
+    }
+}
+
+/// Page size and margins for `paginate`, in points.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PageSetup {
+    pub width: f64,
+    pub height: f64,
+    pub margin_top: f64,
+    pub margin_bottom: f64,
+    pub margin_left: f64,
+    pub margin_right: f64,
+}
+
+impl Default for PageSetup {
+    /// A4 with one-inch margins.
+    fn default() -> Self {
+        Self {
+            width: 595.0,
+            height: 842.0,
+            margin_top: 72.0,
+            margin_bottom: 72.0,
+            margin_left: 72.0,
+            margin_right: 72.0,
+        }
+    }
 }
 
+/// One page of a paginated document; `start` is the index of its first element.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Page {
+    pub start: usize,
+    pub elements: Vec<DocumentElement>,
+}
+
+/// Character counts per formatting flag. A character that is both bold and
+/// italic counts toward both buckets.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct StyleStatistics {
+    pub total: usize,
+    pub bold: usize,
+    pub italic: usize,
+    pub underline: usize,
+    pub colored: usize,
+    pub hidden: usize,
+}
+
+/// One step of an element-level diff between two documents.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum DiffOp {
+    Equal { old_index: usize, new_index: usize },
+    Delete { old_index: usize },
+    Insert { new_index: usize },
+    Replace { old_index: usize, new_index: usize },
+    Move { old_index: usize, new_index: usize },
+}
+
+/// A review comment; `anchor` is the commented text when the source marks a
+/// range, and `element_index` the element it is attached to, if known.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Comment {
+    pub author: String,
+    pub text: String,
+    #[serde(default)]
+    pub anchor: Option<String>,
+    #[serde(default)]
+    pub element_index: Option<usize>,
+    /// Replies to this comment, oldest first.
+    #[serde(default)]
+    pub replies: Vec<Comment>,
+    /// Whether the thread is marked done; `None` when the source doesn't say.
+    #[serde(default)]
+    pub resolved: Option<bool>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct StructuredDocument {
     pub elements: Vec<DocumentElement>,
     pub styles: HashMap<String, TextStyle>,
+    #[serde(default)]
+    pub comments: Vec<Comment>,
+    #[serde(default)]
+    pub footnotes: Vec<String>,
+    /// Base formatting runs start from, e.g. the document font.
+    #[serde(default)]
+    pub default_style: Option<TextStyle>,
+    /// Primary language as a BCP 47 tag such as `fr-FR`.
+    #[serde(default)]
+    pub language: Option<String>,
+    /// Document properties such as `title` or `author`, in source order.
+    #[serde(default, skip_serializing_if = "IndexMap::is_empty")]
+    pub metadata: IndexMap<String, String>,
 }
 
 impl StructuredDocument {
     pub fn new() -> Self {
-        Self {
-            elements: Vec::new(),
-            styles: HashMap::new(),
+        Self::default()
+    }
+
+    /// Promotes plain text to a document with one paragraph per line.
+    pub fn from_plain_text(text: &str) -> Self {
+        let mut doc = Self::new();
+        for line in text.split_terminator('\n') {
+            let line = line.strip_suffix('\r').unwrap_or(line);
+            let runs = if line.is_empty() { Vec::new() } else { vec![TextRun::plain(line)] };
+            doc.elements.push(DocumentElement::Paragraph { runs, background: None, indent: None, alignment: None, direction: None });
+        }
+        doc
+    }
+
+    /// Imports a Markdown file. A leading YAML front-matter block of
+    /// `key: value` lines goes to `metadata`; the body after it is kept as
+    /// plain text lines.
+    pub fn from_markdown(text: &str) -> Self {
+        match split_front_matter(text) {
+            Some((metadata, body)) => {
+                let mut doc = Self::from_plain_text(body);
+                doc.metadata = metadata;
+                doc
+            }
+            None => Self::from_plain_text(text),
+        }
+    }
+
+    /// A document holding a single paragraph of `runs`.
+    pub fn paragraph(runs: Vec<TextRun>) -> Self {
+        DocumentBuilder::new().paragraph_runs(runs).build()
+    }
+
+    pub fn builder() -> DocumentBuilder {
+        DocumentBuilder::new()
+    }
+
+    /// Builds paragraphs from blank-line-separated blocks, keeping the line
+    /// breaks inside a block. A line starting with one of `heading_prefixes`
+    /// becomes a heading on its own; the longest matching prefix wins and the
+    /// shortest prefix gives level 1, the next longer level 2, and so on.
+    pub fn from_plain_text_blocks(text: &str, heading_prefixes: &[String]) -> Self {
+        let mut lengths: Vec<usize> = heading_prefixes
+            .iter()
+            .filter(|prefix| !prefix.is_empty())
+            .map(|prefix| prefix.chars().count())
+            .collect();
+        lengths.sort_unstable();
+        lengths.dedup();
+        let mut doc = Self::new();
+        let mut block: Vec<&str> = Vec::new();
+        let flush = |doc: &mut Self, block: &mut Vec<&str>| {
+            if !block.is_empty() {
+                doc.elements.push(DocumentElement::Paragraph {
+                    runs: vec![TextRun::plain(block.join("\n"))],
+                    background: None,
+                    indent: None,
+                    alignment: None,
+                    direction: None,
+                });
+                block.clear();
+            }
+        };
+        for line in text.split_terminator('\n') {
+            let line = line.strip_suffix('\r').unwrap_or(line);
+            let prefix = heading_prefixes
+                .iter()
+                .filter(|prefix| !prefix.is_empty() && line.starts_with(prefix.as_str()))
+                .max_by_key(|prefix| prefix.len());
+            if let Some(prefix) = prefix {
+                flush(&mut doc, &mut block);
+                let rank = lengths.iter().position(|len| *len == prefix.chars().count()).unwrap_or(0);
+                let heading = line[prefix.len()..].trim();
+                doc.elements.push(DocumentElement::Heading {
+                    level: (rank + 1).min(6) as u8,
+                    runs: if heading.is_empty() { Vec::new() } else { vec![TextRun::plain(heading)] },
+                    background: None,
+                    direction: None,
+                });
+            } else if line.trim().is_empty() {
+                flush(&mut doc, &mut block);
+            } else {
+                block.push(line);
+            }
+        }
+        flush(&mut doc, &mut block);
+        doc
+    }
+
+    /// Appends `other`'s elements and styles. Style names that already exist
+    /// with a different definition are renamed with a numeric suffix.
+    pub fn merge(&mut self, other: StructuredDocument) {
+        self.elements.extend(other.elements);
+        self.comments.extend(other.comments);
+        self.footnotes.extend(other.footnotes);
+        for (name, style) in other.styles {
+            match self.styles.get(&name) {
+                None => {
+                    self.styles.insert(name, style);
+                }
+                Some(existing) if *existing == style => {}
+                Some(_) => {
+                    let mut suffix = 2;
+                    while self.styles.contains_key(&format!("{}_{}", name, suffix)) {
+                        suffix += 1;
+                    }
+                    self.styles.insert(format!("{}_{}", name, suffix), style);
+                }
+            }
         }
     }
 
+    /// Builds a structured document from an HTML fragment or page.
+    /// Malformed markup is tolerated: unclosed tags are closed implicitly and
+    /// markup that can't be parsed is skipped.
+    pub fn from_html(html: &str) -> Self {
+        let mut reader = Reader::from_str(html);
+        reader.check_end_names(false);
+        let mut importer = HtmlImporter::new();
+        // Where in `html` the current reader started
+        let mut offset = 0;
+
+        loop {
+            match reader.read_event() {
+                Ok(Event::Start(e)) => {
+                    let name = String::from_utf8_lossy(local_name(e.name().as_ref())).to_ascii_lowercase();
+                    importer.start_tag(&name, &e);
+                }
+                Ok(Event::Empty(e)) => {
+                    let name = String::from_utf8_lossy(local_name(e.name().as_ref())).to_ascii_lowercase();
+                    if name == "br" {
+                        importer.line_break();
+                    }
+                }
+                Ok(Event::End(e)) => {
+                    let name = String::from_utf8_lossy(local_name(e.name().as_ref())).to_ascii_lowercase();
+                    importer.end_tag(&name);
+                }
+                Ok(Event::Text(t)) => match t.unescape_with(html_entity) {
+                    Ok(cow) => importer.push_text(&cow),
+                    Err(_) => importer.push_text(&String::from_utf8_lossy(t.as_ref())),
+                },
+                Ok(Event::CData(t)) => {
+                    importer.push_text(&String::from_utf8_lossy(t.as_ref()));
+                }
+                Ok(Event::Eof) => break,
+                // The reader stops for good at an error, so a new one carries
+                // on after the end of the markup it couldn't parse
+                Err(_) => {
+                    let position = offset + reader.buffer_position();
+                    match html.get(position..).and_then(|rest| rest.find('>')) {
+                        Some(end) => {
+                            offset = position + end + 1;
+                            reader = Reader::from_str(&html[offset..]);
+                            reader.check_end_names(false);
+                        }
+                        None => break,
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        importer.finish()
+    }
+
     pub fn to_html(&self) -> String {
+        self.render_html(&RenderOptions::default())
+    }
+
+    /// Wraps `to_html` in a standalone UTF-8 page with a little default CSS.
+    pub fn to_html_document(&self, title: Option<&str>) -> String {
+        self.render_html(&RenderOptions {
+            standalone: true,
+            title: title.map(str::to_string),
+            ..RenderOptions::default()
+        })
+    }
+
+    /// Renders HTML, inlining images up to `options.max_image_bytes` as data URIs.
+    pub fn render_html(&self, options: &RenderOptions) -> String {
+        let body = self.html_body(options);
+        if options.standalone {
+            html_page(
+                options.title.as_deref(),
+                self.language.as_deref(),
+                self.default_style.as_ref(),
+                options.include_styles,
+                &body,
+            )
+        } else {
+            body
+        }
+    }
+
+    fn html_body(&self, options: &RenderOptions) -> String {
+        let styled = |style: String| if options.include_styles { style } else { String::new() };
         let mut html = String::new();
+        let mut anchors = self.heading_anchors().into_iter();
         for element in &self.elements {
             match element {
-                DocumentElement::Paragraph { runs } => {
-                    html.push_str("<p>");
+                DocumentElement::Paragraph { runs, background, indent, alignment, direction } => {
+                    html.push_str(&format!(
+                        "<p{}{}>",
+                        direction_attribute(direction),
+                        styled(block_style(background, *indent, alignment.as_deref()))
+                    ));
                     for run in runs {
-                        html.push_str(&self.run_to_html(run));
+                        html.push_str(&self.run_to_html(run, options.include_styles));
                     }
                     html.push_str("</p>\n");
                 }
-                DocumentElement::Heading { level, runs } => {
-                    html.push_str(&format!("<h{}>", level));
+                DocumentElement::Heading { level, runs, background, direction } => {
+                    let anchor = anchors.next().unwrap_or_default();
+                    let id = if options.heading_anchors { format!(" id=\"{}\"", anchor) } else { String::new() };
+                    html.push_str(&format!(
+                        "<h{}{}{}{}>",
+                        level,
+                        id,
+                        direction_attribute(direction),
+                        styled(block_style(background, None, None))
+                    ));
                     for run in runs {
-                        html.push_str(&self.run_to_html(run));
+                        html.push_str(&self.run_to_html(run, options.include_styles));
                     }
                     html.push_str(&format!("</h{}>\n", level));
                 }
-                DocumentElement::List { items, ordered } => {
-                    let tag = if *ordered { "ol" } else { "ul" };
-                    html.push_str(&format!("<{}>", tag));
-                    for item in items {
+                DocumentElement::List { items, ordered, levels, .. } => {
+                    // Nested lists share the outer list's marker style; only
+                    // the outermost one takes the start number
+                    let mut nested = String::new();
+                    if *ordered {
+                        nested.push_str("<ol");
+                        if options.ordered_format != ListNumbering::Decimal {
+                            nested.push_str(&format!(" style=\"list-style-type:{}\"", options.ordered_format.css_name()));
+                        }
+                        nested.push('>');
+                    } else {
+                        match &options.unordered_bullet {
+                            // A string value is used as the marker verbatim
+                            Some(bullet) => nested.push_str(&format!(
+                                "<ul style=\"list-style-type:'{} '\">",
+                                xml_escape(&bullet.replace(['\'', '\\'], ""))
+                            )),
+                            None => nested.push_str("<ul>"),
+                        }
+                    }
+                    if *ordered && options.ordered_start != 1 {
+                        html.push_str(&nested.replacen("<ol", &format!("<ol start=\"{}\"", options.ordered_start), 1));
+                    } else {
+                        html.push_str(&nested);
+                    }
+                    let close = if *ordered { "</ol>" } else { "</ul>" };
+                    let mut depth = 0;
+                    for (item_index, item) in items.iter().enumerate() {
+                        // A deeper item opens a list inside the open <li>, one
+                        // level at a time; a shallower one closes back out
+                        let level = levels.get(item_index).copied().unwrap_or(0).min(MAX_LIST_LEVEL) as usize;
+                        if item_index > 0 && level > depth {
+                            depth += 1;
+                            html.push_str(&nested);
+                        } else if item_index > 0 {
+                            html.push_str("</li>");
+                            while depth > level {
+                                depth -= 1;
+                                html.push_str(close);
+                                html.push_str("</li>");
+                            }
+                        }
                         html.push_str("<li>");
-                        for run in item {
-                            html.push_str(&self.run_to_html(run));
+                        // A single paragraph stays inline; several each get their own <p>
+                        let paragraphs: Vec<&Vec<TextRun>> = item_paragraphs(item).collect();
+                        for runs in &paragraphs {
+                            if paragraphs.len() > 1 {
+                                html.push_str("<p>");
+                            }
+                            for run in runs.iter() {
+                                html.push_str(&self.run_to_html(run, options.include_styles));
+                            }
+                            if paragraphs.len() > 1 {
+                                html.push_str("</p>");
+                            }
                         }
+                    }
+                    if !items.is_empty() {
+                        html.push_str("</li>");
+                    }
+                    for _ in 0..depth {
+                        html.push_str(close);
                         html.push_str("</li>");
                     }
-                    html.push_str(&format!("</{}>", tag));
+                    html.push_str(close);
                 }
                 DocumentElement::Table { rows } => {
                     html.push_str("<table>");
                     for row in rows {
                         html.push_str("<tr>");
                         for cell in row {
-                            html.push_str("<td>");
-                            for run in cell {
-                                html.push_str(&self.run_to_html(run));
+                            html.push_str("<td");
+                            if cell.colspan > 1 {
+                                html.push_str(&format!(" colspan=\"{}\"", cell.colspan));
+                            }
+                            if cell.rowspan > 1 {
+                                html.push_str(&format!(" rowspan=\"{}\"", cell.rowspan));
+                            }
+                            html.push('>');
+                            for run in &cell.runs {
+                                html.push_str(&self.run_to_html(run, options.include_styles));
                             }
                             html.push_str("</td>");
                         }
@@ -112,13 +777,38 @@ impl StructuredDocument {
                 DocumentElement::LineBreak => {
                     html.push_str("<br/>\n");
                 }
+                DocumentElement::Image { name, mime_type, data, alt } => {
+                    let alt = xml_escape(alt.as_deref().unwrap_or(""));
+                    // Only a plain image/* type goes into a data URI; anything
+                    // else could carry markup out of the attribute
+                    let inline_type = mime_type.strip_prefix("image/").is_some_and(|subtype| {
+                        !subtype.is_empty()
+                            && subtype.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'))
+                    });
+                    if inline_type && data.len() <= options.max_image_bytes {
+                        html.push_str(&format!(
+                            "<img src=\"data:{};base64,{}\" alt=\"{}\"/>\n",
+                            mime_type,
+                            base64::engine::general_purpose::STANDARD.encode(data),
+                            alt
+                        ));
+                    } else {
+                        // Too large or unknown to inline: point at the archive part instead
+                        html.push_str(&format!(
+                            "<img src=\"{}\" alt=\"{}\" data-size=\"{}\"/>\n",
+                            xml_escape(name),
+                            alt,
+                            data.len()
+                        ));
+                    }
+                }
             }
         }
         html
     }
 
-    fn run_to_html(&self, run: &TextRun) -> String {
-        let mut result = xml_escape(&run.text);
+    fn run_to_html(&self, run: &TextRun, include_styles: bool) -> String {
+        let mut result = xml_escape(&run.text).replace('\n', "<br/>");
         
         if run.style.bold {
             result = format!("<b>{}</b>", result);
@@ -129,160 +819,4393 @@ impl StructuredDocument {
         if run.style.underline {
             result = format!("<u>{}</u>", result);
         }
+        if let Some(highlight) = run.style.highlight.as_ref().filter(|_| include_styles) {
+            result = format!("<span style=\"background-color:{}\">{}</span>", xml_escape(highlight), result);
+        }
+        if let Some(lang) = &run.lang {
+            let dir = if is_rtl_language(lang) { " dir=\"rtl\"" } else { "" };
+            result = format!("<span lang=\"{}\"{}>{}</span>", xml_escape(lang), dir, result);
+        }
+        // Links with other schemes (javascript:, data:, ...) keep only their text
+        if let Some(link) = run.link.as_ref().filter(|link| is_safe_href(&link.href)) {
+            let title = match &link.title {
+                Some(title) => format!(" title=\"{}\"", xml_escape(title)),
+                None => String::new(),
+            };
+            result = format!("<a href=\"{}\"{}>{}</a>", xml_escape(&link.href), title, result);
+        }
+        let anchors: String = run.bookmarks.iter().map(|name| format!("<a id=\"{}\"></a>", xml_escape(name))).collect();
         
-        result
+        anchors + &result
+    }
+
+    pub fn to_markdown(&self) -> String {
+        self.to_markdown_opts(&RenderOptions::default())
+    }
+
+    /// Renders Markdown. Tables become pipe tables with the first row as
+    /// header; merged cells leave the positions they cover empty.
+    pub fn to_markdown_opts(&self, options: &RenderOptions) -> String {
+        let bullet = options.unordered_bullet.as_deref().unwrap_or("-");
+        let mut markdown = String::new();
+        for element in &self.elements {
+            match element {
+                DocumentElement::Paragraph { runs, .. } => {
+                    markdown.push_str(&runs_to_markdown(runs, "\\\n"));
+                    markdown.push_str("\n\n");
+                }
+                DocumentElement::Heading { level, runs, .. } => {
+                    markdown.push_str(&"#".repeat((*level).clamp(1, 6) as usize));
+                    markdown.push(' ');
+                    markdown.push_str(&runs_to_markdown(runs, " "));
+                    markdown.push_str("\n\n");
+                }
+                DocumentElement::List { items, ordered, levels, .. } => {
+                    let mut counters: Vec<usize> = Vec::new();
+                    for (item_index, item) in items.iter().enumerate() {
+                        let depth = levels.get(item_index).copied().unwrap_or(0).min(MAX_LIST_LEVEL) as usize;
+                        // Only the outermost level takes the configured start
+                        counters.resize(depth + 1, if depth == 0 { options.ordered_start } else { 1 });
+                        let marker = if *ordered {
+                            format!("{}.", counters[depth])
+                        } else {
+                            bullet.to_string()
+                        };
+                        counters[depth] += 1;
+                        let indent = "   ".repeat(depth);
+                        // Continuation paragraphs line up with the item text
+                        let continuation = format!("\n\n{}{}", indent, " ".repeat(marker.chars().count() + 1));
+                        let paragraphs: Vec<String> = item_paragraphs(item).map(|runs| runs_to_markdown(runs, "\\\n")).collect();
+                        markdown.push_str(&format!("{}{} {}\n", indent, marker, paragraphs.join(&continuation)));
+                    }
+                    markdown.push('\n');
+                }
+                DocumentElement::Table { rows } => {
+                    let grid = table_grid(rows);
+                    let columns = grid.iter().map(Vec::len).max().unwrap_or(0);
+                    for (row_index, row) in grid.iter().enumerate() {
+                        let cells: Vec<String> = (0..columns)
+                            .map(|column| match row.get(column) {
+                                Some(Some(cell)) => runs_to_markdown(&cell.runs, "<br>").replace('|', "\\|"),
+                                _ => String::new(),
+                            })
+                            .collect();
+                        markdown.push_str(&format!("| {} |\n", cells.join(" | ")));
+                        if row_index == 0 {
+                            markdown.push_str(&format!("|{}\n", " --- |".repeat(columns)));
+                        }
+                    }
+                    markdown.push('\n');
+                }
+                DocumentElement::LineBreak => {
+                    markdown.push_str("<br>\n\n");
+                }
+                DocumentElement::Image { name, alt, .. } => {
+                    markdown.push_str(&format!(
+                        "![{}]({})\n\n",
+                        markdown_escape(alt.as_deref().unwrap_or("")),
+                        name.replace(' ', "%20")
+                    ));
+                }
+            }
+        }
+        markdown
     }
 
     pub fn to_plain_text(&self) -> String {
+        self.to_plain_text_opts(&PlainTextOptions::default())
+    }
+
+    pub fn to_plain_text_opts(&self, options: &PlainTextOptions) -> String {
+        self.plain_text_spans(options).into_iter().map(|span| span.text).collect()
+    }
+
+    /// `to_plain_text` with sentinel lines for indexers: a form feed line
+    /// (`\x0c`) before each element in `page_starts` other than the first, and
+    /// headings written as `## Title` with one `#` per level, up to six.
+    /// Without headings or page starts this is exactly `to_plain_text`.
+    pub fn to_plain_text_with_markers(&self, page_starts: &[usize]) -> String {
         let mut text = String::new();
-        for element in &self.elements {
+        let mut element_index = None;
+        for span in self.plain_text_spans(&PlainTextOptions::default()) {
+            if element_index != Some(span.element_index) {
+                if element_index.is_some() && page_starts.contains(&span.element_index) {
+                    text.push_str("\x0c\n");
+                }
+                if let Some(DocumentElement::Heading { level, .. }) = self.elements.get(span.element_index) {
+                    text.push_str(&"#".repeat((*level).clamp(1, 6) as usize));
+                    text.push(' ');
+                }
+                element_index = Some(span.element_index);
+            }
+            text.push_str(&span.text);
+        }
+        text
+    }
+
+    /// Splits the `to_plain_text` output into run text and separators, in order.
+    /// Everything that maps plain-text positions back onto the element tree
+    /// goes through here so it can never drift from the text itself.
+    fn plain_text_spans<'a>(&'a self, options: &PlainTextOptions) -> Vec<PlainTextSpan<'a>> {
+        let mut spans = Vec::new();
+        for (element_index, element) in self.elements.iter().enumerate() {
+            let separator = |text: Cow<'a, str>| PlainTextSpan { element_index, run: None, text };
+            let push_runs = |runs: &'a [TextRun], spans: &mut Vec<PlainTextSpan<'a>>| {
+                for (run_index, run) in runs.iter().enumerate() {
+                    if run_index > 0 && !options.run_separator.is_empty() {
+                        spans.push(separator(Cow::Owned(options.run_separator.clone())));
+                    }
+                    let text = match &options.soft_break {
+                        Some(soft_break) if run.text.contains('\n') => Cow::Owned(run.text.replace('\n', soft_break)),
+                        _ => Cow::Borrowed(run.text.as_str()),
+                    };
+                    spans.push(PlainTextSpan { element_index, run: Some(run), text });
+                }
+            };
             match element {
-                DocumentElement::Paragraph { runs } | DocumentElement::Heading { runs, .. } => {
-                    for run in runs {
-                        text.push_str(&run.text);
+                DocumentElement::Paragraph { runs, .. } => {
+                    push_runs(runs, &mut spans);
+                    spans.push(separator(Cow::Borrowed("\n")));
+                }
+                DocumentElement::Heading { runs, .. } => {
+                    if options.include_headings {
+                        push_runs(runs, &mut spans);
+                        // The blank line after a heading keeps sections visually apart
+                        spans.push(separator(Cow::Borrowed("\n\n")));
                     }
-                    text.push('\n');
                 }
-                DocumentElement::List { items, .. } => {
-                    for item in items {
-                        for run in item {
-                            text.push_str(&run.text);
+                DocumentElement::List {
+                    items,
+                    ordered,
+                    levels,
+                    display_levels,
+                } => {
+                    // Numbering restarts whenever an item is shallower than the last
+                    let mut counters: Vec<usize> = Vec::new();
+                    for (item_index, item) in items.iter().enumerate() {
+                        let depth = levels.get(item_index).copied().unwrap_or(0).min(MAX_LIST_LEVEL) as usize;
+                        counters.resize(depth + 1, 0);
+                        counters[depth] += 1;
+                        let indent = "  ".repeat(depth);
+                        if *ordered {
+                            let shown = display_levels.get(depth).copied().unwrap_or(1).clamp(1, depth as u8 + 1);
+                            let number: Vec<String> =
+                                counters[depth + 1 - shown as usize..].iter().map(|n| n.to_string()).collect();
+                            spans.push(separator(Cow::Owned(format!("{}{}. ", indent, number.join(".")))));
+                        } else if let Some(bullet) = &options.bullet {
+                            spans.push(separator(Cow::Owned(format!("{}{}", indent, bullet))));
                         }
-                        text.push('\n');
+                        for (paragraph_index, runs) in item_paragraphs(item).enumerate() {
+                            if paragraph_index > 0 {
+                                spans.push(separator(Cow::Borrowed("\n")));
+                            }
+                            push_runs(runs, &mut spans);
+                        }
+                        spans.push(separator(Cow::Borrowed("\n")));
                     }
                 }
                 DocumentElement::Table { rows } => {
+                    // Cells are tab-separated by logical column, so positions
+                    // covered by a merged cell still get their (empty) column
+                    let mut covered: Vec<usize> = Vec::new();
                     for row in rows {
+                        let mut next_covered: Vec<usize> =
+                            covered.iter().map(|rows_left| rows_left.saturating_sub(1)).collect();
+                        let mut column = 0;
                         for cell in row {
-                            for run in cell {
-                                text.push_str(&run.text);
+                            while covered.get(column).is_some_and(|rows_left| *rows_left > 0) {
+                                if column > 0 {
+                                    spans.push(separator(Cow::Borrowed("\t")));
+                                }
+                                column += 1;
+                            }
+                            if column > 0 {
+                                spans.push(separator(Cow::Borrowed("\t")));
+                            }
+                            push_runs(&cell.runs, &mut spans);
+                            let colspan = cell.colspan.clamp(1, MAX_TABLE_SPAN);
+                            for _ in 1..colspan {
+                                spans.push(separator(Cow::Borrowed("\t")));
+                            }
+                            if next_covered.len() < column + colspan {
+                                next_covered.resize(column + colspan, 0);
+                            }
+                            for rows_left in &mut next_covered[column..column + colspan] {
+                                *rows_left = cell.rowspan.saturating_sub(1);
                             }
-                            text.push('\t');
+                            column += colspan;
                         }
-                        text.push('\n');
+                        covered = next_covered;
+                        spans.push(separator(Cow::Borrowed("\n")));
                     }
                 }
                 DocumentElement::LineBreak => {
-                    text.push('\n');
+                    spans.push(separator(Cow::Borrowed("\n")));
                 }
+                DocumentElement::Image { .. } => {}
             }
         }
-        text
+        spans
     }
-}
-
-fn ext_lower(path: &str) -> String {
-    Path::new(path)
-        .extension()
-        .and_then(|s| s.to_str())
-        .unwrap_or("")
-        .to_ascii_lowercase()
-}
 
-fn local_name(name: &[u8]) -> &[u8] {
-    if let Some(pos) = name.iter().position(|&b| b == b':') {
-        &name[pos + 1..]
-    } else {
-        name
+    /// Every run and separator of the plain text with its `[start, end)` char range.
+    pub fn runs_with_offsets(&self) -> Vec<RunSpan> {
+        let mut offset = 0;
+        self.plain_text_spans(&PlainTextOptions::default())
+            .into_iter()
+            .map(|span| {
+                let start = offset;
+                offset += span.text.chars().count();
+                RunSpan {
+                    element_index: span.element_index,
+                    start,
+                    end: offset,
+                    text: span.text.to_string(),
+                    style: span.run.map(|run| run.style.clone()),
+                }
+            })
+            .collect()
     }
-}
-
-fn read_zip_file_to_string<R: Read>(mut reader: R) -> std::io::Result<String> {
-    let mut s = String::new();
-    reader.read_to_string(&mut s)?;
-    Ok(s)
-}
 
-fn read_docx_text(path: &str) -> std::io::Result<String> {
-    let f = File::open(path)?;
-    let mut zip = ZipArchive::new(f)?;
-    let mut docxml = zip.by_name("word/document.xml")?;
-    let xml = read_zip_file_to_string(&mut docxml)?;
-
-    let mut reader = Reader::from_str(&xml);
-    reader.trim_text(true);
-    let mut buf = Vec::new();
-    let mut out = String::new();
+    /// The `[start, end)` char range of `to_plain_text` each element covers,
+    /// separators included. Elements without text, such as images, get an
+    /// empty range where they sit.
+    pub fn offset_map(&self) -> Vec<(usize, usize)> {
+        let mut ranges = Vec::with_capacity(self.elements.len());
+        let mut offset = 0;
+        let mut spans = self.plain_text_spans(&PlainTextOptions::default()).into_iter().peekable();
+        for element_index in 0..self.elements.len() {
+            let start = offset;
+            while let Some(span) = spans.next_if(|span| span.element_index == element_index) {
+                offset += span.text.chars().count();
+            }
+            ranges.push((start, offset));
+        }
+        ranges
+    }
 
-    loop {
-        match reader.read_event_into(&mut buf) {
-            Ok(Event::Start(_)) => {}
-            Ok(Event::Empty(e)) => {
-                if local_name(e.name().as_ref()) == b"p" {
-                    out.push('\n');
-                }
+    /// Locates a char offset of `to_plain_text`. A caret right after a run's
+    /// text stays in that run; the end of the text is a valid offset, anything
+    /// past it gives `None`.
+    pub fn locate(&self, char_offset: usize) -> Option<TextLocation> {
+        let mut start = 0;
+        let mut element_index = usize::MAX;
+        let mut run_index = 0;
+        // The location at the end of the previous span
+        let mut previous: Option<TextLocation> = None;
+        for span in self.plain_text_spans(&PlainTextOptions::default()) {
+            if span.element_index != element_index {
+                element_index = span.element_index;
+                run_index = 0;
             }
-            Ok(Event::End(e)) => {
-                if local_name(e.name().as_ref()) == b"p" {
-                    out.push('\n');
+            let length = span.text.chars().count();
+            let end = start + length;
+            let current_run = span.run.map(|_| run_index);
+            if (start..end).contains(&char_offset) {
+                return match previous {
+                    Some(previous) if char_offset == start && current_run.is_none() && previous.run_index.is_some() => {
+                        Some(previous)
+                    }
+                    _ => Some(TextLocation {
+                        element_index,
+                        run_index: current_run,
+                        offset_in_run: char_offset - start,
+                    }),
+                };
+            }
+            previous = Some(TextLocation {
+                element_index,
+                run_index: current_run,
+                offset_in_run: length,
+            });
+            if span.run.is_some() {
+                run_index += 1;
+            }
+            start = end;
+        }
+        previous.filter(|_| char_offset == start)
+    }
+
+    fn run_lists(&self) -> Vec<&Vec<TextRun>> {
+        let mut lists = Vec::new();
+        for element in &self.elements {
+            match element {
+                DocumentElement::Paragraph { runs, .. } | DocumentElement::Heading { runs, .. } => {
+                    lists.push(runs);
+                }
+                DocumentElement::List { items, .. } => {
+                    for item in items {
+                        lists.extend(item_paragraphs(item));
+                    }
+                }
+                DocumentElement::Table { rows } => {
+                    for row in rows {
+                        lists.extend(row.iter().map(|cell| &cell.runs));
+                    }
+                }
+                DocumentElement::LineBreak | DocumentElement::Image { .. } => {}
+            }
+        }
+        lists
+    }
+
+    fn run_lists_mut(&mut self) -> Vec<&mut Vec<TextRun>> {
+        let mut lists = Vec::new();
+        for element in &mut self.elements {
+            match element {
+                DocumentElement::Paragraph { runs, .. } | DocumentElement::Heading { runs, .. } => {
+                    lists.push(runs);
+                }
+                DocumentElement::List { items, .. } => {
+                    for item in items {
+                        lists.extend(item_paragraphs_mut(item));
+                    }
+                }
+                DocumentElement::Table { rows } => {
+                    for row in rows {
+                        lists.extend(row.iter_mut().map(|cell| &mut cell.runs));
+                    }
+                }
+                DocumentElement::LineBreak | DocumentElement::Image { .. } => {}
+            }
+        }
+        lists
+    }
+
+    /// Gives link runs the look of the hyperlink character style (Word's
+    /// "Hyperlink", LibreOffice's "Internet Link"), which the readers don't
+    /// resolve: underlined in the link color, unless the run sets its own
+    /// underline or color.
+    pub fn style_links(&mut self) {
+        for runs in self.run_lists_mut() {
+            for run in runs.iter_mut().filter(|run| run.link.is_some()) {
+                if !run.style.underline && run.style.color.is_none() {
+                    run.style.underline = true;
+                    run.style.color = Some(LINK_COLOR.to_string());
+                }
+            }
+        }
+    }
+
+    /// Merges consecutive runs with identical styles and drops empty runs.
+    /// A run starting a bookmark is never merged into the one before it.
+    pub fn coalesce_runs(&mut self) {
+        for runs in self.run_lists_mut() {
+            let mut merged: Vec<TextRun> = Vec::with_capacity(runs.len());
+            for run in runs.drain(..) {
+                if run.text.is_empty() && run.bookmarks.is_empty() {
+                    continue;
+                }
+                match merged.last_mut() {
+                    Some(last)
+                        if run.bookmarks.is_empty()
+                            && last.style == run.style
+                            && last.link == run.link
+                            && last.lang == run.lang =>
+                    {
+                        last.text.push_str(&run.text)
+                    }
+                    _ => merged.push(run),
+                }
+            }
+            *runs = merged;
+        }
+    }
+
+    /// Moves bookmarks held by empty marker runs onto the next run with
+    /// text, or the last one when none follows, and drops the markers.
+    fn settle_bookmarks(&mut self) {
+        for runs in self.run_lists_mut() {
+            if !runs.iter().any(|run| run.text.is_empty() && !run.bookmarks.is_empty()) {
+                continue;
+            }
+            let mut settled: Vec<TextRun> = Vec::with_capacity(runs.len());
+            let mut pending = Vec::new();
+            for mut run in runs.drain(..) {
+                pending.append(&mut run.bookmarks);
+                if !run.text.is_empty() {
+                    run.bookmarks = std::mem::take(&mut pending);
+                    settled.push(run);
+                }
+            }
+            match settled.last_mut() {
+                Some(last) => last.bookmarks.append(&mut pending),
+                None => settled.push(TextRun { bookmarks: pending, ..TextRun::plain("") }),
+            }
+            *runs = settled;
+        }
+    }
+
+    /// Element keys used for diffing: runs are coalesced first so that
+    /// differences in run boundaries alone don't count as changes.
+    fn diff_keys(&self) -> Vec<String> {
+        let mut normalized = self.clone();
+        normalized.coalesce_runs();
+        normalized
+            .elements
+            .iter()
+            .map(|element| serde_json::to_string(element).unwrap_or_default())
+            .collect()
+    }
+
+    /// Element-level diff from `self` to `other` based on the longest common
+    /// subsequence, found with Myers' algorithm. Past `MAX_DIFF_EDITS` edits
+    /// the differing middle is reported as deleted and reinserted instead.
+    /// Elements deleted in one place and inserted unchanged in
+    /// another are reported as moves, and neighbouring delete/insert pairs as
+    /// replacements.
+    pub fn diff(&self, other: &StructuredDocument) -> Vec<DiffOp> {
+        let old_keys = self.diff_keys();
+        let new_keys = other.diff_keys();
+        // Elements are compared by small ids rather than by their JSON
+        let mut ids: HashMap<&str, usize> = HashMap::new();
+        for key in old_keys.iter().chain(&new_keys) {
+            let next = ids.len();
+            ids.entry(key.as_str()).or_insert(next);
+        }
+        let old_ids: Vec<usize> = old_keys.iter().map(|key| ids[key.as_str()]).collect();
+        let new_ids: Vec<usize> = new_keys.iter().map(|key| ids[key.as_str()]).collect();
+
+        let ops = myers_diff(&old_ids, &new_ids, MAX_DIFF_EDITS).unwrap_or_else(|| {
+            let prefix = old_ids.iter().zip(&new_ids).take_while(|(a, b)| a == b).count();
+            let suffix = old_ids[prefix..]
+                .iter()
+                .rev()
+                .zip(new_ids[prefix..].iter().rev())
+                .take_while(|(a, b)| a == b)
+                .count();
+            let (n, m) = (old_ids.len(), new_ids.len());
+            let mut ops: Vec<DiffOp> = (0..prefix).map(|i| DiffOp::Equal { old_index: i, new_index: i }).collect();
+            ops.extend((prefix..n - suffix).map(|old_index| DiffOp::Delete { old_index }));
+            ops.extend((prefix..m - suffix).map(|new_index| DiffOp::Insert { new_index }));
+            ops.extend((0..suffix).map(|k| DiffOp::Equal { old_index: n - suffix + k, new_index: m - suffix + k }));
+            ops
+        });
+
+        // An insert whose content was deleted elsewhere is a move
+        let mut deleted: HashMap<usize, std::collections::VecDeque<usize>> = HashMap::new();
+        for op in &ops {
+            if let DiffOp::Delete { old_index } = op {
+                deleted.entry(old_ids[*old_index]).or_default().push_back(*old_index);
+            }
+        }
+        let mut moved_from: HashMap<usize, usize> = HashMap::new();
+        for op in &ops {
+            if let DiffOp::Insert { new_index } = op {
+                if let Some(old_index) = deleted.get_mut(&new_ids[*new_index]).and_then(|queue| queue.pop_front()) {
+                    moved_from.insert(*new_index, old_index);
+                }
+            }
+        }
+        let moved: std::collections::HashSet<usize> = moved_from.values().copied().collect();
+        let ops: Vec<DiffOp> = ops
+            .into_iter()
+            .filter_map(|op| match op {
+                DiffOp::Delete { old_index } if moved.contains(&old_index) => None,
+                DiffOp::Insert { new_index } => Some(match moved_from.get(&new_index) {
+                    Some(&old_index) => DiffOp::Move { old_index, new_index },
+                    None => op,
+                }),
+                _ => Some(op),
+            })
+            .collect();
+
+        // Pair up runs of deletes and inserts that sit next to each other
+        let mut result = Vec::with_capacity(ops.len());
+        let mut index = 0;
+        while index < ops.len() {
+            let mut deletes = Vec::new();
+            let mut inserts = Vec::new();
+            while let Some(op) = ops.get(index) {
+                match op {
+                    DiffOp::Delete { old_index } => deletes.push(*old_index),
+                    DiffOp::Insert { new_index } => inserts.push(*new_index),
+                    _ => break,
+                }
+                index += 1;
+            }
+            let paired = deletes.len().min(inserts.len());
+            for k in 0..paired {
+                result.push(DiffOp::Replace { old_index: deletes[k], new_index: inserts[k] });
+            }
+            result.extend(deletes[paired..].iter().map(|&old_index| DiffOp::Delete { old_index }));
+            result.extend(inserts[paired..].iter().map(|&new_index| DiffOp::Insert { new_index }));
+            if let Some(op) = ops.get(index) {
+                result.push(op.clone());
+                index += 1;
+            }
+        }
+        result
+    }
+
+    pub fn style_statistics(&self) -> StyleStatistics {
+        let mut stats = StyleStatistics::default();
+        for run in self.run_lists().into_iter().flatten() {
+            let chars = run.text.chars().count();
+            stats.total += chars;
+            if run.style.bold {
+                stats.bold += chars;
+            }
+            if run.style.italic {
+                stats.italic += chars;
+            }
+            if run.style.underline {
+                stats.underline += chars;
+            }
+            if run.style.color.is_some() {
+                stats.colored += chars;
+            }
+            if run.style.hidden {
+                stats.hidden += chars;
+            }
+        }
+        stats
+    }
+
+    /// Reports structural problems without changing anything.
+    pub fn validate(&self) -> Vec<String> {
+        let mut issues = Vec::new();
+        for (index, pair) in self.elements.windows(2).enumerate() {
+            if let [DocumentElement::Heading { .. }, DocumentElement::Heading { .. }] = pair {
+                issues.push(format!(
+                    "elements {} and {}: adjacent headings with no content between them",
+                    index,
+                    index + 1
+                ));
+            }
+        }
+        for (index, element) in self.elements.iter().enumerate() {
+            match element {
+                DocumentElement::Heading { level, .. } if !(1..=6).contains(level) => {
+                    issues.push(format!("element {}: heading level {} outside 1-6", index, level));
+                }
+                DocumentElement::Table { rows } => {
+                    let widths = table_row_widths(rows);
+                    if widths.iter().any(|width| *width != widths[0]) {
+                        issues.push(format!("element {}: table rows have different widths", index));
+                    }
+                }
+                DocumentElement::List { items, levels, .. } if !levels.is_empty() && levels.len() != items.len() => {
+                    issues.push(format!(
+                        "element {}: list has {} levels for {} items",
+                        index,
+                        levels.len(),
+                        items.len()
+                    ));
+                }
+                _ => {}
+            }
+        }
+        let empty_runs: usize = self
+            .run_lists()
+            .iter()
+            .map(|runs| runs.iter().filter(|run| run.text.is_empty() && run.bookmarks.is_empty()).count())
+            .sum();
+        if empty_runs > 0 {
+            issues.push(format!("{} empty runs", empty_runs));
+        }
+        let bad_colors = self.colors().into_iter().filter(|color| !is_valid_color(color)).count();
+        if bad_colors > 0 {
+            issues.push(format!("{} colors that are not #rrggbb", bad_colors));
+        }
+        for (index, comment) in self.comments.iter().enumerate() {
+            if let Some(element_index) = comment.element_index.filter(|i| *i >= self.elements.len()) {
+                issues.push(format!("comment {}: element {} does not exist", index, element_index));
+            }
+        }
+        issues
+    }
+
+    /// Fixes what `validate` reports and can be fixed safely: heading levels
+    /// are clamped to 1-6, ragged tables padded with empty cells, empty runs
+    /// dropped, list levels realigned with their items, colors the writers
+    /// can't express reset to the default and comments on missing elements
+    /// detached. Returns a description of each repair.
+    pub fn repair(&mut self) -> Vec<String> {
+        let mut repairs = Vec::new();
+        for (index, element) in self.elements.iter_mut().enumerate() {
+            match element {
+                DocumentElement::Heading { level, .. } if !(1..=6).contains(level) => {
+                    let clamped = (*level).clamp(1, 6);
+                    repairs.push(format!("element {}: heading level {} clamped to {}", index, level, clamped));
+                    *level = clamped;
+                }
+                DocumentElement::Table { rows } => {
+                    let widths = table_row_widths(rows);
+                    let columns = widths.iter().copied().max().unwrap_or(0);
+                    let mut padded = 0;
+                    for (row, width) in rows.iter_mut().zip(widths) {
+                        if width < columns {
+                            row.extend((width..columns).map(|_| TableCell::new(Vec::new())));
+                            padded += 1;
+                        }
+                    }
+                    if padded > 0 {
+                        repairs.push(format!("element {}: padded {} table rows to {} columns", index, padded, columns));
+                    }
+                }
+                DocumentElement::List { items, levels, .. } if !levels.is_empty() && levels.len() != items.len() => {
+                    repairs.push(format!(
+                        "element {}: list levels resized from {} to {}",
+                        index,
+                        levels.len(),
+                        items.len()
+                    ));
+                    levels.resize(items.len(), 0);
+                }
+                _ => {}
+            }
+        }
+        let mut empty_runs = 0;
+        for runs in self.run_lists_mut() {
+            let before = runs.len();
+            runs.retain(|run| !run.text.is_empty() || !run.bookmarks.is_empty());
+            empty_runs += before - runs.len();
+        }
+        if empty_runs > 0 {
+            repairs.push(format!("removed {} empty runs", empty_runs));
+        }
+        let mut bad_colors = 0;
+        for color in self.colors_mut() {
+            if !is_valid_color(color) {
+                *color = None;
+                bad_colors += 1;
+            }
+        }
+        if bad_colors > 0 {
+            repairs.push(format!("reset {} colors that are not #rrggbb to the default", bad_colors));
+        }
+        let element_count = self.elements.len();
+        for (index, comment) in self.comments.iter_mut().enumerate() {
+            if let Some(element_index) = comment.element_index.filter(|i| *i >= element_count) {
+                repairs.push(format!("comment {}: detached from missing element {}", index, element_index));
+                comment.element_index = None;
+            }
+        }
+        repairs
+    }
+
+    /// Every color the writers emit: run text and highlight colors, those of
+    /// the named and default styles, and block backgrounds.
+    fn colors(&self) -> Vec<&Option<String>> {
+        let mut colors = Vec::new();
+        for style in self.styles.values().chain(self.default_style.as_ref()) {
+            colors.push(&style.color);
+            colors.push(&style.highlight);
+        }
+        let blocks = self.elements.iter().flat_map(|element| match element {
+            DocumentElement::List { items, .. } => items.iter().flatten().collect(),
+            element => vec![element],
+        });
+        for block in blocks {
+            match block {
+                DocumentElement::Paragraph { runs, background, .. } | DocumentElement::Heading { runs, background, .. } => {
+                    colors.push(background);
+                    for run in runs {
+                        colors.push(&run.style.color);
+                        colors.push(&run.style.highlight);
+                    }
+                }
+                DocumentElement::Table { rows } => {
+                    for run in rows.iter().flatten().flat_map(|cell| cell.runs.iter()) {
+                        colors.push(&run.style.color);
+                        colors.push(&run.style.highlight);
+                    }
+                }
+                _ => {}
+            }
+        }
+        colors
+    }
+
+    /// `colors`, for changing them in place.
+    fn colors_mut(&mut self) -> Vec<&mut Option<String>> {
+        let mut colors = Vec::new();
+        let styles = self.styles.values_mut().chain(self.default_style.as_mut());
+        for style in styles {
+            colors.push(&mut style.color);
+            colors.push(&mut style.highlight);
+        }
+        let blocks = self.elements.iter_mut().flat_map(|element| match element {
+            DocumentElement::List { items, .. } => items.iter_mut().flatten().collect(),
+            element => vec![element],
+        });
+        for block in blocks {
+            match block {
+                DocumentElement::Paragraph { runs, background, .. } | DocumentElement::Heading { runs, background, .. } => {
+                    colors.push(background);
+                    for run in runs {
+                        colors.push(&mut run.style.color);
+                        colors.push(&mut run.style.highlight);
+                    }
+                }
+                DocumentElement::Table { rows } => {
+                    for run in rows.iter_mut().flatten().flat_map(|cell| cell.runs.iter_mut()) {
+                        colors.push(&mut run.style.color);
+                        colors.push(&mut run.style.highlight);
+                    }
+                }
+                _ => {}
+            }
+        }
+        colors
+    }
+
+    /// Folds a heading that directly follows a higher-level heading into it as
+    /// a subtitle, joined by `separator`; a chain of ever deeper headings
+    /// collapses into the first. The separator takes the style of the text
+    /// before it, and comments on a subtitle move to the heading it joins.
+    /// Returns the number of merges.
+    pub fn collapse_adjacent_headings(&mut self, separator: &str) -> usize {
+        let mut merged = 0;
+        let mut index = 0;
+        // Level of the last heading folded into the one at `index`
+        let mut chain_level = None;
+        while index + 1 < self.elements.len() {
+            let subtitle_level = match (&self.elements[index], &self.elements[index + 1]) {
+                (DocumentElement::Heading { level, .. }, DocumentElement::Heading { level: next_level, .. })
+                    if next_level > chain_level.as_ref().unwrap_or(level) =>
+                {
+                    Some(*next_level)
+                }
+                _ => None,
+            };
+            let Some(subtitle_level) = subtitle_level else {
+                index += 1;
+                chain_level = None;
+                continue;
+            };
+            for comment in &mut self.comments {
+                if comment.element_index == Some(index + 1) {
+                    comment.element_index = Some(index);
+                }
+            }
+            if let DocumentElement::Heading { runs: subtitle, .. } = self.remove_element(index + 1) {
+                if let DocumentElement::Heading { runs, .. } = &mut self.elements[index] {
+                    if !separator.is_empty() {
+                        let style = runs.last().map(|run| run.style.clone()).unwrap_or_default();
+                        runs.push(TextRun {
+                            text: separator.to_string(),
+                            style,
+                            link: None,
+                            lang: None,
+                            bookmarks: Vec::new(),
+                        });
+                    }
+                    runs.extend(subtitle);
+                }
+            }
+            chain_level = Some(subtitle_level);
+            merged += 1;
+        }
+        merged
+    }
+
+    /// Clamps heading levels to 1..=6 and closes gaps so that no heading is
+    /// more than one level deeper than the heading before it.
+    pub fn normalize_heading_levels(&mut self) {
+        let mut previous = 0u8;
+        for element in &mut self.elements {
+            if let DocumentElement::Heading { level, .. } = element {
+                *level = (*level).clamp(1, 6).min(previous + 1);
+                previous = *level;
+            }
+        }
+    }
+
+    /// Drops paragraphs, headings and list items without visible text and
+    /// collapses runs of consecutive line breaks into one.
+    pub fn clean(&mut self) {
+        let has_text = |runs: &[TextRun]| runs.iter().any(|run| !run.text.trim().is_empty());
+        for element in &mut self.elements {
+            if let DocumentElement::List { items, levels, .. } = element {
+                for item in items.iter_mut() {
+                    item.retain(|element| match element {
+                        DocumentElement::Paragraph { runs, .. } | DocumentElement::Heading { runs, .. } => has_text(runs),
+                        _ => true,
+                    });
+                }
+                if !levels.is_empty() {
+                    let mut keep = items.iter().map(|item| !item.is_empty());
+                    levels.retain(|_| keep.next().unwrap_or(false));
+                }
+                items.retain(|item| !item.is_empty());
+            }
+        }
+        let mut previous_was_break = false;
+        self.elements.retain(|element| {
+            let keep = match element {
+                DocumentElement::Paragraph { runs, .. } | DocumentElement::Heading { runs, .. } => has_text(runs),
+                DocumentElement::List { items, .. } => !items.is_empty(),
+                DocumentElement::LineBreak => !previous_was_break,
+                _ => true,
+            };
+            if keep {
+                previous_was_break = matches!(element, DocumentElement::LineBreak);
+            }
+            keep
+        });
+    }
+
+    /// Names accepted by `apply_transform`.
+    pub const TRANSFORMS: &'static [&'static str] = &[
+        "coalesce_runs",
+        "optimize",
+        "strip_hidden",
+        "normalize_headings",
+        "collapse_headings",
+        "clean",
+    ];
+
+    /// Runs one of the named normalization passes listed in `TRANSFORMS`.
+    pub fn apply_transform(&mut self, name: &str) -> Result<(), String> {
+        match name {
+            "coalesce_runs" => self.coalesce_runs(),
+            "optimize" => self.optimize(),
+            "strip_hidden" => self.strip_hidden_runs(),
+            "normalize_headings" => self.normalize_heading_levels(),
+            "collapse_headings" => {
+                self.collapse_adjacent_headings("\n");
+            }
+            "clean" => self.clean(),
+            _ => {
+                return Err(format!(
+                    "unknown transform '{}', expected one of: {}",
+                    name,
+                    Self::TRANSFORMS.join(", ")
+                ))
+            }
+        }
+        Ok(())
+    }
+
+    /// Anchor ids for every heading in document order: the slugified heading
+    /// text, with repeats suffixed `-2`, `-3`, ...
+    fn heading_anchors(&self) -> Vec<String> {
+        let mut used = std::collections::HashSet::new();
+        let mut anchors = Vec::new();
+        for element in &self.elements {
+            if let DocumentElement::Heading { runs, .. } = element {
+                let text: String = runs.iter().map(|run| run.text.as_str()).collect();
+                let slug = slugify(&text);
+                let mut anchor = slug.clone();
+                let mut n = 2;
+                while !used.insert(anchor.clone()) {
+                    anchor = format!("{}-{}", slug, n);
+                    n += 1;
+                }
+                anchors.push(anchor);
+            }
+        }
+        anchors
+    }
+
+    /// (level, text, anchor) for every heading, matching the ids emitted by `to_html`.
+    pub fn table_of_contents(&self) -> Vec<(u8, String, String)> {
+        let anchors = self.heading_anchors();
+        self.elements
+            .iter()
+            .filter_map(|element| match element {
+                DocumentElement::Heading { level, runs, .. } => {
+                    Some((*level, runs.iter().map(|run| run.text.as_str()).collect::<String>()))
+                }
+                _ => None,
+            })
+            .zip(anchors)
+            .map(|((level, text), anchor)| (level, text, anchor))
+            .collect()
+    }
+
+    /// The heading hierarchy as an OPML 2.0 document; each heading is nested
+    /// under the nearest preceding heading of a lower level.
+    pub fn to_opml(&self) -> String {
+        let mut opml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<opml version=\"2.0\">\n<head/>\n<body>\n");
+        // Levels of the outlines still open, and whether each has children yet
+        let mut open: Vec<(u8, bool)> = Vec::new();
+        let close = |opml: &mut String, open: &mut Vec<(u8, bool)>| {
+            if let Some((_, has_children)) = open.pop() {
+                if has_children {
+                    opml.push_str(&"  ".repeat(open.len() + 1));
+                    opml.push_str("</outline>\n");
+                } else {
+                    opml.push_str("/>\n");
+                }
+            }
+        };
+        for (level, text, _) in self.table_of_contents() {
+            while open.last().is_some_and(|(open_level, _)| *open_level >= level) {
+                close(&mut opml, &mut open);
+            }
+            if let Some((_, has_children)) = open.last_mut().filter(|(_, has_children)| !*has_children) {
+                *has_children = true;
+                opml.push_str(">\n");
+            }
+            opml.push_str(&"  ".repeat(open.len() + 1));
+            opml.push_str(&format!("<outline text=\"{}\"", xml_escape(&text.replace('\n', " "))));
+            open.push((level, false));
+        }
+        while !open.is_empty() {
+            close(&mut opml, &mut open);
+        }
+        opml.push_str("</body>\n</opml>\n");
+        opml
+    }
+
+    /// Bookmark names in document order.
+    pub fn bookmarks(&self) -> Vec<String> {
+        self.run_lists()
+            .into_iter()
+            .flatten()
+            .flat_map(|run| run.bookmarks.iter().cloned())
+            .collect()
+    }
+
+    /// Text of the paragraph, heading, list item paragraph or table cell a
+    /// bookmark starts in.
+    pub fn text_at_bookmark(&self, name: &str) -> Option<String> {
+        let runs = self
+            .run_lists()
+            .into_iter()
+            .find(|runs| runs.iter().any(|run| run.bookmarks.iter().any(|bookmark| bookmark == name)))?;
+        Some(runs.iter().map(|run| run.text.as_str()).collect())
+    }
+
+    /// The data rows of table `table_index` as header -> value records, keyed by
+    /// the first row's cell texts. Short rows are padded with empty values;
+    /// blank or missing headers become `column_N` and repeats get a `_2`, `_3`... suffix.
+    pub fn table_records(&self, table_index: usize) -> Option<Vec<IndexMap<String, String>>> {
+        let rows = self
+            .elements
+            .iter()
+            .filter_map(|element| match element {
+                DocumentElement::Table { rows } => Some(rows),
+                _ => None,
+            })
+            .nth(table_index)?;
+        let cell_text = |cell: Option<&TableCell>| -> String {
+            cell.map(|cell| cell.runs.iter().map(|run| run.text.as_str()).collect())
+                .unwrap_or_default()
+        };
+        let grid = table_grid(rows);
+        let columns = grid.iter().map(|slots| slots.len()).max().unwrap_or(0);
+        let mut headers: Vec<String> = Vec::new();
+        for column in 0..columns {
+            let text = cell_text(grid.first().and_then(|slots| slots.get(column).copied().flatten()));
+            let name = match text.trim() {
+                "" => format!("column_{}", column + 1),
+                name => name.to_string(),
+            };
+            let mut unique = name.clone();
+            let mut suffix = 2;
+            while headers.contains(&unique) {
+                unique = format!("{}_{}", name, suffix);
+                suffix += 1;
+            }
+            headers.push(unique);
+        }
+        Some(
+            grid.iter()
+                .skip(1)
+                .map(|slots| {
+                    headers
+                        .iter()
+                        .enumerate()
+                        .map(|(column, header)| (header.clone(), cell_text(slots.get(column).copied().flatten())))
+                        .collect()
+                })
+                .collect(),
+        )
+    }
+
+    /// The cell texts of each table, row by row, as the cells are stored.
+    pub fn table_texts(&self) -> Vec<Vec<Vec<String>>> {
+        self.elements
+            .iter()
+            .filter_map(|element| match element {
+                DocumentElement::Table { rows } => Some(rows),
+                _ => None,
+            })
+            .map(|rows| {
+                rows.iter()
+                    .map(|cells| cells.iter().map(|cell| cell.runs.iter().map(|run| run.text.as_str()).collect()).collect())
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// One CSV string per table, rows ending in CRLF. Every row is padded to
+    /// the table's full width; merged cells leave the positions they cover empty.
+    pub fn tables_to_csv(&self) -> Vec<String> {
+        self.elements
+            .iter()
+            .filter_map(|element| match element {
+                DocumentElement::Table { rows } => Some(rows),
+                _ => None,
+            })
+            .map(|rows| {
+                let grid = table_grid(rows);
+                let columns = grid.iter().map(|slots| slots.len()).max().unwrap_or(0);
+                let mut csv = String::new();
+                for slots in grid {
+                    let fields: Vec<String> = (0..columns)
+                        .map(|column| match slots.get(column).copied().flatten() {
+                            Some(cell) => csv_field(&cell.runs.iter().map(|run| run.text.as_str()).collect::<String>()),
+                            None => String::new(),
+                        })
+                        .collect();
+                    csv.push_str(&fields.join(","));
+                    csv.push_str("\r\n");
+                }
+                csv
+            })
+            .collect()
+    }
+
+    /// Flows elements onto pages by estimated height. Text is assumed to
+    /// average half a line height per character; elements are never split, so
+    /// one taller than a page gets a page to itself. An empty document has no pages.
+    pub fn paginate(&self, page_setup: &PageSetup, line_height: f64) -> Vec<Page> {
+        let line_height = line_height.max(1.0);
+        let text_width = (page_setup.width - page_setup.margin_left - page_setup.margin_right).max(line_height);
+        let page_height = (page_setup.height - page_setup.margin_top - page_setup.margin_bottom).max(line_height);
+        let lines_for = |runs: &[TextRun], width: f64| -> f64 {
+            let chars_per_line = (width / (line_height * 0.5)).max(1.0);
+            let text: String = runs.iter().map(|run| run.text.as_str()).collect();
+            text.split('\n')
+                .map(|line| (line.chars().count() as f64 / chars_per_line).ceil().max(1.0))
+                .sum()
+        };
+
+        if self.elements.is_empty() {
+            return Vec::new();
+        }
+        // The first page is open from the start, so a first element taller
+        // than a page still lands on it
+        let mut pages = vec![Page {
+            start: 0,
+            elements: Vec::new(),
+        }];
+        let mut used = 0.0;
+        for (index, element) in self.elements.iter().enumerate() {
+            let lines = match element {
+                DocumentElement::Paragraph { runs, .. } => lines_for(runs, text_width) + 0.5,
+                DocumentElement::Heading { level, runs, .. } => {
+                    let scale = match level {
+                        1 => 2.0,
+                        2 => 1.6,
+                        3 => 1.3,
+                        _ => 1.1,
+                    };
+                    lines_for(runs, text_width / scale) * scale + 1.0
+                }
+                DocumentElement::List { items, .. } => {
+                    items
+                        .iter()
+                        .flat_map(|item| item_paragraphs(item))
+                        .map(|runs| lines_for(runs, text_width))
+                        .sum::<f64>()
+                        + 0.5
+                }
+                DocumentElement::Table { rows } => {
+                    let columns = table_row_widths(rows).into_iter().max().unwrap_or(1).max(1);
+                    let cell_width = text_width / columns as f64;
+                    rows.iter()
+                        .map(|row| {
+                            row.iter()
+                                .map(|cell| lines_for(&cell.runs, cell_width * cell.colspan.max(1) as f64))
+                                .fold(1.0, f64::max)
+                        })
+                        .sum::<f64>()
+                        + 0.5
+                }
+                DocumentElement::LineBreak => 1.0,
+                // A typical figure; real image sizes are not known here
+                DocumentElement::Image { .. } => 15.0,
+            };
+            let height = lines * line_height;
+            if used + height > page_height && used > 0.0 {
+                pages.push(Page {
+                    start: index,
+                    elements: Vec::new(),
+                });
+                used = 0.0;
+            }
+            if let Some(page) = pages.last_mut() {
+                page.elements.push(element.clone());
+            }
+            used += height;
+        }
+        pages
+    }
+
+    /// Alt text of every image in document order; `None` marks a missing description.
+    pub fn alt_texts(&self) -> Vec<Option<String>> {
+        self.elements
+            .iter()
+            .filter_map(|element| match element {
+                DocumentElement::Image { alt, .. } => Some(alt.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Inserts an element before `index`, shifting comments on later elements
+    /// along with them.
+    pub fn insert_element(&mut self, index: usize, element: DocumentElement) {
+        self.elements.insert(index, element);
+        for comment in &mut self.comments {
+            if let Some(i) = comment.element_index.as_mut().filter(|i| **i >= index) {
+                *i += 1;
+            }
+        }
+    }
+
+    /// Inserts an empty `rows` x `cols` table before element `index`.
+    pub fn insert_table(&mut self, index: usize, rows: usize, cols: usize) -> Result<(), String> {
+        if index > self.elements.len() {
+            return Err(format!("insert index {} out of range for {} elements", index, self.elements.len()));
+        }
+        let rows = vec![vec![TableCell::new(Vec::new()); cols]; rows];
+        self.insert_element(index, DocumentElement::Table { rows });
+        Ok(())
+    }
+
+    /// Replaces a cell's content with a single unstyled run. `col` counts the
+    /// cells present in the row, so a merged cell counts once.
+    pub fn set_cell(&mut self, table_index: usize, row: usize, col: usize, text: String) -> Result<(), String> {
+        let rows = self
+            .elements
+            .iter_mut()
+            .filter_map(|element| match element {
+                DocumentElement::Table { rows } => Some(rows),
+                _ => None,
+            })
+            .nth(table_index)
+            .ok_or_else(|| format!("table index {} out of range", table_index))?;
+        let row_count = rows.len();
+        let cells = rows.get_mut(row).ok_or_else(|| format!("row {} out of range for {} rows", row, row_count))?;
+        let cell_count = cells.len();
+        let cell = cells
+            .get_mut(col)
+            .ok_or_else(|| format!("column {} out of range for {} cells in row {}", col, cell_count, row))?;
+        cell.runs = vec![TextRun::plain(text)];
+        Ok(())
+    }
+
+    /// Removes the element at `index`, detaching comments on it and shifting
+    /// later comments down so they stay on the same element.
+    pub fn remove_element(&mut self, index: usize) -> DocumentElement {
+        let removed = self.elements.remove(index);
+        for comment in &mut self.comments {
+            comment.element_index = match comment.element_index {
+                Some(i) if i == index => None,
+                Some(i) if i > index => Some(i - 1),
+                other => other,
+            };
+        }
+        removed
+    }
+
+    /// Keeps only the elements in `start..end`, clamped to the document.
+    /// Comments on dropped elements are detached, the rest move with theirs.
+    pub fn keep_range(&mut self, start: usize, end: usize) {
+        let end = end.min(self.elements.len());
+        let start = start.min(end);
+        self.elements.truncate(end);
+        self.elements.drain(..start);
+        for comment in &mut self.comments {
+            comment.element_index = match comment.element_index {
+                Some(i) if (start..end).contains(&i) => Some(i - start),
+                _ => None,
+            };
+        }
+    }
+
+    /// Splits the paragraph or heading at `index` into two elements of the same
+    /// kind at `char_offset`; an offset at either end leaves one half empty.
+    /// Returns false, changing nothing, for any other element.
+    pub fn split_paragraph(&mut self, index: usize, char_offset: usize) -> bool {
+        let Some(element) = self.elements.get_mut(index) else {
+            return false;
+        };
+        let Some(runs) = element.runs_mut() else {
+            return false;
+        };
+        let tail = split_runs(runs, char_offset);
+        let mut second = element.clone();
+        if let Some(runs) = second.runs_mut() {
+            *runs = tail;
+        }
+        self.insert_element(index + 1, second);
+        true
+    }
+
+    /// Appends the runs of the element after `index` to it and removes that
+    /// element, moving its comments onto the merged one. Returns false,
+    /// changing nothing, unless both are paragraphs or headings.
+    pub fn merge_paragraphs(&mut self, index: usize) -> bool {
+        let mergeable = |element: Option<&DocumentElement>| {
+            matches!(element, Some(DocumentElement::Paragraph { .. } | DocumentElement::Heading { .. }))
+        };
+        if !mergeable(self.elements.get(index)) || !mergeable(self.elements.get(index + 1)) {
+            return false;
+        }
+        for comment in &mut self.comments {
+            if comment.element_index == Some(index + 1) {
+                comment.element_index = Some(index);
+            }
+        }
+        let second = self.remove_element(index + 1);
+        if let Some(runs) = self.elements[index].runs_mut() {
+            runs.extend_from_slice(second.runs());
+        }
+        true
+    }
+
+    /// Words in paragraphs and list items, plus headings and table cells
+    /// when asked for. Run boundaries inside a word don't split it.
+    pub fn word_count(&self, include_headings: bool, include_tables: bool) -> usize {
+        let words = |runs: &[TextRun]| {
+            let text: String = runs.iter().map(|run| run.text.as_str()).collect();
+            text.split_whitespace().count()
+        };
+        self.elements
+            .iter()
+            .map(|element| match element {
+                DocumentElement::Paragraph { runs, .. } => words(runs),
+                DocumentElement::Heading { runs, .. } if include_headings => words(runs),
+                DocumentElement::List { items, .. } => {
+                    items.iter().flat_map(|item| item_paragraphs(item)).map(|runs| words(runs)).sum()
+                }
+                DocumentElement::Table { rows } if include_tables => {
+                    rows.iter().flatten().map(|cell| words(&cell.runs)).sum()
+                }
+                _ => 0,
+            })
+            .sum()
+    }
+
+    /// Minutes to read every word `word_count` counts at `wpm` words per
+    /// minute, rounded up; an empty document takes none.
+    pub fn reading_time_minutes(&self, wpm: u32) -> usize {
+        self.word_count(true, true).div_ceil(wpm.max(1) as usize)
+    }
+
+    /// Paragraphs with any text, counting each list item paragraph and,
+    /// optionally, headings.
+    pub fn paragraph_count(&self, include_headings: bool) -> usize {
+        let has_text = |runs: &[TextRun]| runs.iter().any(|run| !run.text.trim().is_empty());
+        self.elements
+            .iter()
+            .map(|element| match element {
+                DocumentElement::Paragraph { runs, .. } => usize::from(has_text(runs)),
+                DocumentElement::Heading { runs, .. } if include_headings => usize::from(has_text(runs)),
+                DocumentElement::List { items, .. } => items
+                    .iter()
+                    .flat_map(|item| item_paragraphs(item))
+                    .filter(|runs| has_text(runs))
+                    .count(),
+                _ => 0,
+            })
+            .sum()
+    }
+
+    /// Number of top-level elements of each kind, keyed by `DocumentElement::kind`.
+    pub fn counts(&self) -> HashMap<String, usize> {
+        let mut counts = HashMap::new();
+        for element in &self.elements {
+            *counts.entry(element.kind().to_string()).or_insert(0) += 1;
+        }
+        counts
+    }
+
+    /// Indices of the top-level elements of one kind, in document order.
+    pub fn element_indices_of_kind(&self, kind: &str) -> Vec<usize> {
+        self.elements
+            .iter()
+            .enumerate()
+            .filter(|(_, element)| element.kind() == kind)
+            .map(|(index, _)| index)
+            .collect()
+    }
+
+    /// Removes hidden runs, dropping paragraphs and headings left with no visible text.
+    pub fn strip_hidden_runs(&mut self) {
+        let strip = |element: &mut DocumentElement| match element {
+            DocumentElement::Paragraph { runs, .. } | DocumentElement::Heading { runs, .. } => {
+                let had_runs = !runs.is_empty();
+                runs.retain(|run| !run.style.hidden);
+                !had_runs || !runs.is_empty()
+            }
+            _ => true,
+        };
+        self.elements.retain_mut(strip);
+        for element in &mut self.elements {
+            if let DocumentElement::List { items, .. } = element {
+                for item in items {
+                    item.retain_mut(strip);
+                }
+            }
+        }
+        for runs in self.run_lists_mut() {
+            runs.retain(|run| !run.style.hidden);
+        }
+    }
+
+    /// Coalesces runs and removes style definitions no longer used by any run.
+    /// Runs hold resolved styles rather than style names, so a definition
+    /// counts as used when some run's style equals it: a default-valued
+    /// "Normal" survives any plain run, and equal styles under different
+    /// names are all kept.
+    pub fn optimize(&mut self) {
+        self.coalesce_runs();
+        let mut unused: Vec<(&String, &TextStyle)> = self.styles.iter().collect();
+        let mut runs = self.run_lists().into_iter().flatten();
+        while let Some(run) = runs.next().filter(|_| !unused.is_empty()) {
+            unused.retain(|(_, style)| **style != run.style);
+        }
+        let unused: std::collections::HashSet<String> = unused.into_iter().map(|(name, _)| name.clone()).collect();
+        self.styles.retain(|name, _| !unused.contains(name));
+    }
+}
+
+/// Builds a document element by element, e.g.
+/// `DocumentBuilder::new().heading(1, "Results").paragraph("Passed.").build()`.
+#[derive(Debug, Clone, Default)]
+pub struct DocumentBuilder {
+    doc: StructuredDocument,
+}
+
+impl DocumentBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn heading(mut self, level: u8, text: impl Into<String>) -> Self {
+        self.doc.elements.push(DocumentElement::Heading {
+            level,
+            runs: vec![TextRun::plain(text)],
+            background: None,
+            direction: None,
+        });
+        self
+    }
+
+    pub fn paragraph(self, text: impl Into<String>) -> Self {
+        self.paragraph_runs(vec![TextRun::plain(text)])
+    }
+
+    pub fn paragraph_runs(mut self, runs: Vec<TextRun>) -> Self {
+        self.doc.elements.push(DocumentElement::Paragraph { runs, background: None, indent: None, alignment: None, direction: None });
+        self
+    }
+
+    /// A flat list with one paragraph per item.
+    pub fn list<I, T>(mut self, items: I, ordered: bool) -> Self
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<String>,
+    {
+        let items = items
+            .into_iter()
+            .map(|item| {
+                vec![DocumentElement::Paragraph {
+                    runs: vec![TextRun::plain(item)],
+                    background: None,
+                    indent: None,
+                    alignment: None,
+                    direction: None,
+                }]
+            })
+            .collect();
+        self.doc.elements.push(DocumentElement::List {
+            items,
+            ordered,
+            levels: Vec::new(),
+            display_levels: Vec::new(),
+        });
+        self
+    }
+
+    pub fn build(self) -> StructuredDocument {
+        self.doc
+    }
+}
+
+/// Text color of the built-in hyperlink character styles.
+const LINK_COLOR: &str = "#0563c1";
+
+/// Cuts `runs` at a character offset and returns the runs after it; a run
+/// the offset falls inside is split with its style kept on both halves.
+fn split_runs(runs: &mut Vec<TextRun>, char_offset: usize) -> Vec<TextRun> {
+    let mut remaining = char_offset;
+    for index in 0..runs.len() {
+        let run = &mut runs[index];
+        let len = run.text.chars().count();
+        if remaining < len {
+            if remaining == 0 {
+                return runs.split_off(index);
+            }
+            let byte = run.text.char_indices().nth(remaining).map_or(run.text.len(), |(byte, _)| byte);
+            let text = run.text.split_off(byte);
+            let second = TextRun {
+                text,
+                bookmarks: Vec::new(),
+                ..run.clone()
+            };
+            let mut tail = runs.split_off(index + 1);
+            tail.insert(0, second);
+            return tail;
+        }
+        remaining -= len;
+    }
+    Vec::new()
+}
+
+/// Runs of each paragraph or heading in a list item, in order.
+fn item_paragraphs(item: &[DocumentElement]) -> impl Iterator<Item = &Vec<TextRun>> {
+    item.iter().filter_map(|element| match element {
+        DocumentElement::Paragraph { runs, .. } | DocumentElement::Heading { runs, .. } => Some(runs),
+        _ => None,
+    })
+}
+
+fn item_paragraphs_mut(item: &mut [DocumentElement]) -> impl Iterator<Item = &mut Vec<TextRun>> {
+    item.iter_mut().filter_map(|element| match element {
+        DocumentElement::Paragraph { runs, .. } | DocumentElement::Heading { runs, .. } => Some(runs),
+        _ => None,
+    })
+}
+
+/// Whether an optional style color is unset or a `#rrggbb` hex color.
+fn is_valid_color(color: &Option<String>) -> bool {
+    color.as_deref().is_none_or(|color| {
+        color.len() == 7 && color.starts_with('#') && color[1..].chars().all(|c| c.is_ascii_hexdigit())
+    })
+}
+
+/// Most edits `StructuredDocument::diff` searches for before giving up on a
+/// minimal diff; the search keeps O(edits²) state.
+const MAX_DIFF_EDITS: usize = 1000;
+
+/// Myers' shortest edit script from `old` to `new` as equal, delete and
+/// insert ops in order, or `None` when it takes more than `max_edits` edits.
+fn myers_diff(old: &[usize], new: &[usize], max_edits: usize) -> Option<Vec<DiffOp>> {
+    let (n, m) = (old.len() as isize, new.len() as isize);
+    let max = (n + m).min(max_edits as isize);
+    let offset = max + 1;
+    // furthest[k + offset] is the furthest x reached on diagonal k = x - y
+    let mut furthest = vec![0isize; 2 * offset as usize + 1];
+    // The diagonals -d..=d after each step, for walking the path back
+    let mut trace: Vec<Vec<isize>> = Vec::new();
+    let mut edits = None;
+    'search: for d in 0..=max {
+        for k in (-d..=d).step_by(2) {
+            let down = k == -d || (k != d && furthest[(k - 1 + offset) as usize] < furthest[(k + 1 + offset) as usize]);
+            let mut x = if down {
+                furthest[(k + 1 + offset) as usize]
+            } else {
+                furthest[(k - 1 + offset) as usize] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && old[x as usize] == new[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            furthest[(k + offset) as usize] = x;
+            if x >= n && y >= m {
+                trace.push(furthest[(offset - d) as usize..=(offset + d) as usize].to_vec());
+                edits = Some(d);
+                break 'search;
+            }
+        }
+        trace.push(furthest[(offset - d) as usize..=(offset + d) as usize].to_vec());
+    }
+
+    let mut ops = Vec::new();
+    let (mut x, mut y) = (n, m);
+    for d in (1..=edits?).rev() {
+        let previous = &trace[d as usize - 1];
+        let at = |k: isize| previous[(k + d - 1) as usize];
+        let k = x - y;
+        let previous_k = if k == -d || (k != d && at(k - 1) < at(k + 1)) { k + 1 } else { k - 1 };
+        let previous_x = at(previous_k);
+        let previous_y = previous_x - previous_k;
+        while x > previous_x && y > previous_y {
+            x -= 1;
+            y -= 1;
+            ops.push(DiffOp::Equal { old_index: x as usize, new_index: y as usize });
+        }
+        ops.push(if previous_k == k + 1 {
+            DiffOp::Insert { new_index: previous_y as usize }
+        } else {
+            DiffOp::Delete { old_index: previous_x as usize }
+        });
+        (x, y) = (previous_x, previous_y);
+    }
+    while x > 0 && y > 0 {
+        x -= 1;
+        y -= 1;
+        ops.push(DiffOp::Equal { old_index: x as usize, new_index: y as usize });
+    }
+    ops.reverse();
+    Some(ops)
+}
+
+/// Largest column or row span accepted from a file; anything wider is a
+/// damaged or hostile document rather than a real table.
+const MAX_TABLE_SPAN: usize = 1000;
+
+/// Deepest list level, counted from 0; DOCX and ODF both define nine.
+const MAX_LIST_LEVEL: u8 = 8;
+
+/// A cell span attribute, defaulting to 1 and clamped to `MAX_TABLE_SPAN`.
+fn parse_span(value: Option<&str>) -> usize {
+    value.and_then(|value| value.parse().ok()).unwrap_or(1).clamp(1, MAX_TABLE_SPAN)
+}
+
+/// Lays a table out on its logical grid: each row lists every column it
+/// occupies, with the cell at its first column and `None` for positions
+/// covered by a column or row span.
+fn table_grid(rows: &[Vec<TableCell>]) -> Vec<Vec<Option<&TableCell>>> {
+    let mut grid = Vec::new();
+    // Rows still covered by a span, by column
+    let mut covered: Vec<usize> = Vec::new();
+    for row in rows {
+        let mut next_covered: Vec<usize> = covered.iter().map(|rows_left| rows_left.saturating_sub(1)).collect();
+        let mut slots = Vec::new();
+        for cell in row {
+            while covered.get(slots.len()).is_some_and(|rows_left| *rows_left > 0) {
+                slots.push(None);
+            }
+            let column = slots.len();
+            let colspan = cell.colspan.clamp(1, MAX_TABLE_SPAN);
+            slots.push(Some(cell));
+            slots.extend((1..colspan).map(|_| None));
+            if next_covered.len() < column + colspan {
+                next_covered.resize(column + colspan, 0);
+            }
+            for rows_left in &mut next_covered[column..column + colspan] {
+                *rows_left = cell.rowspan.saturating_sub(1);
+            }
+        }
+        let covered_width = covered.iter().rposition(|rows_left| *rows_left > 0).map_or(0, |last| last + 1);
+        while slots.len() < covered_width {
+            slots.push(None);
+        }
+        grid.push(slots);
+        covered = next_covered;
+    }
+    grid
+}
+
+/// What a position on a table's grid holds, for writers that need an entry
+/// for covered positions too.
+enum GridSlot<'a> {
+    Cell(&'a TableCell),
+    /// The first column of a cell spanning down from an earlier row; holds
+    /// that cell's colspan.
+    RowContinuation(usize),
+    /// Any other position covered by a span.
+    Covered,
+}
+
+/// `table_grid`, telling apart the kinds of covered position.
+fn table_slots(rows: &[Vec<TableCell>]) -> Vec<Vec<GridSlot<'_>>> {
+    let grid = table_grid(rows);
+    let origin = |row: usize, column: usize| {
+        (0..=row).rev().find_map(|origin_row| {
+            grid[origin_row].iter().enumerate().take(column + 1).find_map(|(origin_column, slot)| {
+                let cell = (*slot)?;
+                let covers = origin_column + cell.colspan.max(1) > column && origin_row + cell.rowspan.max(1) > row;
+                covers.then_some((origin_row, origin_column, cell))
+            })
+        })
+    };
+    grid.iter()
+        .enumerate()
+        .map(|(row, slots)| {
+            slots
+                .iter()
+                .enumerate()
+                .map(|(column, slot)| match slot {
+                    Some(cell) => GridSlot::Cell(cell),
+                    None => match origin(row, column) {
+                        Some((origin_row, origin_column, cell)) if origin_row < row && origin_column == column => {
+                            GridSlot::RowContinuation(cell.colspan.max(1))
+                        }
+                        _ => GridSlot::Covered,
+                    },
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Logical width of each table row, counting columns covered by spans.
+fn table_row_widths(rows: &[Vec<TableCell>]) -> Vec<usize> {
+    table_grid(rows).iter().map(|slots| slots.len()).collect()
+}
+
+/// Whether `href` is an http(s) or mailto URL or an in-document `#` anchor,
+/// the only links written to HTML.
+fn is_safe_href(href: &str) -> bool {
+    let href = href.trim_start().to_ascii_lowercase();
+    ["http://", "https://", "mailto:", "#"].iter().any(|prefix| href.starts_with(prefix))
+}
+
+/// Quotes a CSV field per RFC 4180 when it needs it.
+fn csv_field(text: &str) -> String {
+    if text.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
+
+/// Splits a leading `---` front-matter block off Markdown, returning its
+/// `key: value` pairs and the body after the closing `---` or `...` line.
+/// Only flat scalar values are understood: nested blocks under a key with
+/// no value are skipped, and any other line means the `---` was a thematic
+/// break rather than front matter.
+fn split_front_matter(text: &str) -> Option<(IndexMap<String, String>, &str)> {
+    let rest = text.strip_prefix('\u{feff}').unwrap_or(text);
+    let mut lines = rest.split_inclusive('\n');
+    let opening = lines.next()?;
+    if opening.trim_end() != "---" {
+        return None;
+    }
+    let mut metadata = IndexMap::new();
+    let mut consumed = opening.len();
+    // Whether the last key had an empty value, opening a nested list or map
+    let mut nested = false;
+    for line in lines {
+        consumed += line.len();
+        let line = line.trim_end();
+        if line == "---" || line == "..." {
+            return Some((metadata, &rest[consumed..]));
+        }
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if line.starts_with(char::is_whitespace) || line == "-" || line.starts_with("- ") {
+            if !nested {
+                return None;
+            }
+            continue;
+        }
+        let (key, value) = line.split_once(':').filter(|(key, _)| !key.trim().is_empty())?;
+        nested = value.trim().is_empty();
+        if !nested {
+            let value = value.trim();
+            let value = value
+                .strip_prefix('"')
+                .and_then(|value| value.strip_suffix('"'))
+                .or_else(|| value.strip_prefix('\'').and_then(|value| value.strip_suffix('\'')))
+                .unwrap_or(value);
+            metadata.insert(key.trim().to_string(), value.to_string());
+        }
+    }
+    // No closing line: it was a thematic break, not front matter
+    None
+}
+
+/// Backslash-escapes the characters Markdown would read as inline syntax.
+fn markdown_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '\\' | '*' | '_' | '`' | '[' | ']' | '<' | '>' | '#') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Inline Markdown for a block's runs, writing manual line breaks as
+/// `line_break`. Emphasis markers go inside any leading or trailing spaces,
+/// which would otherwise stop them applying.
+fn runs_to_markdown(runs: &[TextRun], line_break: &str) -> String {
+    let mut markdown = String::new();
+    for run in runs {
+        for name in &run.bookmarks {
+            markdown.push_str(&format!("<a id=\"{}\"></a>", xml_escape(name)));
+        }
+        let text = &run.text;
+        let trimmed = text.trim();
+        if trimmed.is_empty() {
+            markdown.push_str(text);
+            continue;
+        }
+        let mut inner = markdown_escape(trimmed);
+        if run.style.italic {
+            inner = format!("*{}*", inner);
+        }
+        if run.style.bold {
+            inner = format!("**{}**", inner);
+        }
+        if let Some(link) = &run.link {
+            inner = format!("[{}]({})", inner, link.href.replace(' ', "%20").replace(')', "%29"));
+        }
+        let leading = &text[..text.len() - text.trim_start().len()];
+        let trailing = &text[text.trim_end().len()..];
+        markdown.push_str(leading);
+        markdown.push_str(&inner);
+        markdown.push_str(trailing);
+    }
+    markdown.replace('\n', line_break)
+}
+
+/// A ` style` attribute shading a block element, or nothing.
+fn block_style(background: &Option<String>, indent: Option<f64>, alignment: Option<&str>) -> String {
+    let mut declarations = Vec::new();
+    if let Some(color) = background {
+        declarations.push(format!("background-color:{}", xml_escape(color)));
+    }
+    if let Some(indent) = indent.filter(|indent| *indent != 0.0) {
+        declarations.push(format!("margin-left:{}pt", (indent * 100.0).round() / 100.0));
+    }
+    if let Some(alignment) = alignment {
+        declarations.push(format!("text-align:{}", xml_escape(alignment)));
+    }
+    if declarations.is_empty() {
+        String::new()
+    } else {
+        format!(" style=\"{}\"", declarations.join(";"))
+    }
+}
+
+/// A `dir` attribute for a block with an explicit writing direction.
+fn direction_attribute(direction: &Option<String>) -> String {
+    match direction {
+        Some(direction) => format!(" dir=\"{}\"", xml_escape(direction)),
+        None => String::new(),
+    }
+}
+
+/// Lowercases `text` and joins its words with hyphens, dropping punctuation.
+fn slugify(text: &str) -> String {
+    let slug = text
+        .to_lowercase()
+        .split_whitespace()
+        .map(|word| word.chars().filter(|c| c.is_alphanumeric() || *c == '-' || *c == '_').collect::<String>())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join("-");
+    if slug.is_empty() {
+        "section".to_string()
+    } else {
+        slug
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum HtmlBlock {
+    None,
+    Paragraph,
+    Heading(u8),
+    ListItem,
+    Cell { colspan: usize, rowspan: usize },
+}
+
+fn html_entity(name: &str) -> Option<&'static str> {
+    match name {
+        "amp" => Some("&"),
+        "lt" => Some("<"),
+        "gt" => Some(">"),
+        "quot" => Some("\""),
+        "apos" => Some("'"),
+        "nbsp" => Some("\u{a0}"),
+        "ndash" => Some("\u{2013}"),
+        "mdash" => Some("\u{2014}"),
+        "hellip" => Some("\u{2026}"),
+        "copy" => Some("\u{a9}"),
+        _ => None,
+    }
+}
+
+/// Abbreviations whose period doesn't end a sentence, lowercased.
+const ABBREVIATIONS: &[&str] = &[
+    "mr.", "mrs.", "ms.", "dr.", "prof.", "st.", "jr.", "sr.", "vs.", "e.g.", "i.e.", "cf.", "approx.",
+];
+
+/// Sentences ending in `.`, `!` or `?`, past any closing quotes or brackets.
+/// A line break also ends a sentence, so headings and list items without
+/// final punctuation count on their own.
+fn sentence_count(text: &str) -> usize {
+    let mut sentences = 0;
+    for line in text.lines() {
+        let mut open = false;
+        for word in line.split_whitespace() {
+            open = true;
+            let bare = word.trim_end_matches(['"', '\'', ')', ']', '\u{201d}', '\u{2019}']);
+            if !bare.ends_with(['.', '!', '?']) {
+                continue;
+            }
+            let lower = bare.trim_start_matches(['"', '\'', '(', '[', '\u{201c}', '\u{2018}']).to_lowercase();
+            if ABBREVIATIONS.contains(&lower.as_str()) {
+                continue;
+            }
+            sentences += 1;
+            open = false;
+        }
+        if open {
+            sentences += 1;
+        }
+    }
+    sentences
+}
+
+fn collapse_whitespace(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut last_space = false;
+    for c in text.chars() {
+        if c.is_ascii_whitespace() {
+            if !last_space {
+                out.push(' ');
+            }
+            last_space = true;
+        } else {
+            out.push(c);
+            last_space = false;
+        }
+    }
+    out
+}
+
+fn trim_runs(mut runs: Vec<TextRun>) -> Vec<TextRun> {
+    while let Some(first) = runs.first_mut() {
+        first.text = first.text.trim_start_matches(' ').to_string();
+        if !first.text.is_empty() {
+            break;
+        }
+        runs.remove(0);
+    }
+    while let Some(last) = runs.last_mut() {
+        last.text = last.text.trim_end_matches(' ').to_string();
+        if !last.text.is_empty() {
+            break;
+        }
+        runs.pop();
+    }
+    runs
+}
+
+/// A table under construction while a reader walks its rows and cells.
+#[derive(Default)]
+struct TableBuilder {
+    rows: Vec<Vec<TableCell>>,
+    row: Vec<TableCell>,
+    cell: Vec<TextRun>,
+    colspan: usize,
+    rowspan: usize,
+    /// Logical column of the next cell in the current row.
+    column: usize,
+    /// For DOCX vertical merges: the (row, cell) that started a merge, by column.
+    merge_origins: HashMap<usize, (usize, usize)>,
+}
+
+impl TableBuilder {
+    /// Finishes the current cell with the pending span values.
+    fn push_cell(&mut self, runs: Vec<TextRun>) {
+        let colspan = std::mem::replace(&mut self.colspan, 1).max(1);
+        let rowspan = std::mem::replace(&mut self.rowspan, 1).max(1);
+        self.row.push(TableCell { runs, colspan, rowspan });
+        self.column += colspan;
+    }
+
+    fn push_row(&mut self) {
+        self.rows.push(std::mem::take(&mut self.row));
+        self.column = 0;
+    }
+}
+
+/// Incremental state for turning an HTML event stream into document elements.
+/// Unclosed or mismatched tags are resolved by flushing the open block whenever
+/// a new block-level tag starts.
+struct HtmlImporter {
+    doc: StructuredDocument,
+    block: HtmlBlock,
+    runs: Vec<TextRun>,
+    bold: usize,
+    italic: usize,
+    underline: usize,
+    /// Open lists, innermost last: (ordered, items, nesting depth of each item)
+    lists: Vec<(bool, Vec<Vec<DocumentElement>>, Vec<u8>)>,
+    tables: Vec<TableBuilder>,
+    skip_depth: usize,
+    link: Option<Link>,
+}
+
+impl HtmlImporter {
+    fn new() -> Self {
+        Self {
+            doc: StructuredDocument::new(),
+            block: HtmlBlock::None,
+            runs: Vec::new(),
+            bold: 0,
+            italic: 0,
+            underline: 0,
+            lists: Vec::new(),
+            tables: Vec::new(),
+            skip_depth: 0,
+            link: None,
+        }
+    }
+
+    fn push_text(&mut self, text: &str) {
+        if self.skip_depth > 0 {
+            return;
+        }
+        let mut text = collapse_whitespace(text);
+        if self.block == HtmlBlock::None {
+            if text.trim().is_empty() {
+                return;
+            }
+            self.block = HtmlBlock::Paragraph;
+        }
+        let follows_space = self.runs.last().is_none_or(|run| run.text.ends_with(' '));
+        if follows_space {
+            text = text.trim_start_matches(' ').to_string();
+        }
+        if text.is_empty() {
+            return;
+        }
+        self.runs.push(TextRun {
+            text,
+            style: TextStyle {
+                bold: self.bold > 0,
+                italic: self.italic > 0,
+                underline: self.underline > 0,
+                ..Default::default()
+            },
+            link: self.link.clone(),
+            lang: None,
+            bookmarks: Vec::new(),
+        });
+    }
+
+    fn line_break(&mut self) {
+        if self.block == HtmlBlock::None {
+            self.doc.elements.push(DocumentElement::LineBreak);
+        } else {
+            self.runs.push(TextRun {
+                text: "\n".to_string(),
+                style: TextStyle::default(),
+                link: None,
+                lang: None,
+                bookmarks: Vec::new(),
+            });
+        }
+    }
+
+    fn flush_block(&mut self) {
+        let runs = trim_runs(std::mem::take(&mut self.runs));
+        match self.block {
+            HtmlBlock::None | HtmlBlock::Paragraph => {
+                if !runs.is_empty() {
+                    self.doc.elements.push(DocumentElement::Paragraph { runs, background: None, indent: None, alignment: None, direction: None });
+                }
+            }
+            HtmlBlock::Heading(level) => {
+                if !runs.is_empty() {
+                    self.doc.elements.push(DocumentElement::Heading { level, runs, background: None, direction: None });
+                }
+            }
+            HtmlBlock::ListItem => {
+                if let Some(item) = self.lists.last_mut().and_then(|(_, items, _)| items.last_mut()) {
+                    if !runs.is_empty() {
+                        item.push(DocumentElement::Paragraph { runs, background: None, indent: None, alignment: None, direction: None });
+                    }
+                } else if !runs.is_empty() {
+                    self.doc.elements.push(DocumentElement::Paragraph { runs, background: None, indent: None, alignment: None, direction: None });
+                }
+            }
+            HtmlBlock::Cell { colspan, rowspan } => {
+                if let Some(table) = self.tables.last_mut() {
+                    table.colspan = colspan;
+                    table.rowspan = rowspan;
+                    table.push_cell(runs);
+                } else if !runs.is_empty() {
+                    self.doc.elements.push(DocumentElement::Paragraph { runs, background: None, indent: None, alignment: None, direction: None });
+                }
+            }
+        }
+        self.block = HtmlBlock::None;
+    }
+
+    fn start_block(&mut self, block: HtmlBlock) {
+        self.flush_block();
+        self.block = block;
+    }
+
+    fn close_list(&mut self) {
+        self.flush_block();
+        if let Some((ordered, items, levels)) = self.lists.pop() {
+            let (items, mut levels): (Vec<_>, Vec<_>) =
+                items.into_iter().zip(levels).filter(|(item, _)| !item.is_empty()).unzip();
+            if let Some((_, parent_items, parent_levels)) = self.lists.last_mut() {
+                // Nested lists join their parent one level deeper
+                parent_items.extend(items);
+                parent_levels.extend(levels.into_iter().map(|level| level.saturating_add(1)));
+            } else if !items.is_empty() {
+                if levels.iter().all(|level| *level == 0) {
+                    levels.clear();
+                }
+                self.doc.elements.push(DocumentElement::List {
+                    items,
+                    ordered,
+                    levels,
+                    display_levels: Vec::new(),
+                });
+            }
+        }
+    }
+
+    fn close_row(&mut self) {
+        if let Some(table) = self.tables.last_mut() {
+            if !table.row.is_empty() {
+                table.push_row();
+            }
+        }
+    }
+
+    fn close_table(&mut self) {
+        self.flush_block();
+        self.close_row();
+        if let Some(table) = self.tables.pop() {
+            if !table.rows.is_empty() {
+                self.doc.elements.push(DocumentElement::Table { rows: table.rows });
+            }
+        }
+    }
+
+    fn start_tag(&mut self, name: &str, e: &BytesStart) {
+        match name {
+            "head" | "script" | "style" | "title" | "template" => self.skip_depth += 1,
+            // Paragraphs nested in list items and cells stay part of that block
+            "p" | "div" | "blockquote" | "pre"
+                if !matches!(self.block, HtmlBlock::ListItem | HtmlBlock::Cell { .. }) =>
+            {
+                self.start_block(HtmlBlock::None);
+            }
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                let level = name[1..].parse().unwrap_or(1);
+                self.start_block(HtmlBlock::Heading(level));
+            }
+            "ul" | "ol" => {
+                self.flush_block();
+                self.lists.push((name == "ol", Vec::new(), Vec::new()));
+            }
+            "li" => {
+                self.start_block(HtmlBlock::ListItem);
+                if let Some((_, items, levels)) = self.lists.last_mut() {
+                    items.push(Vec::new());
+                    levels.push(0);
+                }
+            }
+            // Each paragraph in a list item becomes one of the item's paragraphs
+            "p" | "div" if matches!(self.block, HtmlBlock::ListItem) => self.start_block(HtmlBlock::ListItem),
+            "table" => {
+                self.flush_block();
+                self.tables.push(TableBuilder::default());
+            }
+            "tr" => {
+                self.flush_block();
+                self.close_row();
+            }
+            "td" | "th" => {
+                let span = |attr: &[u8]| parse_span(attr_value(e, attr).as_deref().map(str::trim));
+                self.start_block(HtmlBlock::Cell {
+                    colspan: span(b"colspan"),
+                    rowspan: span(b"rowspan"),
+                });
+            }
+            "b" | "strong" => self.bold += 1,
+            "i" | "em" => self.italic += 1,
+            "u" => self.underline += 1,
+            "a" => {
+                self.link = attr_value(e, b"href").map(|href| Link {
+                    href,
+                    title: attr_value(e, b"title"),
+                })
+            }
+            "br" => self.line_break(),
+            _ => {}
+        }
+    }
+
+    fn end_tag(&mut self, name: &str) {
+        match name {
+            "head" | "script" | "style" | "title" | "template" => {
+                self.skip_depth = self.skip_depth.saturating_sub(1)
+            }
+            "p" | "div" | "blockquote" | "pre"
+                if !matches!(self.block, HtmlBlock::ListItem | HtmlBlock::Cell { .. }) =>
+            {
+                self.flush_block();
+            }
+            "p" | "div" if matches!(self.block, HtmlBlock::ListItem) => self.start_block(HtmlBlock::ListItem),
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" | "li" | "td" | "th" => self.flush_block(),
+            "ul" | "ol" => self.close_list(),
+            "tr" => {
+                self.flush_block();
+                self.close_row();
+            }
+            "table" => self.close_table(),
+            "b" | "strong" => self.bold = self.bold.saturating_sub(1),
+            "i" | "em" => self.italic = self.italic.saturating_sub(1),
+            "u" => self.underline = self.underline.saturating_sub(1),
+            "a" => self.link = None,
+            _ => {}
+        }
+    }
+
+    fn finish(mut self) -> StructuredDocument {
+        self.flush_block();
+        while !self.lists.is_empty() {
+            self.close_list();
+        }
+        while !self.tables.is_empty() {
+            self.close_table();
+        }
+        self.doc.coalesce_runs();
+        self.doc
+    }
+}
+
+/// Line terminator used when writing text out.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LineEnding {
+    #[default]
+    Lf,
+    CrLf,
+    /// Lone `\r`, as in classic Mac OS files.
+    Cr,
+    /// `CrLf` on Windows, `Lf` elsewhere.
+    Native,
+}
+
+impl LineEnding {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "lf" => Some(LineEnding::Lf),
+            "crlf" => Some(LineEnding::CrLf),
+            "cr" => Some(LineEnding::Cr),
+            "native" => Some(LineEnding::Native),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            LineEnding::Lf => "lf",
+            LineEnding::CrLf => "crlf",
+            LineEnding::Cr => "cr",
+            LineEnding::Native => "native",
+        }
+    }
+
+    /// The ending of the first line terminator in `text`, if it has one.
+    pub fn detect(text: &str) -> Option<Self> {
+        let index = text.find(['\r', '\n'])?;
+        Some(match &text.as_bytes()[index..] {
+            [b'\r', b'\n', ..] => LineEnding::CrLf,
+            [b'\r', ..] => LineEnding::Cr,
+            _ => LineEnding::Lf,
+        })
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LineEnding::Lf => "\n",
+            LineEnding::CrLf => "\r\n",
+            LineEnding::Cr => "\r",
+            LineEnding::Native => {
+                if cfg!(windows) {
+                    "\r\n"
+                } else {
+                    "\n"
+                }
+            }
+        }
+    }
+}
+
+/// Rewrites `\r\n`, lone `\r` and `\n` terminators to a single consistent ending.
+fn normalize_line_endings(text: &str, ending: LineEnding) -> String {
+    let normalized = text.replace("\r\n", "\n").replace('\r', "\n");
+    match ending.as_str() {
+        "\n" => normalized,
+        terminator => normalized.replace('\n', terminator),
+    }
+}
+
+/// Options shared by the document readers.
+#[derive(Debug, Clone, Default)]
+pub struct ReadOptions {
+    /// Keep runs marked as hidden (DOCX `w:vanish`, ODT `text:display="none"`).
+    pub include_hidden: bool,
+    /// Read the content of objects embedded inline in ODT text (`draw:object`),
+    /// such as spreadsheet tables, as if it were part of the body.
+    pub include_embedded: bool,
+    /// Leave link runs with only their own formatting, rather than the blue
+    /// underline the hyperlink character style gives them.
+    pub raw_link_style: bool,
+    /// Keep whitespace at the edges of text nodes instead of trimming it:
+    /// DOCX `w:t` text marked `xml:space="preserve"` is kept verbatim, and
+    /// ODT paragraph text has its whitespace collapsed to single spaces as
+    /// ODF specifies, unless the paragraph is marked `xml:space="preserve"`.
+    pub preserve_whitespace: bool,
+    /// Skip past malformed XML in the ODT body instead of failing, noting
+    /// each error as a warning.
+    pub lenient: bool,
+}
+
+fn parse_hunk_range(range: &str) -> Option<(usize, usize)> {
+    let range = range.get(1..)?;
+    match range.split_once(',') {
+        Some((start, count)) => Some((start.parse().ok()?, count.parse().ok()?)),
+        None => Some((range.parse().ok()?, 1)),
+    }
+}
+
+/// Applies a unified diff to `text`, requiring every context and removed line
+/// to match exactly. The error names the first hunk that failed.
+fn apply_unified_diff(text: &str, diff: &str) -> Result<String, String> {
+    let source: Vec<&str> = text.split('\n').collect();
+    let mut output: Vec<String> = Vec::new();
+    let mut position = 0;
+    let mut lines = diff.lines().peekable();
+    let mut hunk_number = 0;
+
+    while let Some(header) = lines.next() {
+        if !header.starts_with("@@") {
+            continue;
+        }
+        hunk_number += 1;
+        let mut parts = header.trim_start_matches("@@").split_whitespace();
+        let malformed = || format!("hunk {} has a malformed header: {}", hunk_number, header);
+        let (old_start, old_count) = parts.next().and_then(parse_hunk_range).ok_or_else(malformed)?;
+        let (_, new_count) = parts.next().and_then(parse_hunk_range).ok_or_else(malformed)?;
+
+        // The header's counts say where the hunk ends; a body line may itself
+        // look like a file header ("--- " from a removed "-- " line)
+        let mut expected: Vec<&str> = Vec::new();
+        let mut replacement: Vec<&str> = Vec::new();
+        let mut old_seen = 0;
+        let mut new_seen = 0;
+        while old_seen < old_count || new_seen < new_count {
+            let next = lines.next().ok_or_else(|| {
+                format!(
+                    "hunk {} ends after {} of {} old and {} of {} new lines",
+                    hunk_number, old_seen, old_count, new_seen, new_count
+                )
+            })?;
+            match next.chars().next() {
+                Some(' ') => {
+                    expected.push(&next[1..]);
+                    replacement.push(&next[1..]);
+                    old_seen += 1;
+                    new_seen += 1;
+                }
+                Some('-') => {
+                    expected.push(&next[1..]);
+                    old_seen += 1;
+                }
+                Some('+') => {
+                    replacement.push(&next[1..]);
+                    new_seen += 1;
+                }
+                Some('\\') => {}
+                // Some tools drop the leading space on empty context lines
+                None => {
+                    expected.push("");
+                    replacement.push("");
+                    old_seen += 1;
+                    new_seen += 1;
+                }
+                _ => return Err(format!("hunk {} has an invalid line: {}", hunk_number, next)),
+            }
+            if old_seen > old_count || new_seen > new_count {
+                return Err(format!(
+                    "hunk {} has more lines than its header ({}) counts",
+                    hunk_number, header
+                ));
+            }
+        }
+
+        let start = if old_count == 0 { old_start } else { old_start.saturating_sub(1) };
+        let end = start + expected.len();
+        if start < position || end > source.len() || source[start..end] != expected[..] {
+            return Err(format!(
+                "hunk {} ({}) does not match the document text",
+                hunk_number, header
+            ));
+        }
+        output.extend(source[position..start].iter().map(|line| line.to_string()));
+        output.extend(replacement.iter().map(|line| line.to_string()));
+        position = end;
+    }
+
+    if hunk_number == 0 {
+        return Err("patch contains no hunks".to_string());
+    }
+    output.extend(source[position..].iter().map(|line| line.to_string()));
+    Ok(output.join("\n"))
+}
+
+fn ext_lower(path: &str) -> String {
+    Path::new(path)
+        .extension()
+        .and_then(|s| s.to_str())
+        .unwrap_or("")
+        .to_ascii_lowercase()
+}
+
+/// Like `ext_lower`, but maps template extensions (`.dotx`, `.ott`) onto the
+/// document format they share their package layout with.
+fn format_ext(path: &str) -> String {
+    match ext_lower(path).as_str() {
+        "dotx" => "docx".to_string(),
+        "ott" => "odt".to_string(),
+        ext => ext.to_string(),
+    }
+}
+
+fn local_name(name: &[u8]) -> &[u8] {
+    if let Some(pos) = name.iter().position(|&b| b == b':') {
+        &name[pos + 1..]
+    } else {
+        name
+    }
+}
+
+fn read_zip_file_to_string<R: Read>(mut reader: R) -> std::io::Result<String> {
+    let mut s = String::new();
+    reader.read_to_string(&mut s)?;
+    Ok(s)
+}
+
+/// Names of every entry in a zip-based file (DOCX, ODT, EPUB...), in archive order.
+fn archive_entries(path: &str) -> std::io::Result<Vec<String>> {
+    let mut zip = ZipArchive::new(File::open(path)?).map_err(|e| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("{} is not a readable zip archive: {}", path, e),
+        )
+    })?;
+    let mut names = Vec::with_capacity(zip.len());
+    for index in 0..zip.len() {
+        names.push(zip.by_index_raw(index)?.name().to_string());
+    }
+    Ok(names)
+}
+
+/// Text of one entry in a zip-based file; a leading `/` on `part_name` is
+/// ignored. `None` when the archive has no such entry.
+fn archive_part(path: &str, part_name: &str) -> std::io::Result<Option<String>> {
+    let mut zip = ZipArchive::new(File::open(path)?).map_err(|e| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("{} is not a readable zip archive: {}", path, e),
+        )
+    })?;
+    let part = match zip.by_name(part_name.trim_start_matches('/')) {
+        Ok(part) => part,
+        Err(ZipError::FileNotFound) => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    read_zip_file_to_string(part).map(Some)
+}
+
+/// Size figures for a saved file: the total size of its entries before and
+/// after compression and how many there are. A file that isn't a zip
+/// package counts as one uncompressed entry.
+fn package_stats(path: &str) -> std::io::Result<HashMap<String, u64>> {
+    let (uncompressed, compressed, entries) = match ZipArchive::new(File::open(path)?) {
+        Ok(mut zip) => {
+            let (mut uncompressed, mut compressed) = (0, 0);
+            for index in 0..zip.len() {
+                let entry = zip.by_index_raw(index)?;
+                uncompressed += entry.size();
+                compressed += entry.compressed_size();
+            }
+            (uncompressed, compressed, zip.len() as u64)
+        }
+        Err(_) => {
+            let size = std::fs::metadata(path)?.len();
+            (size, size, 1)
+        }
+    };
+    Ok(HashMap::from([
+        ("uncompressed_bytes".to_string(), uncompressed),
+        ("compressed_bytes".to_string(), compressed),
+        ("entry_count".to_string(), entries),
+    ]))
+}
+
+/// Checks that a DOCX or ODT package is complete before it is read: the
+/// archive opens, every entry decompresses with a matching checksum, and the
+/// parts the format needs are present.
+fn verify_package(path: &str, format: &str) -> std::io::Result<()> {
+    let invalid = |message: String| std::io::Error::new(std::io::ErrorKind::InvalidData, message);
+    let mut zip = ZipArchive::new(File::open(path)?)
+        .map_err(|e| invalid(format!("{} is not a readable zip archive: {}", path, e)))?;
+    for index in 0..zip.len() {
+        let mut entry = zip
+            .by_index(index)
+            .map_err(|e| invalid(format!("damaged zip entry #{}: {}", index, e)))?;
+        let name = entry.name().to_string();
+        std::io::copy(&mut entry, &mut std::io::sink())
+            .map_err(|e| invalid(format!("damaged zip entry {}: {}", name, e)))?;
+    }
+    match format {
+        "docx" => {
+            let main_part = docx_main_part(&mut zip);
+            if zip.by_name(&main_part).is_err() {
+                return Err(invalid(format!("DOCX package has no {}", main_part)));
+            }
+        }
+        "odt" => {
+            if zip.by_name("content.xml").is_err() {
+                return Err(invalid("ODT package has no content.xml".to_string()));
+            }
+            let mimetype = match zip.by_name("mimetype") {
+                Ok(mut entry) => read_zip_file_to_string(&mut entry)?,
+                Err(_) => return Err(invalid("ODT package has no mimetype".to_string())),
+            };
+            let mimetype = mimetype.trim();
+            if !matches!(
+                mimetype,
+                "application/vnd.oasis.opendocument.text" | "application/vnd.oasis.opendocument.text-template"
+            ) {
+                return Err(invalid(format!("ODT package has mimetype '{}', not a text document", mimetype)));
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+fn image_mime_type(name: &str) -> &'static str {
+    match ext_lower(name).as_str() {
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "bmp" => "image/bmp",
+        "svg" => "image/svg+xml",
+        "webp" => "image/webp",
+        "tif" | "tiff" => "image/tiff",
+        _ => "application/octet-stream",
+    }
+}
+
+fn read_zip_image<R: Read + std::io::Seek>(zip: &mut ZipArchive<R>, name: &str) -> Option<DocumentElement> {
+    let mut entry = zip.by_name(name).ok()?;
+    let mut data = Vec::new();
+    entry.read_to_end(&mut data).ok()?;
+    Some(DocumentElement::Image {
+        name: name.to_string(),
+        mime_type: image_mime_type(name).to_string(),
+        data,
+        alt: None,
+    })
+}
+
+fn read_docx_text(path: &str, options: &ReadOptions) -> std::io::Result<String> {
+    let f = File::open(path)?;
+    let mut zip = ZipArchive::new(f)?;
+    let main_part = docx_main_part(&mut zip);
+    let mut docxml = zip.by_name(&main_part)?;
+    let xml = read_zip_file_to_string(&mut docxml)?;
+
+    extract_docx_text(Reader::from_str(&xml), options)
+}
+
+/// Like `read_docx_text`, but parses `document.xml` straight from the zip entry
+/// so memory use stays bounded by the extracted text rather than the raw XML.
+fn read_docx_text_streaming(path: &str, options: &ReadOptions) -> std::io::Result<String> {
+    read_docx_text_archive(&mut ZipArchive::new(File::open(path)?)?, options)
+}
+
+/// `read_docx_text_streaming` for an already opened package.
+fn read_docx_text_archive<R: Read + std::io::Seek>(zip: &mut ZipArchive<R>, options: &ReadOptions) -> std::io::Result<String> {
+    let main_part = docx_main_part(zip);
+    let docxml = zip.by_name(&main_part)?;
+
+    extract_docx_text(Reader::from_reader(BufReader::new(docxml)), options)
+}
+
+/// Plain text of a DOCX or ODT file read from a read-only memory map of it,
+/// so the package is paged in by the OS instead of copied into memory.
+/// Other formats give `None`.
+#[cfg(feature = "mmap")]
+fn read_text_mapped(path: &str, options: &ReadOptions) -> std::io::Result<Option<String>> {
+    let ext = format_ext(path);
+    if ext != "docx" && ext != "odt" {
+        return Ok(None);
+    }
+    let file = File::open(path)?;
+    // Safety: the map is only read; like any reader, results are undefined
+    // if another process truncates the file while it is being read
+    let map = unsafe { memmap2::Mmap::map(&file)? };
+    let mut zip = ZipArchive::new(std::io::Cursor::new(&map[..]))?;
+    let mut text = String::new();
+    if ext == "docx" {
+        text = read_docx_text_archive(&mut zip, options)?;
+    } else {
+        read_odt_archive(&mut zip, options, Some(&mut text), &mut Vec::new())?;
+    }
+    Ok(Some(text))
+}
+
+fn extract_docx_text<R: BufRead>(mut reader: Reader<R>, options: &ReadOptions) -> std::io::Result<String> {
+    reader.trim_text(!options.preserve_whitespace);
+    let mut buf = Vec::new();
+    let mut out = String::new();
+    // Only w:t holds document text: markers such as w:proofErr and
+    // w:bookmarkStart, field codes in w:instrText and the whitespace between
+    // elements are all left out
+    let mut in_text = false;
+    // Whether the w:t being read keeps its edge whitespace
+    let mut space_preserved = false;
+
+    // Run text is buffered so a <w:vanish/> in the run properties can discard it
+    let mut in_run = false;
+    let mut run_hidden = false;
+    let mut run_text = String::new();
+
+    loop {
+        match reader.read_event_into(&mut buf) {
+            Ok(Event::Start(e)) if local_name(e.name().as_ref()) == b"r" => {
+                in_run = true;
+                run_hidden = false;
+                run_text.clear();
+            }
+            Ok(Event::Start(e)) if local_name(e.name().as_ref()) == b"t" => {
+                in_text = true;
+                space_preserved = xml_space_preserved(&e);
+            }
+            Ok(Event::End(e)) if local_name(e.name().as_ref()) == b"t" => {
+                in_text = false;
+                space_preserved = false;
+            }
+            Ok(Event::Empty(e)) if in_run && local_name(e.name().as_ref()) == b"vanish" => {
+                run_hidden = !e.attributes().flatten().any(|attr| {
+                    local_name(attr.key.as_ref()) == b"val"
+                        && matches!(attr.value.as_ref(), b"0" | b"false")
+                });
+            }
+            Ok(Event::Empty(e)) if local_name(e.name().as_ref()) == b"p" => {
+                out.push('\n');
+            }
+            Ok(Event::End(e)) if local_name(e.name().as_ref()) == b"r" => {
+                if !run_hidden || options.include_hidden {
+                    out.push_str(&run_text);
+                }
+                in_run = false;
+                run_text.clear();
+            }
+            Ok(Event::End(e)) if local_name(e.name().as_ref()) == b"p" => {
+                out.push('\n');
+            }
+            Ok(Event::Text(t)) if in_text => {
+                let target = if in_run { &mut run_text } else { &mut out };
+                let text = match t.unescape() {
+                    Ok(cow) => cow,
+                    Err(_) => Cow::Owned(String::from_utf8_lossy(t.as_ref()).into_owned()),
+                };
+                if space_preserved || !options.preserve_whitespace {
+                    target.push_str(&text);
+                } else {
+                    target.push_str(text.trim());
+                }
+            }
+            Ok(Event::CData(t)) if in_text => {
+                let txt = String::from_utf8_lossy(t.as_ref());
+                if in_run {
+                    run_text.push_str(&txt);
+                } else {
+                    out.push_str(&txt);
+                }
+            }
+            Ok(Event::Eof) => break,
+            Err(e) => return Err(std::io::Error::other(e.to_string())),
+            _ => {}
+        }
+        buf.clear();
+    }
+
+    Ok(out)
+}
+
+/// Whether an element carries `xml:space="preserve"`.
+fn xml_space_preserved(e: &BytesStart) -> bool {
+    attr_value(e, b"space").as_deref() == Some("preserve")
+}
+
+fn attr_value(e: &BytesStart, name: &[u8]) -> Option<String> {
+    e.attributes()
+        .flatten()
+        .find(|attr| local_name(attr.key.as_ref()) == name)
+        .map(|attr| match attr.unescape_value() {
+            Ok(value) => value.into_owned(),
+            Err(_) => String::from_utf8_lossy(&attr.value).to_string(),
+        })
+}
+
+/// The default language set on an `element` in `xml`, as a BCP 47 tag: ODT's
+/// `fo:language` plus `fo:country`, or an OOXML `w:val`. The document
+/// defaults win over the default paragraph style (Normal / Standard), which
+/// wins over the first language set anywhere else.
+fn parse_language(xml: &str, element: &[u8]) -> Option<String> {
+    let mut reader = Reader::from_str(xml);
+    let mut buf = Vec::new();
+    // Best candidate so far by rank (0 = document defaults), and the rank of
+    // the container being read
+    let mut best: Option<(u8, String)> = None;
+    let mut scope: Option<(Vec<u8>, u8)> = None;
+    loop {
+        match reader.read_event_into(&mut buf) {
+            Ok(Event::Start(e)) | Ok(Event::Empty(e)) if local_name(e.name().as_ref()) == element => {
+                let language = match attr_value(&e, b"language") {
+                    Some(language) => match attr_value(&e, b"country").filter(|country| country != "none") {
+                        Some(country) => Some(format!("{}-{}", language, country)),
+                        None => Some(language),
+                    },
+                    None => attr_value(&e, b"val"),
+                };
+                let rank = scope.as_ref().map_or(2, |(_, rank)| *rank);
+                // "zxx" and "none" mean no linguistic content
+                if let Some(language) = language.filter(|l| !l.is_empty() && !l.starts_with("zxx") && !l.starts_with("none")) {
+                    if best.as_ref().is_none_or(|(best_rank, _)| rank < *best_rank) {
+                        best = Some((rank, language));
+                    }
+                }
+            }
+            Ok(Event::Start(e)) if scope.is_none() => {
+                let name = local_name(e.name().as_ref()).to_vec();
+                let paragraph_family = attr_value(&e, b"family").as_deref() == Some("paragraph");
+                let rank = match name.as_slice() {
+                    b"docDefaults" => Some(0),
+                    b"default-style" if paragraph_family => Some(0),
+                    b"style" if attr_value(&e, b"type").as_deref() == Some("paragraph")
+                        && attr_value(&e, b"default").is_some_and(|d| d == "1" || d == "true") =>
+                    {
+                        Some(1)
+                    }
+                    b"style" if paragraph_family && attr_value(&e, b"name").as_deref() == Some("Standard") => Some(1),
+                    _ => None,
+                };
+                scope = rank.map(|rank| (name, rank));
+            }
+            Ok(Event::End(e)) if scope.as_ref().is_some_and(|(name, _)| name.as_slice() == local_name(e.name().as_ref())) => {
+                scope = None;
+            }
+            Ok(Event::Eof) | Err(_) => break,
+            _ => {}
+        }
+        buf.clear();
+    }
+    best.map(|(_, language)| language)
+}
+
+/// Whether text in `language` (a BCP 47 tag) is written right to left, going
+/// by its script subtag or else its primary language.
+fn is_rtl_language(language: &str) -> bool {
+    const RTL_SCRIPTS: &[&str] = &["arab", "hebr", "syrc", "thaa", "nkoo", "adlm", "mand", "samr"];
+    const RTL_LANGUAGES: &[&str] = &[
+        "ar", "arc", "ckb", "dv", "fa", "he", "iw", "ks", "ku", "ps", "sd", "syr", "ug", "ur", "yi",
+    ];
+    let mut subtags = language.split(['-', '_']).map(str::to_ascii_lowercase);
+    let primary = subtags.next().unwrap_or_default();
+    match subtags.find(|subtag| subtag.len() == 4 && subtag.chars().all(|c| c.is_ascii_alphabetic())) {
+        Some(script) => RTL_SCRIPTS.contains(&script.as_str()),
+        None => RTL_LANGUAGES.contains(&primary.as_str()),
+    }
+}
+
+/// The language an ODT `style:text-properties` element sets: `fo:language`,
+/// else the complex-script language, else the Asian one.
+fn odt_language(e: &BytesStart) -> Option<String> {
+    ["", "-complex", "-asian"].iter().find_map(|suffix| {
+        let language = attr_value(e, format!("language{}", suffix).as_bytes())
+            .filter(|l| !l.is_empty() && l != "zxx" && l != "none")?;
+        Some(match attr_value(e, format!("country{}", suffix).as_bytes()).filter(|c| !c.is_empty() && c != "none") {
+            Some(country) => format!("{}-{}", language, country),
+            None => language,
+        })
+    })
+}
+
+/// A `w:jc` value as a CSS `text-align` value.
+fn docx_alignment(value: &str) -> Option<String> {
+    let alignment = match value {
+        "left" | "start" => "start",
+        "right" | "end" => "end",
+        "center" => "center",
+        "both" | "distribute" => "justify",
+        _ => return None,
+    };
+    Some(alignment.to_string())
+}
+
+/// Reads an OOXML on/off property such as `<w:b/>` or `<w:b w:val="0"/>`.
+fn docx_toggle(e: &BytesStart) -> bool {
+    !matches!(attr_value(e, b"val").as_deref(), Some("0" | "false" | "off"))
+}
+
+/// The fixed palette behind `w:highlight`'s named values.
+const DOCX_HIGHLIGHT_COLORS: &[(&str, &str)] = &[
+    ("yellow", "#ffff00"),
+    ("green", "#00ff00"),
+    ("cyan", "#00ffff"),
+    ("magenta", "#ff00ff"),
+    ("blue", "#0000ff"),
+    ("red", "#ff0000"),
+    ("darkBlue", "#000080"),
+    ("darkCyan", "#008080"),
+    ("darkGreen", "#008000"),
+    ("darkMagenta", "#800080"),
+    ("darkRed", "#800000"),
+    ("darkYellow", "#808000"),
+    ("darkGray", "#808080"),
+    ("lightGray", "#c0c0c0"),
+    ("black", "#000000"),
+    ("white", "#ffffff"),
+];
+
+fn docx_highlight_hex(name: &str) -> Option<&'static str> {
+    DOCX_HIGHLIGHT_COLORS.iter().find(|(n, _)| *n == name).map(|(_, hex)| *hex)
+}
+
+/// Applies a single `<w:rPr>` child element to `style`.
+fn apply_docx_run_property(style: &mut TextStyle, e: &BytesStart) {
+    match local_name(e.name().as_ref()) {
+        b"b" => style.bold = docx_toggle(e),
+        b"i" => style.italic = docx_toggle(e),
+        b"u" => style.underline = !matches!(attr_value(e, b"val").as_deref(), Some("none")),
+        b"vanish" => style.hidden = docx_toggle(e),
+        b"sz" => {
+            // w:sz is measured in half-points
+            if let Some(half_points) = attr_value(e, b"val").and_then(|v| v.parse::<f32>().ok()) {
+                style.font_size = Some(format!("{}pt", half_points / 2.0));
+            }
+        }
+        b"rFonts" => {
+            if let Some(font) = attr_value(e, b"ascii").or_else(|| attr_value(e, b"hAnsi")) {
+                style.font_family = Some(font);
+            }
+        }
+        b"color" => {
+            if let Some(color) = attr_value(e, b"val").filter(|v| v != "auto") {
+                style.color = Some(format!("#{}", color));
+            }
+        }
+        b"highlight" => {
+            style.highlight = attr_value(e, b"val").and_then(|name| docx_highlight_hex(&name)).map(str::to_string);
+        }
+        b"shd" => {
+            if let Some(fill) = attr_value(e, b"fill").filter(|v| v != "auto") {
+                style.highlight = Some(format!("#{}", fill));
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Parses `word/styles.xml` into character styles keyed by style id, plus the
+/// heading level of every paragraph style that represents a heading.
+fn parse_docx_styles(styles_xml: &str) -> (HashMap<String, TextStyle>, HashMap<String, u8>) {
+    let mut styles = HashMap::new();
+    let mut heading_levels = HashMap::new();
+    let mut reader = Reader::from_str(styles_xml);
+    reader.trim_text(true);
+    let mut buf = Vec::new();
+
+    let mut current_id: Option<String> = None;
+    let mut current_style = TextStyle::default();
+    let mut in_rpr = false;
+
+    loop {
+        match reader.read_event_into(&mut buf) {
+            Ok(Event::Start(e)) | Ok(Event::Empty(e)) => match local_name(e.name().as_ref()) {
+                b"style" => {
+                    current_id = attr_value(&e, b"styleId");
+                    current_style = TextStyle::default();
+                }
+                b"name" => {
+                    if let (Some(id), Some(name)) = (&current_id, attr_value(&e, b"val")) {
+                        let name = name.to_ascii_lowercase();
+                        if name == "title" {
+                            heading_levels.entry(id.clone()).or_insert(1);
+                        } else if let Some(level) = name.strip_prefix("heading ").and_then(|l| l.parse().ok()) {
+                            heading_levels.insert(id.clone(), level);
+                        }
+                    }
+                }
+                b"outlineLvl" => {
+                    if let (Some(id), Some(level)) = (&current_id, attr_value(&e, b"val").and_then(|v| v.parse::<u8>().ok())) {
+                        // Level 9 is "body text" in OOXML
+                        if level < 9 {
+                            heading_levels.insert(id.clone(), level + 1);
+                        }
+                    }
+                }
+                b"rPr" => in_rpr = true,
+                _ if in_rpr => apply_docx_run_property(&mut current_style, &e),
+                _ => {}
+            },
+            Ok(Event::End(e)) => match local_name(e.name().as_ref()) {
+                b"rPr" => in_rpr = false,
+                b"style" => {
+                    if let Some(id) = current_id.take() {
+                        styles.insert(id, current_style.clone());
+                    }
+                }
+                _ => {}
+            },
+            Ok(Event::Eof) | Err(_) => break,
+            _ => {}
+        }
+        buf.clear();
+    }
+
+    (styles, heading_levels)
+}
+
+/// Reads the `w:comment` or `w:footnote` entries (per `tag`) of a DOCX
+/// notes part as (id, author, text), paragraphs joined by newlines.
+/// Footnote separators are skipped.
+fn parse_docx_notes(xml: &str, tag: &[u8]) -> Vec<(String, String, String)> {
+    let mut notes = Vec::new();
+    let mut reader = Reader::from_str(xml);
+    reader.trim_text(true);
+    let mut buf = Vec::new();
+    let mut current: Option<(String, String, String)> = None;
+    let mut in_text = false;
+
+    loop {
+        match reader.read_event_into(&mut buf) {
+            Ok(Event::Start(e)) => match local_name(e.name().as_ref()) {
+                name if name == tag => {
+                    let separator = attr_value(&e, b"type").is_some_and(|kind| kind != "normal");
+                    current = (!separator).then(|| {
+                        (
+                            attr_value(&e, b"id").unwrap_or_default(),
+                            attr_value(&e, b"author").unwrap_or_default(),
+                            String::new(),
+                        )
+                    });
+                }
+                b"p" => {
+                    if let Some((_, _, text)) = current.as_mut().filter(|(_, _, text)| !text.is_empty()) {
+                        text.push('\n');
+                    }
+                }
+                b"t" => in_text = true,
+                _ => {}
+            },
+            Ok(Event::End(e)) => match local_name(e.name().as_ref()) {
+                name if name == tag => notes.extend(current.take()),
+                b"t" => in_text = false,
+                _ => {}
+            },
+            Ok(Event::Text(t)) if in_text => {
+                if let (Some((_, _, text)), Ok(cow)) = (current.as_mut(), t.unescape()) {
+                    text.push_str(&cow);
+                }
+            }
+            Ok(Event::Eof) | Err(_) => break,
+            _ => {}
+        }
+        buf.clear();
+    }
+
+    notes
+}
+
+/// Maps each comment id in `comments.xml` to the `w14:paraId` of its last
+/// paragraph, which is how `commentsExtended.xml` refers to comments.
+fn parse_docx_comment_para_ids(xml: &str) -> HashMap<String, String> {
+    let mut para_ids = HashMap::new();
+    let mut reader = Reader::from_str(xml);
+    let mut buf = Vec::new();
+    let mut current: Option<String> = None;
+
+    loop {
+        match reader.read_event_into(&mut buf) {
+            Ok(Event::Start(e)) | Ok(Event::Empty(e)) => match local_name(e.name().as_ref()) {
+                b"comment" => current = attr_value(&e, b"id"),
+                b"p" => {
+                    if let (Some(id), Some(para_id)) = (current.as_ref(), attr_value(&e, b"paraId")) {
+                        para_ids.insert(id.clone(), para_id);
+                    }
+                }
+                _ => {}
+            },
+            Ok(Event::End(e)) if local_name(e.name().as_ref()) == b"comment" => current = None,
+            Ok(Event::Eof) | Err(_) => break,
+            _ => {}
+        }
+        buf.clear();
+    }
+
+    para_ids
+}
+
+/// Reads `commentsExtended.xml` into paraId -> (parent paraId, done).
+fn parse_docx_comments_extended(xml: &str) -> HashMap<String, (Option<String>, bool)> {
+    let mut extended = HashMap::new();
+    let mut reader = Reader::from_str(xml);
+    let mut buf = Vec::new();
+
+    loop {
+        match reader.read_event_into(&mut buf) {
+            Ok(Event::Start(e)) | Ok(Event::Empty(e)) if local_name(e.name().as_ref()) == b"commentEx" => {
+                if let Some(para_id) = attr_value(&e, b"paraId") {
+                    let done = attr_value(&e, b"done").is_some_and(|done| done == "1" || done == "true");
+                    extended.insert(para_id, (attr_value(&e, b"paraIdParent"), done));
+                }
+            }
+            Ok(Event::Eof) | Err(_) => break,
+            _ => {}
+        }
+        buf.clear();
+    }
+
+    extended
+}
+
+/// Nests replies under the comment they answer and records resolved state.
+/// Without `commentsExtended.xml` the comments stay flat with unknown status.
+fn thread_docx_comments(
+    comments: Vec<(String, Comment)>,
+    para_ids: &HashMap<String, String>,
+    extended: Option<&HashMap<String, (Option<String>, bool)>>,
+) -> Vec<Comment> {
+    let Some(extended) = extended else {
+        return comments.into_iter().map(|(_, comment)| comment).collect();
+    };
+    let index_of: HashMap<&str, usize> = comments
+        .iter()
+        .enumerate()
+        .filter_map(|(index, (id, _))| para_ids.get(id).map(|para_id| (para_id.as_str(), index)))
+        .collect();
+    // Word writes a parent before its replies; only earlier parents are honoured,
+    // which also rules out cycles
+    let parents: Vec<Option<usize>> = comments
+        .iter()
+        .enumerate()
+        .map(|(index, (id, _))| {
+            let (parent, _) = extended.get(para_ids.get(id)?)?;
+            index_of.get(parent.as_deref()?).copied().filter(|parent| *parent < index)
+        })
+        .collect();
+    let mut slots: Vec<Option<Comment>> = comments
+        .into_iter()
+        .map(|(id, mut comment)| {
+            comment.resolved = para_ids
+                .get(&id)
+                .and_then(|para_id| extended.get(para_id))
+                .map(|(_, done)| *done);
+            Some(comment)
+        })
+        .collect();
+    // Walk backwards so each reply already carries its own replies when moved
+    for index in (0..slots.len()).rev() {
+        if let Some(parent) = parents[index] {
+            let reply = slots[index].take();
+            if let Some(parent_comment) = slots[parent].as_mut() {
+                parent_comment.replies.splice(0..0, reply);
+            }
+        }
+    }
+    slots.into_iter().flatten().collect()
+}
+
+/// A DOCX content control (`w:sdt`) as found in form templates.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FormField {
+    pub tag: Option<String>,
+    pub alias: Option<String>,
+    /// `richText`, `text`, `checkbox`, `dropDownList`, `comboBox`, `date` or `picture`.
+    #[serde(rename = "type")]
+    pub kind: String,
+    /// The entered text, empty while the placeholder is showing; checkboxes
+    /// report `true` or `false`.
+    pub value: String,
+}
+
+/// Reads every content control in `word/document.xml`, outer controls first.
+fn read_docx_form_fields(path: &str) -> std::io::Result<Vec<FormField>> {
+    let f = File::open(path)?;
+    let mut zip = ZipArchive::new(f)?;
+    let main_part = docx_main_part(&mut zip);
+    let xml = {
+        let mut docxml = zip.by_name(&main_part)?;
+        read_zip_file_to_string(&mut docxml)?
+    };
+
+    let mut reader = Reader::from_str(&xml);
+    reader.trim_text(true);
+    let mut buf = Vec::new();
+    let mut fields = Vec::new();
+    // (index into `fields`, showing placeholder, checked) for each open w:sdt
+    let mut open: Vec<(usize, bool, bool)> = Vec::new();
+    let mut in_properties = false;
+    let mut in_text = false;
+
+    loop {
+        match reader.read_event_into(&mut buf) {
+            Ok(Event::Start(e)) if local_name(e.name().as_ref()) == b"sdt" => {
+                open.push((fields.len(), false, false));
+                fields.push(FormField {
+                    tag: None,
+                    alias: None,
+                    kind: "richText".to_string(),
+                    value: String::new(),
+                });
+            }
+            Ok(Event::Start(e)) | Ok(Event::Empty(e)) if in_properties => {
+                if let Some((index, placeholder, checked)) = open.last_mut() {
+                    let field = &mut fields[*index];
+                    match local_name(e.name().as_ref()) {
+                        b"tag" => field.tag = attr_value(&e, b"val"),
+                        b"alias" => field.alias = attr_value(&e, b"val"),
+                        b"showingPlcHdr" => *placeholder = docx_toggle(&e),
+                        b"checked" => *checked = docx_toggle(&e),
+                        kind @ (b"text" | b"richText" | b"checkbox" | b"dropDownList" | b"comboBox" | b"date" | b"picture") => {
+                            field.kind = String::from_utf8_lossy(kind).to_string();
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            Ok(Event::Start(e)) => match local_name(e.name().as_ref()) {
+                b"sdtPr" => in_properties = true,
+                b"t" => in_text = true,
+                b"p" => {
+                    for (index, _, _) in &open {
+                        if !fields[*index].value.is_empty() {
+                            fields[*index].value.push('\n');
+                        }
+                    }
+                }
+                _ => {}
+            },
+            Ok(Event::End(e)) => match local_name(e.name().as_ref()) {
+                b"sdtPr" => in_properties = false,
+                b"t" => in_text = false,
+                b"sdt" => {
+                    if let Some((index, placeholder, checked)) = open.pop() {
+                        let field = &mut fields[index];
+                        if field.kind == "checkbox" {
+                            field.value = checked.to_string();
+                        } else if placeholder {
+                            field.value.clear();
+                        }
+                    }
+                }
+                _ => {}
+            },
+            Ok(Event::Text(t)) if in_text => {
+                let text = t.unescape().map(|cow| cow.into_owned()).unwrap_or_default();
+                for (index, _, _) in &open {
+                    fields[*index].value.push_str(&text);
+                }
+            }
+            Ok(Event::Eof) => break,
+            Err(e) => return Err(std::io::Error::other(e.to_string())),
+            _ => {}
+        }
+        buf.clear();
+    }
+
+    Ok(fields)
+}
+
+/// Path of a DOCX package's main document part, taken from the
+/// `officeDocument` relationship in `_rels/.rels`. Falls back to
+/// `word/document.xml` when the relationships are missing or point nowhere.
+fn docx_main_part<R: Read + std::io::Seek>(zip: &mut ZipArchive<R>) -> String {
+    let fallback = "word/document.xml".to_string();
+    let rels_xml = match zip.by_name("_rels/.rels") {
+        Ok(mut rels_file) => match read_zip_file_to_string(&mut rels_file) {
+            Ok(xml) => xml,
+            Err(_) => return fallback,
+        },
+        Err(_) => return fallback,
+    };
+    let mut reader = Reader::from_str(&rels_xml);
+    let mut target = None;
+    loop {
+        match reader.read_event() {
+            Ok(Event::Start(e)) | Ok(Event::Empty(e)) if local_name(e.name().as_ref()) == b"Relationship" => {
+                let main = attr_value(&e, b"Type").is_some_and(|kind| kind.ends_with("/officeDocument"));
+                if main && attr_value(&e, b"TargetMode").as_deref() != Some("External") {
+                    target = attr_value(&e, b"Target");
+                    break;
+                }
+            }
+            Ok(Event::Eof) | Err(_) => break,
+            _ => {}
+        }
+    }
+    match target.map(|target| resolve_archive_path("", &target)) {
+        Some(part) if zip.by_name(&part).is_ok() => part,
+        _ => fallback,
+    }
+}
+
+/// The relationships part belonging to `part`, e.g. `word/_rels/document.xml.rels`.
+fn part_rels_path(part: &str) -> String {
+    match part.rsplit_once('/') {
+        Some((dir, name)) => format!("{}/_rels/{}.rels", dir, name),
+        None => format!("_rels/{}.rels", part),
+    }
+}
+
+/// Parses a `.rels` part into a map from relationship id to target, keeping
+/// either the in-package or the external (`TargetMode="External"`) ones.
+fn parse_relationships(rels_xml: &str, external: bool) -> HashMap<String, String> {
+    let mut rels = HashMap::new();
+    let mut reader = Reader::from_str(rels_xml);
+    loop {
+        match reader.read_event() {
+            Ok(Event::Start(e)) | Ok(Event::Empty(e)) if local_name(e.name().as_ref()) == b"Relationship" => {
+                if (attr_value(&e, b"TargetMode").as_deref() == Some("External")) != external {
+                    continue;
+                }
+                if let (Some(id), Some(target)) = (attr_value(&e, b"Id"), attr_value(&e, b"Target")) {
+                    rels.insert(id, target);
+                }
+            }
+            Ok(Event::Eof) | Err(_) => break,
+            _ => {}
+        }
+    }
+    rels
+}
+
+/// The default language a DOCX or ODT package declares, found the way the
+/// structured readers find it; `None` for other formats.
+fn read_package_language(path: &str, ext: &str) -> std::io::Result<Option<String>> {
+    let (styles_part, element): (&str, &[u8]) = match ext {
+        "docx" => ("word/styles.xml", b"lang"),
+        "odt" => ("styles.xml", b"text-properties"),
+        _ => return Ok(None),
+    };
+    let mut zip = ZipArchive::new(File::open(path)?)?;
+    let mut language = match zip.by_name(styles_part) {
+        Ok(mut styles_file) => parse_language(&read_zip_file_to_string(&mut styles_file)?, element),
+        Err(_) => None,
+    };
+    if language.is_none() && ext == "docx" {
+        if let Ok(mut settings_file) = zip.by_name("word/settings.xml") {
+            language = parse_language(&read_zip_file_to_string(&mut settings_file)?, b"themeFontLang");
+        }
+    }
+    Ok(language)
+}
+
+fn read_docx_structured(path: &str) -> std::io::Result<StructuredDocument> {
+    read_docx_structured_with(path, &ReadOptions::default())
+}
+
+fn read_docx_structured_with(path: &str, options: &ReadOptions) -> std::io::Result<StructuredDocument> {
+    let f = File::open(path)?;
+    let mut zip = ZipArchive::new(f)?;
+
+    let mut heading_levels = HashMap::new();
+    let mut doc = StructuredDocument::new();
+    if let Ok(mut styles_file) = zip.by_name("word/styles.xml") {
+        let styles_xml = read_zip_file_to_string(&mut styles_file)?;
+        (doc.styles, heading_levels) = parse_docx_styles(&styles_xml);
+        doc.language = parse_language(&styles_xml, b"lang");
+    }
+    if doc.language.is_none() {
+        if let Ok(mut settings_file) = zip.by_name("word/settings.xml") {
+            doc.language = parse_language(&read_zip_file_to_string(&mut settings_file)?, b"themeFontLang");
+        }
+    }
+    let main_part = docx_main_part(&mut zip);
+    let (rels, external_rels) = match zip.by_name(&part_rels_path(&main_part)) {
+        Ok(mut rels_file) => {
+            let rels_xml = read_zip_file_to_string(&mut rels_file)?;
+            (parse_relationships(&rels_xml, false), parse_relationships(&rels_xml, true))
+        }
+        Err(_) => (HashMap::new(), HashMap::new()),
+    };
+    let (comments, comment_para_ids) = match zip.by_name("word/comments.xml") {
+        Ok(mut comments_file) => {
+            let comments_xml = read_zip_file_to_string(&mut comments_file)?;
+            (parse_docx_notes(&comments_xml, b"comment"), parse_docx_comment_para_ids(&comments_xml))
+        }
+        Err(_) => (Vec::new(), HashMap::new()),
+    };
+    let comments_extended = match zip.by_name("word/commentsExtended.xml") {
+        Ok(mut extended_file) => Some(parse_docx_comments_extended(&read_zip_file_to_string(&mut extended_file)?)),
+        Err(_) => None,
+    };
+    if let Ok(mut footnotes_file) = zip.by_name("word/footnotes.xml") {
+        let footnotes = parse_docx_notes(&read_zip_file_to_string(&mut footnotes_file)?, b"footnote");
+        doc.footnotes = footnotes.into_iter().map(|(_, _, text)| text).collect();
+    }
+    let numbering = match zip.by_name("word/numbering.xml") {
+        Ok(mut numbering_file) => parse_docx_numbering(&read_zip_file_to_string(&mut numbering_file)?),
+        Err(_) => HashMap::new(),
+    };
+
+    let xml = {
+        let mut docxml = zip.by_name(&main_part)?;
+        read_zip_file_to_string(&mut docxml)?
+    };
+
+    let mut reader = Reader::from_str(&xml);
+    reader.trim_text(!options.preserve_whitespace);
+    let mut buf = Vec::new();
+
+    let mut paragraph_runs: Vec<TextRun> = Vec::new();
+    let mut heading_level: Option<u8> = None;
+    let mut in_run = false;
+    let mut in_run_props = false;
+    let mut in_text = false;
+    let mut text_preserved = false;
+    let mut run_style = TextStyle::default();
+    // w:lang of the current run as (w:val, w:bidi, w:eastAsia), and whether
+    // it is marked as right-to-left or complex-script text
+    let mut run_languages: [Option<String>; 3] = Default::default();
+    let mut run_rtl = false;
+    let mut run_text = String::new();
+    let mut tables: Vec<TableBuilder> = Vec::new();
+    let mut image_target: Option<String> = None;
+    let mut image_alt: Option<String> = None;
+    let mut paragraph_background: Option<String> = None;
+    let mut paragraph_indent: Option<f64> = None;
+    let mut paragraph_direction: Option<String> = None;
+    let mut paragraph_alignment: Option<String> = None;
+    let mut run_link: Option<Link> = None;
+    // w:vMerge of the current cell: Some(true) starts a merge, Some(false) continues one
+    let mut cell_vmerge: Option<bool> = None;
+    // Text covered by each comment range, by comment id
+    let mut comment_ranges: HashMap<String, String> = HashMap::new();
+    let mut open_comments: Vec<String> = Vec::new();
+    // Comments whose range has started, until the element it starts in is
+    // complete, and the element index each comment was attached to
+    let mut unplaced_comments: Vec<String> = Vec::new();
+    let mut comment_elements: HashMap<String, usize> = HashMap::new();
+    // Bookmarks waiting for the next run with text, which they start at
+    let mut pending_bookmarks: Vec<String> = Vec::new();
+    // mc:Fallback repeats the mc:Choice content in a legacy form
+    let mut fallback_depth = 0usize;
+    // w:numPr of the current paragraph: (numId, ilvl)
+    let mut paragraph_numbering: (Option<String>, u8) = (None, 0);
+    // numId and element index of the list that numbered paragraphs are joining
+    let mut open_list: Option<(String, usize)> = None;
+
+    loop {
+        match reader.read_event_into(&mut buf) {
+            Ok(Event::Start(e)) if local_name(e.name().as_ref()) == b"Fallback" => fallback_depth += 1,
+            Ok(Event::End(e)) if local_name(e.name().as_ref()) == b"Fallback" => {
+                fallback_depth = fallback_depth.saturating_sub(1)
+            }
+            _ if fallback_depth > 0 => {}
+            Ok(Event::Start(e)) => match local_name(e.name().as_ref()) {
+                b"p" => {
+                    paragraph_runs.clear();
+                    heading_level = None;
+                    paragraph_background = None;
+                    paragraph_indent = None;
+                    paragraph_direction = None;
+                    paragraph_alignment = None;
+                    paragraph_numbering = (None, 0);
+                }
+                b"r" => {
+                    in_run = true;
+                    run_style = TextStyle::default();
+                    run_languages = Default::default();
+                    run_rtl = false;
+                    run_text.clear();
+                }
+                b"rPr" if in_run => in_run_props = true,
+                b"t" if in_run => {
+                    in_text = true;
+                    text_preserved = xml_space_preserved(&e);
+                }
+                b"tbl" => tables.push(TableBuilder::default()),
+                b"tr" => {
+                    if let Some(table) = tables.last_mut() {
+                        table.row.clear();
+                        table.column = 0;
+                    }
+                }
+                b"tc" => {
+                    if let Some(table) = tables.last_mut() {
+                        table.cell.clear();
+                        table.colspan = 1;
+                    }
+                    cell_vmerge = None;
+                }
+                b"hyperlink" => {
+                    // External targets come through relationships, in-document ones name a bookmark
+                    let href = attr_value(&e, b"id")
+                        .and_then(|id| external_rels.get(&id).cloned())
+                        .or_else(|| attr_value(&e, b"anchor").map(|anchor| format!("#{}", anchor)));
+                    run_link = href.map(|href| Link {
+                        href,
+                        title: attr_value(&e, b"tooltip"),
+                    });
+                }
+                b"drawing" => {
+                    image_target = None;
+                    image_alt = None;
+                }
+                b"docPr" => image_alt = attr_value(&e, b"descr").filter(|d| !d.is_empty()),
+                _ => {}
+            },
+            Ok(Event::Empty(e)) => match local_name(e.name().as_ref()) {
+                b"pStyle" => {
+                    heading_level = attr_value(&e, b"val").and_then(|id| {
+                        heading_levels.get(&id).copied().or_else(|| {
+                            id.strip_prefix("Heading").and_then(|level| level.parse().ok())
+                        })
+                    });
+                }
+                b"rStyle" if in_run_props => {
+                    if let Some(style) = attr_value(&e, b"val").and_then(|id| doc.styles.get(&id)) {
+                        run_style = style.clone();
+                    }
+                }
+                b"lang" if in_run_props => {
+                    run_languages = [b"val".as_slice(), b"bidi", b"eastAsia"].map(|name| attr_value(&e, name));
+                }
+                b"rtl" | b"cs" if in_run_props => run_rtl = docx_toggle(&e),
+                _ if in_run_props => apply_docx_run_property(&mut run_style, &e),
+                b"shd" if !in_run => {
+                    paragraph_background = attr_value(&e, b"fill").filter(|v| v != "auto").map(|fill| format!("#{}", fill));
+                }
+                // Table and row w:jc come before the cell's paragraphs reset this
+                b"jc" if !in_run => paragraph_alignment = attr_value(&e, b"val").and_then(|val| docx_alignment(&val)),
+                b"bidi" if !in_run => {
+                    paragraph_direction = Some(if docx_toggle(&e) { "rtl" } else { "ltr" }.to_string());
+                }
+                // Twentieths of a point; w:start is the bidi-aware name for w:left
+                b"ind" if !in_run => {
+                    paragraph_indent = attr_value(&e, b"left")
+                        .or_else(|| attr_value(&e, b"start"))
+                        .and_then(|twips| twips.parse::<f64>().ok())
+                        .map(|twips| twips / 20.0);
+                }
+                // numId 0 explicitly turns numbering off
+                b"numId" => paragraph_numbering.0 = attr_value(&e, b"val").filter(|id| id != "0"),
+                b"ilvl" => paragraph_numbering.1 = attr_value(&e, b"val").and_then(|v| v.parse().ok()).unwrap_or(0),
+                b"tab" if in_run => run_text.push('\t'),
+                b"br" | b"cr" if in_run => run_text.push('\n'),
+                b"docPr" => image_alt = attr_value(&e, b"descr").filter(|d| !d.is_empty()),
+                b"blip" => image_target = attr_value(&e, b"embed").and_then(|id| rels.get(&id).cloned()),
+                b"commentRangeStart" => {
+                    if let Some(id) = attr_value(&e, b"id") {
+                        open_comments.push(id.clone());
+                        unplaced_comments.push(id);
+                    }
+                }
+                b"commentRangeEnd" => {
+                    let id = attr_value(&e, b"id");
+                    open_comments.retain(|open| Some(open) != id.as_ref());
+                }
+                // Word keeps its own "_GoBack" marker for the last edit position
+                b"bookmarkStart" => {
+                    if let Some(name) = attr_value(&e, b"name").filter(|name| name != "_GoBack") {
+                        pending_bookmarks.push(name);
+                    }
+                }
+                b"gridSpan" => {
+                    if let Some(table) = tables.last_mut() {
+                        table.colspan = parse_span(attr_value(&e, b"val").as_deref());
+                    }
+                }
+                b"vMerge" => cell_vmerge = Some(attr_value(&e, b"val").as_deref() == Some("restart")),
+                b"tc" => {
+                    if let Some(table) = tables.last_mut() {
+                        table.push_cell(Vec::new());
+                    }
+                }
+                _ => {}
+            },
+            Ok(Event::End(e)) => match local_name(e.name().as_ref()) {
+                b"t" => in_text = false,
+                b"rPr" => in_run_props = false,
+                b"r" if in_run => {
+                    for id in &open_comments {
+                        comment_ranges.entry(id.clone()).or_default().push_str(&run_text);
+                    }
+                    if !run_text.is_empty() {
+                        // Right-to-left text is tagged with its w:bidi language
+                        let [latin, bidi, east_asian] = std::mem::take(&mut run_languages);
+                        let lang = if run_rtl { bidi.or(latin) } else { latin.or(east_asian) };
+                        paragraph_runs.push(TextRun {
+                            text: std::mem::take(&mut run_text),
+                            style: run_style.clone(),
+                            link: run_link.clone(),
+                            lang,
+                            bookmarks: std::mem::take(&mut pending_bookmarks),
+                        });
+                    }
+                    in_run = false;
+                }
+                b"p" => {
+                    // Bookmarks after the last run still belong to this paragraph
+                    if let Some(run) = paragraph_runs.last_mut() {
+                        run.bookmarks.append(&mut pending_bookmarks);
+                    }
+                    let runs = std::mem::take(&mut paragraph_runs);
+                    if let Some(table) = tables.last_mut() {
+                        // Paragraphs within a cell are joined with line breaks
+                        if !table.cell.is_empty() && !runs.is_empty() {
+                            table.cell.push(TextRun {
+                                text: "\n".to_string(),
+                                style: TextStyle::default(),
+                                link: None,
+                                lang: None,
+                                bookmarks: Vec::new(),
+                            });
+                        }
+                        table.cell.extend(runs);
+                    } else if !runs.is_empty() {
+                        let background = paragraph_background.take();
+                        let indent = paragraph_indent.take();
+                        let direction = paragraph_direction.take();
+                        let alignment = paragraph_alignment.take();
+                        match (heading_level, paragraph_numbering.0.take()) {
+                            (Some(level), _) => {
+                                doc.elements.push(DocumentElement::Heading { level, runs, background, direction })
+                            }
+                            // Consecutive paragraphs with the same numbering form one list
+                            (None, Some(num_id)) => {
+                                let item = vec![DocumentElement::Paragraph { runs, background, indent, alignment, direction }];
+                                let level = paragraph_numbering.1;
+                                let joins = open_list
+                                    .as_ref()
+                                    .is_some_and(|(id, index)| *id == num_id && index + 1 == doc.elements.len());
+                                match doc.elements.last_mut() {
+                                    Some(DocumentElement::List { items, levels, .. }) if joins => {
+                                        items.push(item);
+                                        levels.push(level);
+                                    }
+                                    _ => {
+                                        let style = numbering.get(&num_id).cloned().unwrap_or_default();
+                                        doc.elements.push(DocumentElement::List {
+                                            items: vec![item],
+                                            ordered: style.ordered,
+                                            levels: vec![level],
+                                            display_levels: style.display_levels,
+                                        });
+                                        open_list = Some((num_id, doc.elements.len() - 1));
+                                    }
+                                }
+                            }
+                            (None, None) => doc.elements.push(DocumentElement::Paragraph {
+                                runs,
+                                background,
+                                indent,
+                                alignment,
+                                direction,
+                            }),
+                        }
+                        for id in unplaced_comments.drain(..) {
+                            comment_elements.insert(id, doc.elements.len() - 1);
+                        }
+                    }
+                }
+                b"tc" => {
+                    if let Some(table) = tables.last_mut() {
+                        let column = table.column;
+                        let origin = table.merge_origins.get(&column).copied();
+                        match (cell_vmerge, origin) {
+                            // Continuation cells are covered by the cell above
+                            (Some(false), Some((row, index))) => {
+                                let rowspan = &mut table.rows[row][index].rowspan;
+                                *rowspan = (*rowspan + 1).min(MAX_TABLE_SPAN);
+                                table.column += table.colspan.max(1);
+                                table.colspan = 1;
+                                table.cell.clear();
+                            }
+                            _ => {
+                                if cell_vmerge == Some(true) {
+                                    let origin = (table.rows.len(), table.row.len());
+                                    table.merge_origins.insert(column, origin);
+                                } else {
+                                    table.merge_origins.remove(&column);
+                                }
+                                let cell = std::mem::take(&mut table.cell);
+                                table.push_cell(cell);
+                            }
+                        }
+                    }
+                    cell_vmerge = None;
+                }
+                b"tr" => {
+                    if let Some(table) = tables.last_mut() {
+                        table.push_row();
+                    }
+                }
+                b"tbl" => {
+                    let table = tables.pop();
+                    if let (Some(table), Some(outer)) = (&table, tables.last_mut()) {
+                        // A nested table stays in its cell as text: cells joined
+                        // with tabs, rows with line breaks
+                        for row in &table.rows {
+                            let text = row
+                                .iter()
+                                .map(|cell| cell.runs.iter().map(|run| run.text.as_str()).collect::<String>())
+                                .collect::<Vec<_>>()
+                                .join("\t");
+                            if !outer.cell.is_empty() {
+                                outer.cell.push(TextRun::plain("\n"));
+                            }
+                            outer.cell.push(TextRun::plain(text));
+                        }
+                    } else if let Some(table) = table {
+                        if !table.rows.is_empty() {
+                            doc.elements.push(DocumentElement::Table { rows: table.rows });
+                            for id in unplaced_comments.drain(..) {
+                                comment_elements.insert(id, doc.elements.len() - 1);
+                            }
+                        }
+                    }
+                }
+                b"hyperlink" => run_link = None,
+                b"drawing" => {
+                    if let Some(target) = image_target.take() {
+                        let name = resolve_archive_path(&main_part, &target);
+                        if let Some(DocumentElement::Image { name, mime_type, data, .. }) = read_zip_image(&mut zip, &name) {
+                            doc.elements.push(DocumentElement::Image {
+                                name,
+                                mime_type,
+                                data,
+                                alt: image_alt.take(),
+                            });
+                        }
+                    }
+                }
+                _ => {}
+            },
+            Ok(Event::Text(t)) if in_text => {
+                let text = match t.unescape() {
+                    Ok(cow) => cow,
+                    Err(_) => Cow::Owned(String::from_utf8_lossy(t.as_ref()).into_owned()),
+                };
+                if text_preserved || !options.preserve_whitespace {
+                    run_text.push_str(&text);
+                } else {
+                    run_text.push_str(text.trim());
+                }
+            }
+            Ok(Event::Eof) => break,
+            Err(e) => return Err(std::io::Error::other(e.to_string())),
+            _ => {}
+        }
+        buf.clear();
+    }
+
+    let comments = comments
+        .into_iter()
+        .map(|(id, author, text)| {
+            let comment = Comment {
+                author,
+                text,
+                anchor: comment_ranges.remove(&id),
+                element_index: comment_elements.remove(&id),
+                replies: Vec::new(),
+                resolved: None,
+            };
+            (id, comment)
+        })
+        .collect();
+    doc.comments = thread_docx_comments(comments, &comment_para_ids, comments_extended.as_ref());
+
+    if !options.include_hidden {
+        doc.strip_hidden_runs();
+    }
+    if !options.raw_link_style {
+        doc.style_links();
+    }
+
+    Ok(doc)
+}
+
+/// Numbering settings of a DOCX `w:num`, resolved through its abstract numbering.
+#[derive(Debug, Clone, Default)]
+struct DocxNumbering {
+    /// Whether the top level is numbered rather than bulleted.
+    ordered: bool,
+    /// How many numbers each level shows, from the `%n` placeholders in `w:lvlText`.
+    display_levels: Vec<u8>,
+}
+
+/// Reads `word/numbering.xml` into the settings of each `w:numId`.
+fn parse_docx_numbering(xml: &str) -> HashMap<String, DocxNumbering> {
+    let mut abstract_nums: HashMap<String, DocxNumbering> = HashMap::new();
+    let mut nums: HashMap<String, String> = HashMap::new();
+    let mut reader = Reader::from_str(xml);
+    reader.trim_text(true);
+    let mut buf = Vec::new();
+    let mut current_abstract: Option<(String, DocxNumbering)> = None;
+    let mut current_num: Option<String> = None;
+    let mut level = 0usize;
+    let mut numbered = true;
+
+    loop {
+        match reader.read_event_into(&mut buf) {
+            Ok(Event::Start(e)) | Ok(Event::Empty(e)) => match local_name(e.name().as_ref()) {
+                b"abstractNum" => {
+                    current_abstract = attr_value(&e, b"abstractNumId").map(|id| (id, DocxNumbering::default()));
+                }
+                b"num" => current_num = attr_value(&e, b"numId"),
+                b"abstractNumId" => {
+                    if let (Some(num_id), Some(abstract_id)) = (current_num.clone(), attr_value(&e, b"val")) {
+                        nums.insert(num_id, abstract_id);
+                    }
+                }
+                b"lvl" => {
+                    level = attr_value(&e, b"ilvl").and_then(|v| v.parse().ok()).unwrap_or(0);
+                    numbered = true;
+                }
+                b"numFmt" => {
+                    numbered = !matches!(attr_value(&e, b"val").as_deref(), Some("bullet" | "none"));
+                    if let Some((_, numbering)) = current_abstract.as_mut().filter(|_| level == 0) {
+                        numbering.ordered = numbered;
+                    }
+                }
+                b"lvlText" => {
+                    if let Some((_, numbering)) = current_abstract.as_mut() {
+                        let shown = attr_value(&e, b"val").map(|text| text.matches('%').count()).unwrap_or(1);
+                        if numbering.display_levels.len() <= level {
+                            numbering.display_levels.resize(level + 1, 1);
+                        }
+                        numbering.display_levels[level] = if numbered { shown.max(1) as u8 } else { 1 };
+                    }
+                }
+                _ => {}
+            },
+            Ok(Event::End(e)) => match local_name(e.name().as_ref()) {
+                b"abstractNum" => {
+                    if let Some((id, numbering)) = current_abstract.take() {
+                        abstract_nums.insert(id, numbering);
+                    }
+                }
+                b"num" => current_num = None,
+                _ => {}
+            },
+            Ok(Event::Eof) | Err(_) => break,
+            _ => {}
+        }
+        buf.clear();
+    }
+
+    nums.into_iter()
+        .filter_map(|(num_id, abstract_id)| Some((num_id, abstract_nums.get(&abstract_id)?.clone())))
+        .collect()
+}
+
+/// (level, text) of each heading in a DOCX or ODT file, as `table_of_contents`
+/// would list them, without collecting the text of anything else.
+fn read_outline(path: &str) -> std::io::Result<Vec<(u8, String)>> {
+    match format_ext(path).as_str() {
+        "docx" => read_docx_outline(path),
+        "odt" => read_odt_outline(path),
+        ext => Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            format!("no outline reader for .{} files", ext),
+        )),
+    }
+}
+
+fn read_docx_outline(path: &str) -> std::io::Result<Vec<(u8, String)>> {
+    let mut zip = ZipArchive::new(File::open(path)?)?;
+    let (styles, heading_levels) = match zip.by_name("word/styles.xml") {
+        Ok(mut styles_file) => parse_docx_styles(&read_zip_file_to_string(&mut styles_file)?),
+        Err(_) => (HashMap::new(), HashMap::new()),
+    };
+    let main_part = docx_main_part(&mut zip);
+    let xml = read_zip_file_to_string(&mut zip.by_name(&main_part)?)?;
+
+    let mut reader = Reader::from_str(&xml);
+    reader.trim_text(true);
+    let mut buf = Vec::new();
+    let mut outline = Vec::new();
+    let mut heading: Option<(u8, String)> = None;
+    let mut table_depth = 0usize;
+    let mut fallback_depth = 0usize;
+    let mut in_run = false;
+    let mut in_run_props = false;
+    let mut in_text = false;
+    let mut run_style = TextStyle::default();
+
+    loop {
+        match reader.read_event_into(&mut buf) {
+            Ok(Event::Start(e)) if local_name(e.name().as_ref()) == b"Fallback" => fallback_depth += 1,
+            Ok(Event::End(e)) if local_name(e.name().as_ref()) == b"Fallback" => {
+                fallback_depth = fallback_depth.saturating_sub(1)
+            }
+            _ if fallback_depth > 0 => {}
+            Ok(Event::Start(e)) => match local_name(e.name().as_ref()) {
+                b"tbl" => table_depth += 1,
+                b"p" => heading = None,
+                b"r" => {
+                    in_run = true;
+                    run_style = TextStyle::default();
+                }
+                b"rPr" if in_run => in_run_props = true,
+                b"t" if in_run => in_text = heading.is_some() && !run_style.hidden,
+                _ => {}
+            },
+            Ok(Event::Empty(e)) => match local_name(e.name().as_ref()) {
+                // Headings inside tables stay part of their cell
+                b"pStyle" if table_depth == 0 => {
+                    heading = attr_value(&e, b"val")
+                        .and_then(|id| {
+                            heading_levels.get(&id).copied().or_else(|| {
+                                id.strip_prefix("Heading").and_then(|level| level.parse().ok())
+                            })
+                        })
+                        .map(|level| (level, String::new()));
+                }
+                b"rStyle" if in_run_props => {
+                    if let Some(style) = attr_value(&e, b"val").and_then(|id| styles.get(&id)) {
+                        run_style = style.clone();
+                    }
+                }
+                _ if in_run_props => apply_docx_run_property(&mut run_style, &e),
+                b"tab" if in_run && !run_style.hidden => heading.iter_mut().for_each(|(_, text)| text.push('\t')),
+                b"br" | b"cr" if in_run && !run_style.hidden => {
+                    heading.iter_mut().for_each(|(_, text)| text.push('\n'))
+                }
+                _ => {}
+            },
+            Ok(Event::End(e)) => match local_name(e.name().as_ref()) {
+                b"tbl" => table_depth = table_depth.saturating_sub(1),
+                b"r" => in_run = false,
+                b"rPr" => in_run_props = false,
+                b"t" => in_text = false,
+                b"p" => outline.extend(heading.take().filter(|(_, text)| !text.is_empty())),
+                _ => {}
+            },
+            Ok(Event::Text(t)) if in_text => {
+                if let Some((_, text)) = heading.as_mut() {
+                    match t.unescape() {
+                        Ok(cow) => text.push_str(&cow),
+                        Err(_) => text.push_str(&String::from_utf8_lossy(t.as_ref())),
+                    }
+                }
+            }
+            Ok(Event::Eof) => break,
+            Err(e) => return Err(std::io::Error::other(e.to_string())),
+            _ => {}
+        }
+        buf.clear();
+    }
+
+    Ok(outline)
+}
+
+fn read_odt_outline(path: &str) -> std::io::Result<Vec<(u8, String)>> {
+    let mut zip = ZipArchive::new(File::open(path)?)?;
+    let mut styles = match zip.by_name("styles.xml") {
+        Ok(mut styles_file) => parse_odt_style_props(Reader::from_str(&read_zip_file_to_string(&mut styles_file)?)),
+        Err(_) => HashMap::new(),
+    };
+    let xml = read_zip_file_to_string(&mut zip.by_name("content.xml")?)?;
+    styles.extend(parse_odt_style_props(Reader::from_str(&xml)));
+    let styles = resolve_odt_inheritance(&styles);
+    let hidden = |e: &BytesStart, parent: Option<&OdtStyleProps>| -> (bool, OdtStyleProps) {
+        let mut props = attr_value(e, b"style-name")
+            .and_then(|name| styles.get(&name))
+            .cloned()
+            .unwrap_or_default();
+        if let Some(parent) = parent {
+            props.inherit(parent);
+        }
+        (props.hidden.unwrap_or(false), props)
+    };
+
+    let mut reader = Reader::from_str(&xml);
+    reader.trim_text(true);
+    let mut buf = Vec::new();
+    let mut outline = Vec::new();
+    // Level, text and paragraph style of the heading being read
+    let mut heading: Option<(u8, String, OdtStyleProps)> = None;
+    // Whether each open span (innermost last) hides its text
+    let mut hidden_spans: Vec<bool> = Vec::new();
+    let mut heading_hidden = false;
+    let mut skip_depth = 0usize;
+
+    loop {
+        match reader.read_event_into(&mut buf) {
+            Ok(Event::Start(e)) if matches!(local_name(e.name().as_ref()), b"annotation" | b"note" | b"object") => {
+                skip_depth += 1
+            }
+            Ok(Event::End(e)) if matches!(local_name(e.name().as_ref()), b"annotation" | b"note" | b"object") => {
+                skip_depth = skip_depth.saturating_sub(1)
+            }
+            _ if skip_depth > 0 => {}
+            Ok(Event::Start(e)) => match local_name(e.name().as_ref()) {
+                b"h" => {
+                    let (is_hidden, props) = hidden(&e, None);
+                    heading_hidden = is_hidden;
+                    hidden_spans.clear();
+                    heading = Some((odt_heading_level(&e), String::new(), props));
+                }
+                b"span" => {
+                    if let Some((_, _, props)) = heading.as_ref() {
+                        hidden_spans.push(hidden(&e, Some(props)).0);
+                    }
+                }
+                _ => {}
+            },
+            Ok(Event::End(e)) => match local_name(e.name().as_ref()) {
+                b"h" => {
+                    if let Some((level, text, _)) = heading.take() {
+                        if !text.is_empty() {
+                            outline.push((level, text));
+                        }
+                    }
+                }
+                b"span" => {
+                    hidden_spans.pop();
+                }
+                _ => {}
+            },
+            Ok(Event::Empty(e)) => {
+                let is_hidden = hidden_spans.last().copied().unwrap_or(heading_hidden);
+                if let Some((_, text, _)) = heading.as_mut().filter(|_| !is_hidden) {
+                    text.push_str(&odt_whitespace(&e));
+                }
+            }
+            Ok(Event::Text(t)) => {
+                let is_hidden = hidden_spans.last().copied().unwrap_or(heading_hidden);
+                if let Some((_, text, _)) = heading.as_mut().filter(|_| !is_hidden) {
+                    match t.unescape() {
+                        Ok(cow) => text.push_str(&cow),
+                        Err(_) => text.push_str(&String::from_utf8_lossy(t.as_ref())),
+                    }
+                }
+            }
+            Ok(Event::Eof) => break,
+            Err(e) => return Err(std::io::Error::other(e.to_string())),
+            _ => {}
+        }
+        buf.clear();
+    }
+
+    Ok(outline)
+}
+
+/// Resolves `href` relative to the directory of the archive entry `base`,
+/// folding `.`/`..` segments and decoding percent-escapes.
+fn resolve_archive_path(base: &str, href: &str) -> String {
+    let href = href.split('#').next().unwrap_or("");
+    let mut segments: Vec<&str> = base.split('/').collect();
+    segments.pop();
+    for segment in href.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                segments.pop();
+            }
+            _ => segments.push(segment),
+        }
+    }
+    percent_decode(&segments.join("/"))
+}
+
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).ok();
+            if let Some(byte) = hex.and_then(|h| u8::from_str_radix(h, 16).ok()) {
+                out.push(byte);
+                i += 3;
+                continue;
+            }
+        }
+        out.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).to_string()
+}
+
+fn read_epub_structured(path: &str) -> std::io::Result<StructuredDocument> {
+    let f = File::open(path)?;
+    let mut zip = ZipArchive::new(f)?;
+
+    // META-INF/container.xml points at the OPF package document
+    let container = read_zip_file_to_string(&mut zip.by_name("META-INF/container.xml")?)?;
+    let mut opf_path = None;
+    let mut reader = Reader::from_str(&container);
+    loop {
+        match reader.read_event() {
+            Ok(Event::Start(e)) | Ok(Event::Empty(e)) if local_name(e.name().as_ref()) == b"rootfile" => {
+                for attr in e.attributes().flatten() {
+                    if attr.key.as_ref() == b"full-path" {
+                        opf_path = Some(String::from_utf8_lossy(&attr.value).to_string());
+                    }
+                }
+                if opf_path.is_some() {
+                    break;
+                }
+            }
+            Ok(Event::Eof) => break,
+            Err(e) => return Err(std::io::Error::other(e.to_string())),
+            _ => {}
+        }
+    }
+    let opf_path = opf_path.ok_or_else(|| {
+        std::io::Error::new(std::io::ErrorKind::InvalidData, "EPUB container has no rootfile")
+    })?;
+
+    // The manifest maps ids to chapter files, the spine gives their reading order
+    let opf = read_zip_file_to_string(&mut zip.by_name(&opf_path)?)?;
+    let mut manifest: HashMap<String, String> = HashMap::new();
+    let mut spine: Vec<String> = Vec::new();
+    let mut reader = Reader::from_str(&opf);
+    loop {
+        match reader.read_event() {
+            Ok(Event::Start(e)) | Ok(Event::Empty(e)) => match local_name(e.name().as_ref()) {
+                b"item" => {
+                    let mut id = None;
+                    let mut href = None;
+                    for attr in e.attributes().flatten() {
+                        match attr.key.as_ref() {
+                            b"id" => id = Some(String::from_utf8_lossy(&attr.value).to_string()),
+                            b"href" => href = Some(String::from_utf8_lossy(&attr.value).to_string()),
+                            _ => {}
+                        }
+                    }
+                    if let (Some(id), Some(href)) = (id, href) {
+                        manifest.insert(id, href);
+                    }
+                }
+                b"itemref" => {
+                    for attr in e.attributes().flatten() {
+                        if attr.key.as_ref() == b"idref" {
+                            spine.push(String::from_utf8_lossy(&attr.value).to_string());
+                        }
+                    }
+                }
+                _ => {}
+            },
+            Ok(Event::Eof) => break,
+            Err(e) => return Err(std::io::Error::other(e.to_string())),
+            _ => {}
+        }
+    }
+
+    let mut doc = StructuredDocument::new();
+    for idref in &spine {
+        let Some(href) = manifest.get(idref) else {
+            continue;
+        };
+        let chapter_path = resolve_archive_path(&opf_path, href);
+        let xhtml = match zip.by_name(&chapter_path) {
+            Ok(mut entry) => read_zip_file_to_string(&mut entry)?,
+            Err(_) => continue,
+        };
+        let chapter = StructuredDocument::from_html(&xhtml);
+        if chapter.elements.is_empty() {
+            continue;
+        }
+        if !doc.elements.is_empty() {
+            doc.elements.push(DocumentElement::LineBreak);
+        }
+        doc.elements.extend(chapter.elements);
+    }
+
+    Ok(doc)
+}
+
+/// Reads any supported file into structure, chosen by extension. Formats
+/// without structure (plain text, .doc, PDF) become one paragraph per line.
+fn read_structured_any(path: &str) -> std::io::Result<StructuredDocument> {
+    match format_ext(path).as_str() {
+        "docx" => read_docx_structured(path),
+        "odt" => read_odt_structured(path),
+        "epub" => read_epub_structured(path),
+        "html" | "htm" => read_text_file(path).map(|html| StructuredDocument::from_html(&html)),
+        "md" | "markdown" => read_text_file(path).map(|text| StructuredDocument::from_markdown(&text)),
+        "doc" => read_doc_text(path).map(|text| StructuredDocument::from_plain_text(&text)),
+        "pdf" => read_pdf_text(path).map(|text| StructuredDocument::from_plain_text(&text)),
+        _ => read_text_file(path).map(|text| StructuredDocument::from_plain_text(&text)),
+    }
+}
+
+/// Extensions `convert_dir` picks up; other files are skipped.
+const CONVERTIBLE_EXTENSIONS: &[&str] = &["docx", "dotx", "odt", "ott", "doc", "epub", "pdf", "html", "htm", "txt", "md"];
+
+/// Every file under `dir`, depth first in name order, leaving out `exclude`.
+fn files_under(dir: &Path, exclude: Option<&Path>, files: &mut Vec<std::path::PathBuf>) -> std::io::Result<()> {
+    let mut entries: Vec<std::path::PathBuf> =
+        std::fs::read_dir(dir)?.map(|entry| entry.map(|entry| entry.path())).collect::<Result<_, _>>()?;
+    entries.sort();
+    for path in entries {
+        if exclude.is_some_and(|exclude| path.canonicalize().is_ok_and(|path| path == exclude)) {
+            continue;
+        }
+        if path.is_dir() {
+            files_under(&path, exclude, files)?;
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Reads a text file in whatever encoding it was written in. A BOM wins,
+/// then valid UTF-8, then a statistical guess; bytes that still don't
+/// decode become U+FFFD instead of failing the read.
+fn read_text_file(path: &str) -> std::io::Result<String> {
+    let bytes = std::fs::read(path)?;
+    if let Some((encoding, bom_length)) = encoding_rs::Encoding::for_bom(&bytes) {
+        let (text, _) = encoding.decode_without_bom_handling(&bytes[bom_length..]);
+        return Ok(text.into_owned());
+    }
+    if let Ok(text) = std::str::from_utf8(&bytes) {
+        return Ok(text.to_string());
+    }
+    let mut detector = chardetng::EncodingDetector::new();
+    detector.feed(&bytes, true);
+    let encoding = detector.guess(None, true);
+    let (text, _, _) = encoding.decode(&bytes);
+    Ok(text.into_owned())
+}
+
+/// Extracts the main document text of a Word 97-2003 binary `.doc` file by
+/// following the piece table in its `WordDocument` and table streams.
+fn read_doc_text(path: &str) -> std::io::Result<String> {
+    let unsupported = |message: &str| std::io::Error::new(std::io::ErrorKind::Unsupported, message.to_string());
+    // A missing or unreadable file is reported as such, not as a format problem
+    let mut compound = cfb::CompoundFile::open(File::open(path)?)
+        .map_err(|_| unsupported("not a Word 97-2003 document (no OLE compound file)"))?;
+    let mut word_document = Vec::new();
+    compound
+        .open_stream("/WordDocument")
+        .map_err(|_| unsupported("not a Word 97-2003 document (no WordDocument stream)"))?
+        .read_to_end(&mut word_document)?;
+
+    let u16_at = |bytes: &[u8], at: usize| bytes.get(at..at + 2).map(|b| u16::from_le_bytes([b[0], b[1]]));
+    let u32_at = |bytes: &[u8], at: usize| bytes.get(at..at + 4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]));
+    let truncated = || std::io::Error::new(std::io::ErrorKind::InvalidData, "truncated Word document");
+
+    // FIB: flags, main text length and the location of the piece table (Clx)
+    let flags = u16_at(&word_document, 0x000A).ok_or_else(truncated)?;
+    if flags & 0x0100 != 0 {
+        return Err(unsupported("encrypted Word documents are not supported"));
+    }
+    let text_length = u32_at(&word_document, 0x004C).ok_or_else(truncated)? as usize;
+    let clx_offset = u32_at(&word_document, 0x01A2).ok_or_else(truncated)? as usize;
+    let clx_length = u32_at(&word_document, 0x01A6).ok_or_else(truncated)? as usize;
+
+    let table_name = if flags & 0x0200 != 0 { "/1Table" } else { "/0Table" };
+    let mut table = Vec::new();
+    compound
+        .open_stream(table_name)
+        .map_err(|_| unsupported("Word document has no table stream"))?
+        .read_to_end(&mut table)?;
+    let clx = table.get(clx_offset..clx_offset + clx_length).ok_or_else(truncated)?;
+
+    // Skip formatting (Prc) entries up to the piece table (Pcdt)
+    let mut at = 0;
+    while clx.get(at) == Some(&0x01) {
+        at += 3 + u16_at(clx, at + 1).ok_or_else(truncated)? as usize;
+    }
+    if clx.get(at) != Some(&0x02) {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "Word document has no piece table"));
+    }
+    let pieces_length = u32_at(clx, at + 1).ok_or_else(truncated)? as usize;
+    let pieces = clx.get(at + 5..at + 5 + pieces_length).ok_or_else(truncated)?;
+    // n + 1 character positions followed by n 8-byte piece descriptors
+    let count = pieces_length.saturating_sub(4) / 12;
+
+    // Each character with the file offset just past it, which is how the
+    // paragraph properties below address paragraph ends
+    let mut raw: Vec<(char, usize)> = Vec::new();
+    for index in 0..count {
+        let start = u32_at(pieces, index * 4).ok_or_else(truncated)? as usize;
+        let end = u32_at(pieces, index * 4 + 4).ok_or_else(truncated)? as usize;
+        if start >= text_length {
+            break;
+        }
+        let chars = end.min(text_length).saturating_sub(start);
+        let fc = u32_at(pieces, (count + 1) * 4 + index * 8 + 2).ok_or_else(truncated)?;
+        // Compressed pieces hold one cp1252 byte per character at half the offset
+        if fc & 0x4000_0000 != 0 {
+            let offset = (fc & !0x4000_0000) as usize / 2;
+            let bytes = word_document.get(offset..offset + chars).ok_or_else(truncated)?;
+            let decoded = encoding_rs::WINDOWS_1252.decode_without_bom_handling(bytes).0;
+            raw.extend(decoded.chars().enumerate().map(|(at, c)| (c, offset + at + 1)));
+        } else {
+            let offset = fc as usize;
+            let bytes = word_document.get(offset..offset + chars * 2).ok_or_else(truncated)?;
+            let units = bytes.chunks_exact(2).map(|b| u16::from_le_bytes([b[0], b[1]]));
+            let mut position = offset;
+            for decoded in char::decode_utf16(units) {
+                let (c, width) = match decoded {
+                    Ok(c) => (c, c.len_utf16()),
+                    Err(_) => (char::REPLACEMENT_CHARACTER, 1),
+                };
+                position += 2 * width;
+                raw.push((c, position));
+            }
+        }
+    }
+    let row_ends = doc_row_ends(&word_document, &table);
+
+    // Map Word's control characters; fields keep their result, not their code
+    let mut text = String::with_capacity(raw.len());
+    let mut field_codes: Vec<bool> = Vec::new();
+    for (c, end) in raw {
+        match c {
+            '\u{13}' => field_codes.push(true),
+            '\u{14}' => {
+                if let Some(in_code) = field_codes.last_mut() {
+                    *in_code = false;
                 }
             }
-            Ok(Event::Text(t)) => {
-                match t.unescape() {
-                    Ok(cow) => out.push_str(&cow),
-                    Err(_) => out.push_str(&String::from_utf8_lossy(t.as_ref())),
+            '\u{15}' => {
+                field_codes.pop();
+            }
+            _ if field_codes.last() == Some(&true) => {}
+            '\r' | '\u{0B}' | '\u{0C}' => text.push('\n'),
+            // A row's last cell mark is followed by an end-of-row mark, which
+            // replaces it with the line break
+            '\u{07}' if row_ends.as_ref().is_some_and(|ends| ends.contains(&end)) => {
+                if text.ends_with('\t') {
+                    text.pop();
                 }
+                text.push('\n');
             }
-            Ok(Event::CData(t)) => {
-                let txt = String::from_utf8_lossy(t.as_ref());
-                out.push_str(&txt);
+            '\u{07}' => text.push('\t'),
+            '\u{1E}' => text.push('-'),
+            '\u{01}' | '\u{02}' | '\u{05}' | '\u{08}' | '\u{1F}' => {}
+            _ => text.push(c),
+        }
+    }
+    // Without paragraph properties, a doubled cell mark is the best guess
+    Ok(if row_ends.is_some() { text } else { text.replace("\t\t", "\n") })
+}
+
+/// File offsets just past every end-of-row mark in a `.doc` file: the ends
+/// of paragraphs whose properties set `sprmPFTtp` or `sprmPFInnerTtp`. The
+/// bin table (`PlcfBtePapx`) in the table stream points at 512-byte FKP pages
+/// in `WordDocument` holding each paragraph's properties. `None` when there
+/// is no bin table.
+fn doc_row_ends(word_document: &[u8], table: &[u8]) -> Option<std::collections::HashSet<usize>> {
+    let u16_at = |bytes: &[u8], at: usize| bytes.get(at..at + 2).map(|b| u16::from_le_bytes([b[0], b[1]]));
+    let u32_at = |bytes: &[u8], at: usize| bytes.get(at..at + 4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]));
+    let bin_offset = u32_at(word_document, 0x0102)? as usize;
+    let bin_length = u32_at(word_document, 0x0106)? as usize;
+    let bin_table = table.get(bin_offset..bin_offset.checked_add(bin_length)?)?;
+    if bin_table.is_empty() {
+        return None;
+    }
+    // n + 1 file offsets, then n page numbers
+    let pages = bin_length.saturating_sub(4) / 8;
+    let mut ends = std::collections::HashSet::new();
+    for index in 0..pages {
+        let page_number = (u32_at(bin_table, (pages + 1) * 4 + index * 4)? & 0x003F_FFFF) as usize;
+        let Some(page) = word_document.get(page_number * 512..page_number * 512 + 512) else { continue };
+        let runs = page[511] as usize;
+        for run in 0..runs {
+            let end = u32_at(page, (run + 1) * 4)? as usize;
+            let properties_at = page.get((runs + 1) * 4 + run * 13).copied().unwrap_or(0) as usize * 2;
+            if properties_at == 0 {
+                continue;
+            }
+            // A PapxInFkp: a word count (or a zero then a word count), the style index, then sprms
+            let (start, length) = match page.get(properties_at).copied() {
+                Some(0) => (properties_at + 2, 2 * *page.get(properties_at + 1)? as usize),
+                Some(count) => (properties_at + 1, 2 * count as usize - 1),
+                None => continue,
+            };
+            let Some(sprms) = page.get(start + 2..(start + length).min(512)) else { continue };
+            let mut at = 0;
+            while let Some(sprm) = u16_at(sprms, at) {
+                let operand = at + 2;
+                let size = match sprm >> 13 {
+                    0 | 1 => 1,
+                    2 | 4 | 5 => 2,
+                    3 => 4,
+                    7 => 3,
+                    _ => 1 + *sprms.get(operand)? as usize,
+                };
+                if matches!(sprm, 0x2417 | 0x244C) && sprms.get(operand) == Some(&1) {
+                    ends.insert(end);
+                }
+                at = operand + size;
             }
-            Ok(Event::Eof) => break,
-            Err(e) => return Err(std::io::Error::new(std::io::ErrorKind::Other, e.to_string())),
-            _ => {}
         }
-        buf.clear();
     }
+    Some(ends)
+}
 
-    Ok(out)
+fn read_pdf_text(path: &str) -> std::io::Result<String> {
+    let pdf = lopdf::Document::load(path).map_err(|e| std::io::Error::other(e.to_string()))?;
+    if pdf.is_encrypted() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "encrypted PDF documents are not supported",
+        ));
+    }
+
+    // Pages are separated by a blank line so page boundaries survive in plain text
+    let mut pages = Vec::new();
+    for page_number in pdf.get_pages().keys() {
+        let text = pdf
+            .extract_text(&[*page_number])
+            .map_err(|e| std::io::Error::other(e.to_string()))?;
+        pages.push(text.trim_end().to_string());
+    }
+    Ok(pages.join("\n\n"))
+}
+
+/// Drops characters that are illegal in XML or trip up hand-built JSON: control
+/// characters other than tab, newline and carriage return, and the U+FFFE/U+FFFF
+/// noncharacters. The U+2028/U+2029 separators, which end JavaScript string
+/// literals, become newlines.
+pub fn sanitize_text(text: &str) -> String {
+    text.chars()
+        .filter_map(|c| match c {
+            '\t' | '\n' | '\r' => Some(c),
+            '\u{2028}' | '\u{2029}' => Some('\n'),
+            c if c.is_control() => None,
+            c => is_xml_char(c).then_some(c),
+        })
+        .collect()
 }
 
+/// Whether XML 1.0 can represent `c` at all.
+fn is_xml_char(c: char) -> bool {
+    matches!(c, '\t' | '\n' | '\r' | '\u{20}'..='\u{D7FF}' | '\u{E000}'..='\u{FFFD}' | '\u{10000}'..)
+}
+
+/// Escapes markup characters; characters XML can't represent are dropped and
+/// everything else is kept as it is.
 fn xml_escape(s: &str) -> String {
-    s.replace('&', "&amp;")
+    s.chars()
+        .filter(|&c| is_xml_char(c))
+        .collect::<String>()
+        .replace('&', "&amp;")
         .replace('<', "&lt;")
         .replace('>', "&gt;")
         .replace('"', "&quot;")
 }
 
-fn write_docx_text(path: &str, text: &str) -> std::io::Result<()> {
+/// Zip entry options for a compression setting: `Some(0)` stores entries,
+/// `Some(1..=9)` deflates at that level and `None` uses the default level.
+fn entry_options(compression: Option<u8>) -> FileOptions {
+    match compression {
+        Some(0) => FileOptions::default().compression_method(CompressionMethod::Stored),
+        Some(level) => FileOptions::default()
+            .compression_method(CompressionMethod::Deflated)
+            .compression_level(Some(i32::from(level.min(9)))),
+        None => FileOptions::default().compression_method(CompressionMethod::Deflated),
+    }
+}
+
+fn write_docx_text(path: &str, text: &str, compression: Option<u8>) -> std::io::Result<()> {
+    let mut body = String::new();
+    for line in text.split_terminator('\n') {
+        if line.is_empty() {
+            body.push_str("<w:p/>");
+        } else {
+            body.push_str("<w:p><w:r><w:t>");
+            body.push_str(&xml_escape(line));
+            body.push_str("</w:t></w:r></w:p>");
+        }
+    }
+    write_docx_package(path, &body, &DocxParts::default(), compression)
+}
+
+/// Parts of a DOCX package besides the main document, each added with its
+/// relationship and content type when present.
+#[derive(Default)]
+struct DocxParts<'a> {
+    comments: Option<&'a str>,
+    /// Reply threads and resolved state of the comments
+    comments_extended: Option<&'a str>,
+    styles: Option<&'a str>,
+    numbering: Option<&'a str>,
+    /// (file name under `word/media/`, MIME type, data) of each image
+    media: Vec<(String, &'a str, &'a [u8])>,
+}
+
+/// Writes a DOCX package around the given `w:body` content and `parts`.
+fn write_docx_package(path: &str, body: &str, parts: &DocxParts, compression: Option<u8>) -> std::io::Result<()> {
     let mut f = File::create(path)?;
     let mut zip = ZipWriter::new(&mut f);
 
-    let deflated = FileOptions::default().compression_method(CompressionMethod::Deflated);
+    let deflated = entry_options(compression);
+    let (comments_xml, styles_xml, numbering_xml) = (parts.comments, parts.styles, parts.numbering);
 
     // [Content_Types].xml
-    let content_types = r#"<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>
-<Types xmlns=\"http://schemas.openxmlformats.org/package/2006/content-types\">
-  <Default Extension=\"rels\" ContentType=\"application/vnd.openxmlformats-package.relationships+xml\"/>
-  <Default Extension=\"xml\" ContentType=\"application/xml\"/>
-  <Override PartName=\"/word/document.xml\" ContentType=\"application/vnd.openxmlformats-officedocument.wordprocessingml.document.main+xml\"/>
-</Types>"#;
+    let mut overrides = String::new();
+    let mut extensions = std::collections::HashSet::new();
+    for (name, mime_type, _) in &parts.media {
+        let extension = ext_lower(name);
+        if extensions.insert(extension.clone()) {
+            overrides.push_str(&format!(
+                "<Default Extension=\"{}\" ContentType=\"{}\"/>",
+                xml_escape(&extension),
+                xml_escape(mime_type)
+            ));
+        }
+    }
+    if comments_xml.is_some() {
+        overrides.push_str(r#"<Override PartName="/word/comments.xml" ContentType="application/vnd.openxmlformats-officedocument.wordprocessingml.comments+xml"/>"#);
+    }
+    if parts.comments_extended.is_some() {
+        overrides.push_str(r#"<Override PartName="/word/commentsExtended.xml" ContentType="application/vnd.openxmlformats-officedocument.wordprocessingml.commentsExtended+xml"/>"#);
+    }
+    if styles_xml.is_some() {
+        overrides.push_str(r#"<Override PartName="/word/styles.xml" ContentType="application/vnd.openxmlformats-officedocument.wordprocessingml.styles+xml"/>"#);
+    }
+    if numbering_xml.is_some() {
+        overrides.push_str(r#"<Override PartName="/word/numbering.xml" ContentType="application/vnd.openxmlformats-officedocument.wordprocessingml.numbering+xml"/>"#);
+    }
+    // A template differs from a document only in its main part's type
+    let main_type = if ext_lower(path) == "dotx" { "template" } else { "document" };
+    let content_types = format!(
+        r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<Types xmlns="http://schemas.openxmlformats.org/package/2006/content-types">
+  <Default Extension="rels" ContentType="application/vnd.openxmlformats-package.relationships+xml"/>
+  <Default Extension="xml" ContentType="application/xml"/>
+  <Override PartName="/word/document.xml" ContentType="application/vnd.openxmlformats-officedocument.wordprocessingml.{}.main+xml"/>
+  {}
+</Types>"#,
+        main_type, overrides
+    );
     zip.start_file("[Content_Types].xml", deflated)?;
     zip.write_all(content_types.as_bytes())?;
 
     // _rels/.rels
-    let rels_root = r#"<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>
-<Relationships xmlns=\"http://schemas.openxmlformats.org/package/2006/relationships\">
-  <Relationship Id=\"rId1\" Type=\"http://schemas.openxmlformats.org/officeDocument/2006/relationships/officeDocument\" Target=\"word/document.xml\"/>
+    let rels_root = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships">
+  <Relationship Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/officeDocument" Target="word/document.xml"/>
 </Relationships>"#;
     zip.start_file("_rels/.rels", deflated)?;
     zip.write_all(rels_root.as_bytes())?;
 
     // word/document.xml
-    let mut body = String::new();
-    for line in text.split_terminator('\n') {
-        if line.is_empty() {
-            body.push_str("<w:p/>");
-        } else {
-            body.push_str("<w:p><w:r><w:t>");
-            body.push_str(&xml_escape(line));
-            body.push_str("</w:t></w:r></w:p>");
-        }
-    }
-
     let document_xml = format!(
         "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\
-<w:document xmlns:w=\"http://schemas.openxmlformats.org/wordprocessingml/2006/main\">\
+<w:document xmlns:w=\"http://schemas.openxmlformats.org/wordprocessingml/2006/main\" \
+xmlns:r=\"http://schemas.openxmlformats.org/officeDocument/2006/relationships\" \
+xmlns:wp=\"http://schemas.openxmlformats.org/drawingml/2006/wordprocessingDrawing\">\
 <w:body>{}</w:body>\
 </w:document>",
         body
@@ -290,79 +5213,1040 @@ fn write_docx_text(path: &str, text: &str) -> std::io::Result<()> {
     zip.start_file("word/document.xml", deflated)?;
     zip.write_all(document_xml.as_bytes())?;
 
+    if comments_xml.is_some() || styles_xml.is_some() || numbering_xml.is_some() || !parts.media.is_empty() {
+        let mut relationships = String::new();
+        if comments_xml.is_some() {
+            relationships.push_str(r#"<Relationship Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/comments" Target="comments.xml"/>"#);
+        }
+        if styles_xml.is_some() {
+            relationships.push_str(r#"<Relationship Id="rId2" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/styles" Target="styles.xml"/>"#);
+        }
+        if numbering_xml.is_some() {
+            relationships.push_str(r#"<Relationship Id="rId3" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/numbering" Target="numbering.xml"/>"#);
+        }
+        if parts.comments_extended.is_some() {
+            relationships.push_str(r#"<Relationship Id="rId4" Type="http://schemas.microsoft.com/office/2011/relationships/commentsExtended" Target="commentsExtended.xml"/>"#);
+        }
+        for (index, (name, _, _)) in parts.media.iter().enumerate() {
+            relationships.push_str(&format!(
+                "<Relationship Id=\"rIdImage{}\" \
+Type=\"http://schemas.openxmlformats.org/officeDocument/2006/relationships/image\" Target=\"media/{}\"/>",
+                index + 1,
+                xml_escape(name)
+            ));
+        }
+        let document_rels = format!(
+            r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships">{}</Relationships>"#,
+            relationships
+        );
+        zip.start_file("word/_rels/document.xml.rels", deflated)?;
+        zip.write_all(document_rels.as_bytes())?;
+    }
+    if let Some(comments_xml) = comments_xml {
+        zip.start_file("word/comments.xml", deflated)?;
+        zip.write_all(comments_xml.as_bytes())?;
+    }
+    if let Some(extended_xml) = parts.comments_extended {
+        zip.start_file("word/commentsExtended.xml", deflated)?;
+        zip.write_all(extended_xml.as_bytes())?;
+    }
+    if let Some(styles_xml) = styles_xml {
+        zip.start_file("word/styles.xml", deflated)?;
+        zip.write_all(styles_xml.as_bytes())?;
+    }
+    if let Some(numbering_xml) = numbering_xml {
+        zip.start_file("word/numbering.xml", deflated)?;
+        zip.write_all(numbering_xml.as_bytes())?;
+    }
+    for (name, _, data) in &parts.media {
+        zip.start_file(format!("word/media/{}", name), deflated)?;
+        zip.write_all(data)?;
+    }
+
+    zip.finish()?;
+    Ok(())
+}
+
+/// Adds `offset` to every id written right after `prefix`, e.g. each
+/// `<w:numId w:val="` number of a freshly written body.
+fn offset_ids(xml: &str, prefix: &str, offset: usize) -> String {
+    let mut out = String::with_capacity(xml.len());
+    let mut rest = xml;
+    while let Some(at) = rest.find(prefix) {
+        let start = at + prefix.len();
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let digits = rest.bytes().take_while(u8::is_ascii_digit).count();
+        match rest[..digits].parse::<usize>() {
+            Ok(id) => out.push_str(&(id + offset).to_string()),
+            Err(_) => out.push_str(&rest[..digits]),
+        }
+        rest = &rest[digits..];
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Rewrites the freshly written DOCX at `path` into the `original` package
+/// it was opened from: the original's body content is replaced up to its
+/// final `w:sectPr`, its comments parts by the new comments and their
+/// threads, and every other part (styles, theme, fonts, settings) is copied
+/// untouched. The new lists' numbering definitions join the original ones
+/// under unused ids, and the new images become additional media parts.
+fn write_docx_into_container(path: &str, original: &[u8], compression: Option<u8>) -> std::io::Result<()> {
+    let (fresh_document, fresh_comments, fresh_extended, fresh_numbering, fresh_media) = {
+        let mut fresh = ZipArchive::new(File::open(path)?)?;
+        let document = read_zip_file_to_string(&mut fresh.by_name("word/document.xml")?)?;
+        let mut optional = |name: &str| match fresh.by_name(name) {
+            Ok(mut file) => read_zip_file_to_string(&mut file).map(Some),
+            Err(_) => Ok(None),
+        };
+        let comments = optional("word/comments.xml")?;
+        let extended = optional("word/commentsExtended.xml")?;
+        let numbering = optional("word/numbering.xml")?;
+        let rels = optional("word/_rels/document.xml.rels")?;
+        // (relationship id, file name under word/media/, data) of each image
+        let mut media = Vec::new();
+        for (id, target) in rels.map(|rels| parse_relationships(&rels, false)).unwrap_or_default() {
+            if let Some(name) = target.strip_prefix("media/") {
+                let mut data = Vec::new();
+                fresh.by_name(&format!("word/{}", target))?.read_to_end(&mut data)?;
+                media.push((id, name.to_string(), data));
+            }
+        }
+        media.sort();
+        (document, comments, extended, numbering, media)
+    };
+    let body_of = |xml: &str| -> Option<(usize, usize)> {
+        let open = xml.find("<w:body")?;
+        let start = open + xml[open..].find('>')? + 1;
+        let end = xml.rfind("</w:body>")?;
+        // The last section's properties close the body and hold the page setup
+        let end = xml[start..end].rfind("<w:sectPr").map_or(end, |sect| start + sect);
+        Some((start, end))
+    };
+
+    let mut original = ZipArchive::new(std::io::Cursor::new(original))?;
+    let main = docx_main_part(&mut original);
+    let rels_path = part_rels_path(&main);
+    let main_xml = read_zip_file_to_string(&mut original.by_name(&main)?)?;
+    let rels_xml = match original.by_name(&rels_path) {
+        Ok(mut file) => Some(read_zip_file_to_string(&mut file)?),
+        Err(_) => None,
+    };
+    let mut content_types = read_zip_file_to_string(&mut original.by_name("[Content_Types].xml")?)?;
+    // Parts the original relates to the main part, by relationship type
+    let mut comments_part = None;
+    let mut extended_part = None;
+    let mut numbering_part = None;
+    if let Some(rels_xml) = &rels_xml {
+        let mut reader = Reader::from_str(rels_xml);
+        loop {
+            match reader.read_event() {
+                Ok(Event::Start(e)) | Ok(Event::Empty(e)) if local_name(e.name().as_ref()) == b"Relationship" => {
+                    let target = attr_value(&e, b"Target").map(|target| resolve_archive_path(&main, &target));
+                    match attr_value(&e, b"Type") {
+                        Some(kind) if kind.ends_with("/comments") => comments_part = target,
+                        Some(kind) if kind.ends_with("/commentsExtended") => extended_part = target,
+                        Some(kind) if kind.ends_with("/numbering") => numbering_part = target,
+                        _ => {}
+                    }
+                }
+                Ok(Event::Eof) | Err(_) => break,
+                _ => {}
+            }
+        }
+    }
+    let mut relationships = String::new();
+    let mut replaced = HashMap::new();
+    // Parts the original doesn't have, written after its own
+    let mut added: Vec<(String, Vec<u8>)> = Vec::new();
+
+    // New comments without a comments part to go in need one, with its
+    // relationship and content type
+    if let Some(comments) = fresh_comments {
+        match &comments_part {
+            Some(part) => {
+                replaced.insert(part.clone(), comments);
+            }
+            None => {
+                let part = resolve_archive_path(&main, "comments.xml");
+                relationships.push_str(
+                    "<Relationship Id=\"rIdComments\" \
+Type=\"http://schemas.openxmlformats.org/officeDocument/2006/relationships/comments\" Target=\"comments.xml\"/>",
+                );
+                content_types = content_types.replacen(
+                    "</Types>",
+                    &format!(
+                        "<Override PartName=\"/{}\" \
+ContentType=\"application/vnd.openxmlformats-officedocument.wordprocessingml.comments+xml\"/></Types>",
+                        part
+                    ),
+                    1,
+                );
+                added.push((part, comments.into_bytes()));
+            }
+        }
+        // The original's threads refer to the comments just replaced, so its
+        // extended part gives way to the new one or is emptied
+        match (fresh_extended, &extended_part) {
+            (Some(extended), Some(part)) => {
+                replaced.insert(part.clone(), extended);
+            }
+            (None, Some(part)) => {
+                replaced.insert(
+                    part.clone(),
+                    "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\
+<w15:commentsEx xmlns:w15=\"http://schemas.microsoft.com/office/word/2012/wordml\"/>"
+                        .to_string(),
+                );
+            }
+            (Some(extended), None) => {
+                let part = resolve_archive_path(&main, "commentsExtended.xml");
+                relationships.push_str(
+                    "<Relationship Id=\"rIdCommentsExtended\" \
+Type=\"http://schemas.microsoft.com/office/2011/relationships/commentsExtended\" Target=\"commentsExtended.xml\"/>",
+                );
+                content_types = content_types.replacen(
+                    "</Types>",
+                    &format!(
+                        "<Override PartName=\"/{}\" \
+ContentType=\"application/vnd.openxmlformats-officedocument.wordprocessingml.commentsExtended+xml\"/></Types>",
+                        part
+                    ),
+                    1,
+                );
+                added.push((part, extended.into_bytes()));
+            }
+            (None, None) => {}
+        }
+    }
+
+    // New numbering is shifted past the original's ids so its lists keep their own
+    let mut fresh_body = match body_of(&fresh_document) {
+        Some((start, end)) => fresh_document[start..end].to_string(),
+        None => String::new(),
+    };
+    if let Some(numbering) = fresh_numbering {
+        let original_numbering = match &numbering_part {
+            Some(part) => Some(read_zip_file_to_string(&mut original.by_name(part)?)?),
+            None => None,
+        };
+        let (mut num_base, mut abstract_base) = (0, 0);
+        let mut first_num = None;
+        if let Some(xml) = &original_numbering {
+            let mut reader = Reader::from_str(xml);
+            loop {
+                let position = reader.buffer_position();
+                match reader.read_event() {
+                    Ok(Event::Start(e)) | Ok(Event::Empty(e)) => {
+                        let id = |name: &[u8]| attr_value(&e, name).and_then(|id| id.parse::<usize>().ok());
+                        match local_name(e.name().as_ref()) {
+                            b"abstractNum" => abstract_base = abstract_base.max(id(b"abstractNumId").map_or(0, |id| id + 1)),
+                            b"num" => {
+                                num_base = num_base.max(id(b"numId").unwrap_or(0));
+                                first_num.get_or_insert(position);
+                            }
+                            _ => {}
+                        }
+                    }
+                    Ok(Event::Eof) | Err(_) => break,
+                    _ => {}
+                }
+            }
+        }
+        fresh_body = offset_ids(&fresh_body, "<w:numId w:val=\"", num_base);
+        let numbering = offset_ids(&numbering, "<w:num w:numId=\"", num_base);
+        let numbering = offset_ids(&numbering, "w:abstractNumId=\"", abstract_base);
+        let numbering = offset_ids(&numbering, "<w:abstractNumId w:val=\"", abstract_base);
+        match (&numbering_part, original_numbering) {
+            (Some(part), Some(xml)) => {
+                // Definitions go before the first w:num, instances at the end
+                let definitions_end = numbering.find("<w:num ").unwrap_or(numbering.len());
+                let definitions_start = numbering.find("<w:abstractNum ").unwrap_or(definitions_end);
+                let instances_end = numbering.rfind("</w:numbering>").unwrap_or(numbering.len());
+                let close = xml.rfind("</").unwrap_or(xml.len());
+                let first_num = first_num.unwrap_or(close);
+                let merged = format!(
+                    "{}{}{}{}{}",
+                    &xml[..first_num],
+                    &numbering[definitions_start..definitions_end],
+                    &xml[first_num..close],
+                    &numbering[definitions_end..instances_end],
+                    &xml[close..]
+                );
+                replaced.insert(part.clone(), merged);
+            }
+            _ => {
+                let part = resolve_archive_path(&main, "numbering.xml");
+                relationships.push_str(
+                    "<Relationship Id=\"rIdNumbering\" \
+Type=\"http://schemas.openxmlformats.org/officeDocument/2006/relationships/numbering\" Target=\"numbering.xml\"/>",
+                );
+                content_types = content_types.replacen(
+                    "</Types>",
+                    &format!(
+                        "<Override PartName=\"/{}\" \
+ContentType=\"application/vnd.openxmlformats-officedocument.wordprocessingml.numbering+xml\"/></Types>",
+                        part
+                    ),
+                    1,
+                );
+                added.push((part, numbering.into_bytes()));
+            }
+        }
+    }
+
+    // Images take the first file names the original leaves free
+    for (id, name, data) in fresh_media {
+        let extension = ext_lower(&name);
+        let mut file_name = name;
+        let mut number = 1;
+        while original.by_name(&resolve_archive_path(&main, &format!("media/{}", file_name))).is_ok() {
+            file_name = format!("image_{}.{}", number, extension);
+            number += 1;
+        }
+        relationships.push_str(&format!(
+            "<Relationship Id=\"{}\" \
+Type=\"http://schemas.openxmlformats.org/officeDocument/2006/relationships/image\" Target=\"media/{}\"/>",
+            id, file_name
+        ));
+        if !content_types.to_ascii_lowercase().contains(&format!("extension=\"{}\"", extension)) {
+            content_types = content_types.replacen(
+                "</Types>",
+                &format!("<Default Extension=\"{}\" ContentType=\"{}\"/></Types>", extension, image_mime_type(&file_name)),
+                1,
+            );
+        }
+        added.push((resolve_archive_path(&main, &format!("media/{}", file_name)), data));
+    }
+
+    let document = match body_of(&main_xml) {
+        Some((start, end)) => format!("{}{}{}", &main_xml[..start], fresh_body, &main_xml[end..]),
+        None => fresh_document,
+    };
+    replaced.insert(main.clone(), document);
+    if !relationships.is_empty() {
+        let rels = match &rels_xml {
+            Some(rels) => rels.replacen("</Relationships>", &format!("{}</Relationships>", relationships), 1),
+            None => format!(
+                "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\
+<Relationships xmlns=\"http://schemas.openxmlformats.org/package/2006/relationships\">{}</Relationships>",
+                relationships
+            ),
+        };
+        replaced.insert(rels_path.clone(), rels);
+        replaced.insert("[Content_Types].xml".to_string(), content_types);
+    }
+
+    let mut zip = ZipWriter::new(File::create(path)?);
+    let options = entry_options(compression);
+    let mut written = Vec::new();
+    for index in 0..original.len() {
+        let entry = original.by_index_raw(index)?;
+        let name = entry.name().to_string();
+        match replaced.get(&name) {
+            Some(content) => {
+                drop(entry);
+                zip.start_file(name.as_str(), options)?;
+                zip.write_all(content.as_bytes())?;
+            }
+            None => zip.raw_copy_file(entry)?,
+        }
+        written.push(name);
+    }
+    let missing = replaced
+        .iter()
+        .filter(|(name, _)| !written.contains(name))
+        .map(|(name, content)| (name, content.as_bytes()));
+    for (name, content) in missing.chain(added.iter().map(|(name, data)| (name, data.as_slice()))) {
+        zip.start_file(name.as_str(), options)?;
+        zip.write_all(content)?;
+    }
     zip.finish()?;
     Ok(())
 }
 
+/// `w:rPr` for a run style, or an empty string for the default style.
+fn docx_run_properties(style: &TextStyle) -> String {
+    let mut props = String::new();
+    if let Some(font) = &style.font_family {
+        let font = xml_escape(font);
+        props.push_str(&format!("<w:rFonts w:ascii=\"{}\" w:hAnsi=\"{}\"/>", font, font));
+    }
+    if style.bold {
+        props.push_str("<w:b/>");
+    }
+    if style.italic {
+        props.push_str("<w:i/>");
+    }
+    if style.hidden {
+        props.push_str("<w:vanish/>");
+    }
+    if let Some(color) = &style.color {
+        props.push_str(&format!("<w:color w:val=\"{}\"/>", xml_escape(color.trim_start_matches('#'))));
+    }
+    // w:sz is measured in half-points
+    if let Some(points) = style.font_size.as_deref().and_then(|size| size.trim_end_matches("pt").parse::<f32>().ok()) {
+        props.push_str(&format!("<w:sz w:val=\"{}\"/>", (points * 2.0).round()));
+    }
+    // Named highlights where possible, arbitrary colors as shading
+    let named_highlight = style.highlight.as_deref().and_then(|highlight| {
+        DOCX_HIGHLIGHT_COLORS.iter().find(|(_, hex)| hex.eq_ignore_ascii_case(highlight))
+    });
+    if let Some((name, _)) = named_highlight {
+        props.push_str(&format!("<w:highlight w:val=\"{}\"/>", name));
+    }
+    if style.underline {
+        props.push_str("<w:u w:val=\"single\"/>");
+    }
+    if let (Some(highlight), None) = (&style.highlight, named_highlight) {
+        props.push_str(&format!(
+            "<w:shd w:val=\"clear\" w:color=\"auto\" w:fill=\"{}\"/>",
+            xml_escape(highlight.trim_start_matches('#'))
+        ));
+    }
+    if props.is_empty() {
+        props
+    } else {
+        format!("<w:rPr>{}</w:rPr>", props)
+    }
+}
+
+/// `w:shd` filling a paragraph with `background`, or nothing.
+fn docx_shading(background: &Option<String>) -> String {
+    match background {
+        Some(color) => format!(
+            "<w:shd w:val=\"clear\" w:color=\"auto\" w:fill=\"{}\"/>",
+            xml_escape(color.trim_start_matches('#'))
+        ),
+        None => String::new(),
+    }
+}
+
+/// A `w:r` for `run`, turning tabs and newlines into `w:tab`/`w:br`. The
+/// run's bookmarks come first, numbered on from `bookmark_id`.
+fn docx_run(run: &TextRun, bookmark_id: &mut usize) -> String {
+    let mut bookmarks = String::new();
+    for name in &run.bookmarks {
+        bookmarks.push_str(&format!(
+            "<w:bookmarkStart w:id=\"{}\" w:name=\"{}\"/><w:bookmarkEnd w:id=\"{}\"/>",
+            bookmark_id,
+            xml_escape(name),
+            bookmark_id
+        ));
+        *bookmark_id += 1;
+    }
+    let mut content = String::new();
+    for (index, line) in run.text.split('\n').enumerate() {
+        if index > 0 {
+            content.push_str("<w:br/>");
+        }
+        for (index, part) in line.split('\t').enumerate() {
+            if index > 0 {
+                content.push_str("<w:tab/>");
+            }
+            if !part.is_empty() {
+                content.push_str(&format!("<w:t xml:space=\"preserve\">{}</w:t>", xml_escape(part)));
+            }
+        }
+    }
+    format!("{}<w:r>{}{}</w:r>", bookmarks, docx_run_properties(&run.style), content)
+}
+
+/// `w:bidi` for a paragraph with an explicit writing direction.
+fn docx_bidi(direction: &Option<String>) -> &'static str {
+    match direction.as_deref() {
+        Some("rtl") => "<w:bidi/>",
+        Some("ltr") => "<w:bidi w:val=\"0\"/>",
+        _ => "",
+    }
+}
+
+/// A `w:tbl` with single borders, spread over the text width in equal
+/// columns. Row spans become `w:vMerge` continuation cells.
+fn docx_table(rows: &[Vec<TableCell>], bookmark_id: &mut usize) -> String {
+    // Twips across a letter page with one-inch margins
+    const TEXT_WIDTH: usize = 9360;
+    let slots = table_slots(rows);
+    let columns = slots.iter().map(|row| row.len()).max().unwrap_or(0).max(1);
+    let column_width = TEXT_WIDTH / columns;
+    let borders: String = ["top", "left", "bottom", "right", "insideH", "insideV"]
+        .iter()
+        .map(|side| format!("<w:{} w:val=\"single\" w:sz=\"4\" w:space=\"0\" w:color=\"auto\"/>", side))
+        .collect();
+    let mut xml = format!(
+        "<w:tbl><w:tblPr><w:tblW w:w=\"5000\" w:type=\"pct\"/><w:tblBorders>{}</w:tblBorders></w:tblPr><w:tblGrid>{}</w:tblGrid>",
+        borders,
+        format!("<w:gridCol w:w=\"{}\"/>", column_width).repeat(columns)
+    );
+    let cell_properties = |colspan: usize, merge: &str| {
+        let mut properties = format!("<w:tcW w:w=\"{}\" w:type=\"dxa\"/>", column_width * colspan);
+        if colspan > 1 {
+            properties.push_str(&format!("<w:gridSpan w:val=\"{}\"/>", colspan));
+        }
+        properties.push_str(merge);
+        format!("<w:tcPr>{}</w:tcPr>", properties)
+    };
+    for row in slots {
+        xml.push_str("<w:tr>");
+        for slot in row {
+            match slot {
+                GridSlot::Cell(cell) => {
+                    let merge = if cell.rowspan > 1 { "<w:vMerge w:val=\"restart\"/>" } else { "" };
+                    xml.push_str("<w:tc>");
+                    xml.push_str(&cell_properties(cell.colspan.max(1), merge));
+                    xml.push_str("<w:p>");
+                    for run in &cell.runs {
+                        xml.push_str(&docx_run(run, bookmark_id));
+                    }
+                    xml.push_str("</w:p></w:tc>");
+                }
+                GridSlot::RowContinuation(colspan) => {
+                    xml.push_str("<w:tc>");
+                    xml.push_str(&cell_properties(colspan, "<w:vMerge/>"));
+                    xml.push_str("<w:p/></w:tc>");
+                }
+                GridSlot::Covered => {}
+            }
+        }
+        xml.push_str("</w:tr>");
+    }
+    xml.push_str("</w:tbl>");
+    xml
+}
+
+/// The `w:abstractNum` for list `id`: decimal or bullet numbering at every
+/// level, with `display_levels` numbers shown per level.
+fn docx_numbering_definition(id: usize, ordered: bool, display_levels: &[u8]) -> String {
+    let mut xml = format!("<w:abstractNum w:abstractNumId=\"{}\"><w:multiLevelType w:val=\"hybridMultilevel\"/>", id);
+    for level in 0..9usize {
+        let (format, text) = if ordered {
+            let shown = display_levels.get(level).copied().unwrap_or(1).clamp(1, level as u8 + 1) as usize;
+            let text: String = (level + 2 - shown..=level + 1).map(|n| format!("%{}.", n)).collect();
+            ("decimal", text)
+        } else {
+            ("bullet", "\u{2022}".to_string())
+        };
+        xml.push_str(&format!(
+            "<w:lvl w:ilvl=\"{}\"><w:start w:val=\"1\"/><w:numFmt w:val=\"{}\"/><w:lvlText w:val=\"{}\"/>\
+<w:lvlJc w:val=\"left\"/><w:pPr><w:ind w:left=\"{}\" w:hanging=\"360\"/></w:pPr></w:lvl>",
+            level,
+            format,
+            text,
+            720 * (level + 1)
+        ));
+    }
+    xml.push_str("</w:abstractNum>");
+    xml
+}
+
+/// Width and height in pixels of a PNG or GIF image, read from its header.
+fn image_pixel_size(data: &[u8]) -> Option<(u32, u32)> {
+    if data.starts_with(b"\x89PNG\r\n\x1a\n") && data.len() >= 24 {
+        let width = u32::from_be_bytes(data[16..20].try_into().ok()?);
+        let height = u32::from_be_bytes(data[20..24].try_into().ok()?);
+        return Some((width, height));
+    }
+    if data.starts_with(b"GIF8") && data.len() >= 10 {
+        let width = u16::from_le_bytes([data[6], data[7]]);
+        let height = u16::from_le_bytes([data[8], data[9]]);
+        return Some((u32::from(width), u32::from(height)));
+    }
+    None
+}
+
+/// An inline `w:drawing` paragraph showing image relationship `rIdImage{id}`.
+/// Images are placed at 96 DPI, or two inches square when their size is unknown.
+fn docx_drawing(id: usize, name: &str, alt: Option<&str>, data: &[u8]) -> String {
+    const EMU_PER_PIXEL: u64 = 9525;
+    let (width, height) = match image_pixel_size(data).filter(|(w, h)| *w > 0 && *h > 0) {
+        Some((width, height)) => (u64::from(width) * EMU_PER_PIXEL, u64::from(height) * EMU_PER_PIXEL),
+        None => (1828800, 1828800),
+    };
+    let name = xml_escape(name);
+    format!(
+        "<w:p><w:r><w:drawing><wp:inline><wp:extent cx=\"{width}\" cy=\"{height}\"/>\
+<wp:docPr id=\"{id}\" name=\"{name}\" descr=\"{alt}\"/>\
+<a:graphic xmlns:a=\"http://schemas.openxmlformats.org/drawingml/2006/main\">\
+<a:graphicData uri=\"http://schemas.openxmlformats.org/drawingml/2006/picture\">\
+<pic:pic xmlns:pic=\"http://schemas.openxmlformats.org/drawingml/2006/picture\">\
+<pic:nvPicPr><pic:cNvPr id=\"{id}\" name=\"{name}\"/><pic:cNvPicPr/></pic:nvPicPr>\
+<pic:blipFill><a:blip r:embed=\"rIdImage{id}\"/><a:stretch><a:fillRect/></a:stretch></pic:blipFill>\
+<pic:spPr><a:xfrm><a:off x=\"0\" y=\"0\"/><a:ext cx=\"{width}\" cy=\"{height}\"/></a:xfrm>\
+<a:prstGeom prst=\"rect\"><a:avLst/></a:prstGeom></pic:spPr></pic:pic></a:graphicData></a:graphic>\
+</wp:inline></w:drawing></w:r></w:p>",
+        alt = xml_escape(alt.unwrap_or("")),
+    )
+}
+
+/// Writes a structured document as DOCX: headings use `HeadingN` paragraph
+/// styles, lists get numbering definitions, images are stored under
+/// `word/media/` and comments become ranges around the element they are
+/// attached to. Comments with no element are referenced at the end of the body.
+fn write_docx_structured(path: &str, doc: &StructuredDocument, compression: Option<u8>) -> std::io::Result<()> {
+    let mut body = String::new();
+    let mut bookmark_id = 0;
+    let mut numbering = String::new();
+    let mut list_count = 0;
+    let mut media = Vec::new();
+    // Every comment with its replies right after it, as (index of the comment
+    // the thread starts at, id of the comment answered, comment); replies
+    // share the range of the comment they answer
+    let mut thread: Vec<(usize, Option<usize>, &Comment)> = Vec::new();
+    for (root, comment) in doc.comments.iter().enumerate() {
+        let mut pending = vec![(None, comment)];
+        while let Some((parent, comment)) = pending.pop() {
+            let id = thread.len();
+            thread.push((root, parent, comment));
+            pending.extend(comment.replies.iter().rev().map(|reply| (Some(id), reply)));
+        }
+    }
+    for (index, element) in doc.elements.iter().enumerate() {
+        let element_start = body.len();
+        // (paragraph properties, runs) of each w:p the element becomes
+        let paragraphs: Vec<(String, Vec<TextRun>)> = match element {
+            DocumentElement::Paragraph { runs, background, indent, alignment, direction } => {
+                // w:pPr order: w:shd, w:bidi, w:ind, w:jc
+                let mut properties = docx_shading(background);
+                properties.push_str(docx_bidi(direction));
+                if let Some(indent) = indent {
+                    properties.push_str(&format!("<w:ind w:left=\"{}\"/>", (indent * 20.0).round() as i64));
+                }
+                let jc = match alignment.as_deref() {
+                    Some("left" | "start") => Some("start"),
+                    Some("right" | "end") => Some("end"),
+                    Some("center") => Some("center"),
+                    Some("justify") => Some("both"),
+                    _ => None,
+                };
+                if let Some(jc) = jc {
+                    properties.push_str(&format!("<w:jc w:val=\"{}\"/>", jc));
+                }
+                let properties =
+                    if properties.is_empty() { properties } else { format!("<w:pPr>{}</w:pPr>", properties) };
+                vec![(properties, runs.clone())]
+            }
+            DocumentElement::Heading { level, runs, background, direction } => vec![(
+                format!(
+                    "<w:pPr><w:pStyle w:val=\"Heading{}\"/>{}{}</w:pPr>",
+                    level,
+                    docx_shading(background),
+                    docx_bidi(direction)
+                ),
+                runs.clone(),
+            )],
+            DocumentElement::List { items, ordered, levels, display_levels } => {
+                list_count += 1;
+                numbering.push_str(&docx_numbering_definition(list_count, *ordered, display_levels));
+                let mut paragraphs = Vec::new();
+                for (item_index, item) in items.iter().enumerate() {
+                    let level = levels.get(item_index).copied().unwrap_or(0).min(MAX_LIST_LEVEL);
+                    // Only an item's first paragraph carries its number
+                    for (paragraph_index, runs) in item_paragraphs(item).enumerate() {
+                        let numbered = if paragraph_index == 0 {
+                            format!("<w:numPr><w:ilvl w:val=\"{}\"/><w:numId w:val=\"{}\"/></w:numPr>", level, list_count)
+                        } else {
+                            String::new()
+                        };
+                        paragraphs.push((
+                            format!("<w:pPr><w:pStyle w:val=\"ListParagraph\"/>{}</w:pPr>", numbered),
+                            runs.clone(),
+                        ));
+                    }
+                }
+                paragraphs
+            }
+            DocumentElement::Table { rows } => {
+                body.push_str(&docx_table(rows, &mut bookmark_id));
+                Vec::new()
+            }
+            DocumentElement::LineBreak => vec![(String::new(), Vec::new())],
+            DocumentElement::Image { name, mime_type, data, alt } => {
+                let extension = match ext_lower(name) {
+                    extension if !extension.is_empty() => extension,
+                    _ => mime_type.rsplit('/').next().unwrap_or("bin").split('+').next().unwrap_or("bin").to_string(),
+                };
+                media.push((format!("image{}.{}", media.len() + 1, extension), mime_type.as_str(), data.as_slice()));
+                let file_name = name.rsplit('/').next().unwrap_or(name);
+                body.push_str(&docx_drawing(media.len(), file_name, alt.as_deref(), data));
+                Vec::new()
+            }
+        };
+        let comment_ids: Vec<usize> = thread
+            .iter()
+            .enumerate()
+            .filter(|(_, (root, _, _))| doc.comments[*root].element_index == Some(index))
+            .map(|(id, _)| id)
+            .collect();
+        // Elements without paragraphs of their own are enclosed in body-level
+        // range marks, with a paragraph after them for the references
+        if paragraphs.is_empty() && !comment_ids.is_empty() {
+            let starts: String = comment_ids.iter().map(|id| format!("<w:commentRangeStart w:id=\"{}\"/>", id)).collect();
+            body.insert_str(element_start, &starts);
+            for id in &comment_ids {
+                body.push_str(&format!("<w:commentRangeEnd w:id=\"{}\"/>", id));
+            }
+            body.push_str("<w:p>");
+            for id in &comment_ids {
+                body.push_str(&format!("<w:r><w:commentReference w:id=\"{}\"/></w:r>", id));
+            }
+            body.push_str("</w:p>");
+        }
+        let last = paragraphs.len().saturating_sub(1);
+        for (paragraph_index, (properties, runs)) in paragraphs.iter().enumerate() {
+            body.push_str("<w:p>");
+            body.push_str(properties);
+            if paragraph_index == 0 {
+                for id in &comment_ids {
+                    body.push_str(&format!("<w:commentRangeStart w:id=\"{}\"/>", id));
+                }
+            }
+            for run in runs {
+                body.push_str(&docx_run(run, &mut bookmark_id));
+            }
+            if paragraph_index == last {
+                for id in &comment_ids {
+                    body.push_str(&format!(
+                        "<w:commentRangeEnd w:id=\"{}\"/><w:r><w:commentReference w:id=\"{}\"/></w:r>",
+                        id, id
+                    ));
+                }
+            }
+            body.push_str("</w:p>");
+        }
+    }
+
+    let unattached: Vec<usize> = thread
+        .iter()
+        .enumerate()
+        .filter(|(_, (root, _, _))| doc.comments[*root].element_index.is_none_or(|index| index >= doc.elements.len()))
+        .map(|(id, _)| id)
+        .collect();
+    if !unattached.is_empty() {
+        body.push_str("<w:p>");
+        for id in unattached {
+            body.push_str(&format!("<w:r><w:commentReference w:id=\"{}\"/></w:r>", id));
+        }
+        body.push_str("</w:p>");
+    }
+
+    // The last paragraph of each comment carries the paraId that
+    // commentsExtended.xml refers to it by
+    let para_id = |id: usize| format!("{:08X}", id + 1);
+    let comments_xml = (!thread.is_empty()).then(|| {
+        let mut xml = String::from(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\
+<w:comments xmlns:w=\"http://schemas.openxmlformats.org/wordprocessingml/2006/main\" \
+xmlns:w14=\"http://schemas.microsoft.com/office/word/2010/wordml\">",
+        );
+        for (id, (_, _, comment)) in thread.iter().enumerate() {
+            xml.push_str(&format!("<w:comment w:id=\"{}\" w:author=\"{}\">", id, xml_escape(&comment.author)));
+            let mut lines = comment.text.split('\n').peekable();
+            while let Some(line) = lines.next() {
+                let attributes = match lines.peek() {
+                    Some(_) => String::new(),
+                    None => format!(" w14:paraId=\"{}\"", para_id(id)),
+                };
+                xml.push_str(&format!(
+                    "<w:p{}><w:r><w:t xml:space=\"preserve\">{}</w:t></w:r></w:p>",
+                    attributes,
+                    xml_escape(line)
+                ));
+            }
+            xml.push_str("</w:comment>");
+        }
+        xml.push_str("</w:comments>");
+        xml
+    });
+    let comments_extended_xml =
+        thread.iter().any(|(_, parent, comment)| parent.is_some() || comment.resolved.is_some()).then(|| {
+            let mut xml = String::from(
+                "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\
+<w15:commentsEx xmlns:w15=\"http://schemas.microsoft.com/office/word/2012/wordml\">",
+            );
+            for (id, (_, parent, comment)) in thread.iter().enumerate() {
+                if parent.is_none() && comment.resolved.is_none() {
+                    continue;
+                }
+                xml.push_str(&format!("<w15:commentEx w15:paraId=\"{}\"", para_id(id)));
+                if let Some(parent) = parent {
+                    xml.push_str(&format!(" w15:paraIdParent=\"{}\"", para_id(*parent)));
+                }
+                if let Some(resolved) = comment.resolved {
+                    xml.push_str(&format!(" w15:done=\"{}\"", u8::from(resolved)));
+                }
+                xml.push_str("/>");
+            }
+            xml.push_str("</w15:commentsEx>");
+            xml
+        });
+
+    // The default style becomes the document defaults every run inherits
+    let styles_xml = doc.default_style.as_ref().map(|style| {
+        format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\
+<w:styles xmlns:w=\"http://schemas.openxmlformats.org/wordprocessingml/2006/main\">\
+<w:docDefaults><w:rPrDefault>{}</w:rPrDefault></w:docDefaults></w:styles>",
+            docx_run_properties(style)
+        )
+    });
+
+    // Every w:abstractNum has to come before the first w:num
+    let numbering_xml = (list_count > 0).then(|| {
+        let nums: String = (1..=list_count)
+            .map(|id| format!("<w:num w:numId=\"{}\"><w:abstractNumId w:val=\"{}\"/></w:num>", id, id))
+            .collect();
+        format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\
+<w:numbering xmlns:w=\"http://schemas.openxmlformats.org/wordprocessingml/2006/main\">{}{}</w:numbering>",
+            numbering, nums
+        )
+    });
+
+    let parts = DocxParts {
+        comments: comments_xml.as_deref(),
+        comments_extended: comments_extended_xml.as_deref(),
+        styles: styles_xml.as_deref(),
+        numbering: numbering_xml.as_deref(),
+        media,
+    };
+    write_docx_package(path, &body, &parts, compression)
+}
+
 fn read_odt_structured(path: &str) -> std::io::Result<StructuredDocument> {
-    let f = File::open(path)?;
-    let mut zip = ZipArchive::new(f)?;
-    
+    read_odt_structured_with(path, &ReadOptions::default())
+}
+
+fn read_odt_structured_with(path: &str, options: &ReadOptions) -> std::io::Result<StructuredDocument> {
+    read_odt_with(path, options, None)
+}
+
+/// `read_odt_structured_with` that also returns the XML errors skipped in
+/// `lenient` mode.
+fn read_odt_structured_warnings(path: &str, options: &ReadOptions) -> std::io::Result<(StructuredDocument, Vec<String>)> {
+    let mut warnings = Vec::new();
+    let doc = read_odt_archive(&mut ZipArchive::new(File::open(path)?)?, options, None, &mut warnings)?;
+    Ok((doc, warnings))
+}
+
+/// Extracts the plain text of an ODT file without keeping its element tree
+/// or `content.xml` in memory: the XML is streamed from the package, and each
+/// element is rendered and dropped as soon as it is complete.
+fn read_odt_text_streaming(path: &str, options: &ReadOptions) -> std::io::Result<String> {
+    let mut text = String::new();
+    read_odt_with(path, options, Some(&mut text))?;
+    Ok(text)
+}
+
+/// Parses ODT content. With `text_out`, finished elements are flushed there
+/// as plain text instead of accumulating, and images are not loaded.
+fn read_odt_with(path: &str, options: &ReadOptions, text_out: Option<&mut String>) -> std::io::Result<StructuredDocument> {
+    read_odt_archive(&mut ZipArchive::new(File::open(path)?)?, options, text_out, &mut Vec::new())
+}
+
+/// `read_odt_with` for an already opened package. In `lenient` mode XML
+/// errors in the body are pushed to `warnings` and reading carries on.
+fn read_odt_archive<R: Read + std::io::Seek>(
+    zip: &mut ZipArchive<R>,
+    options: &ReadOptions,
+    mut text_out: Option<&mut String>,
+    warnings: &mut Vec<String>,
+) -> std::io::Result<StructuredDocument> {
     // Read styles.xml first to get style definitions
     let mut styles = HashMap::new();
+    let mut list_styles = HashMap::new();
+    let mut language = None;
     if let Ok(mut styles_file) = zip.by_name("styles.xml") {
         let styles_xml = read_zip_file_to_string(&mut styles_file)?;
-        styles = parse_odt_styles(&styles_xml);
+        styles = parse_odt_style_props(Reader::from_str(&styles_xml));
+        list_styles = parse_odt_list_styles(Reader::from_str(&styles_xml));
+        language = parse_language(&styles_xml, b"text-properties");
     }
     
-    // Read content.xml
-    let mut content = zip.by_name("content.xml")?;
-    let xml = read_zip_file_to_string(&mut content)?;
+    // Read content.xml. Plain text never needs the whole file at once: it is
+    // streamed from the package, once for the styles and once for the body
+    let xml = match text_out {
+        Some(_) => None,
+        None => Some(read_zip_file_to_string(&mut zip.by_name("content.xml")?)?),
+    };
+    // Styles live in both files; automatic ones in content.xml win and may
+    // inherit from the shared ones in styles.xml
+    styles.extend(parse_odt_style_props(odt_content_reader(zip, xml.as_deref())?));
+    list_styles.extend(parse_odt_list_styles(odt_content_reader(zip, xml.as_deref())?));
     
     let mut doc = StructuredDocument::new();
-    doc.styles = styles;
+    doc.language = language;
+    let styles = resolve_odt_inheritance(&styles);
+    doc.styles = resolve_odt_styles(&styles);
+    if let Some(xml) = &xml {
+        (doc.comments, doc.footnotes) = parse_odt_annotations(xml);
+    }
     
-    let mut reader = Reader::from_str(&xml);
-    reader.trim_text(true);
+    let mut reader = odt_content_reader(zip, xml.as_deref())?;
+    reader.trim_text(!options.preserve_whitespace);
+    // A stray end tag is the commonest damage; let it close whatever is open
+    reader.check_end_names(!options.lenient);
     let mut buf = Vec::new();
     
     let mut in_body = false;
-    let mut current_element: Option<DocumentElement> = None;
+    // Whether the paragraph being read is marked xml:space="preserve"
+    let mut space_preserved = false;
+    let mut last_error_position = None;
     let mut current_runs: Vec<TextRun> = Vec::new();
     let mut current_style = TextStyle::default();
+    let mut current_lang: Option<String> = None;
     let mut text_buffer = String::new();
     let mut element_stack: Vec<String> = Vec::new();
-    let mut table_rows: Vec<Vec<Vec<TextRun>>> = Vec::new();
-    let mut current_row: Vec<Vec<TextRun>> = Vec::new();
-    let mut list_items: Vec<Vec<TextRun>> = Vec::new();
+    let mut table_rows: Vec<Vec<TableCell>> = Vec::new();
+    let mut current_row: Vec<TableCell> = Vec::new();
+    let mut cell_span = (1, 1);
+    let mut list_items: Vec<Vec<DocumentElement>> = Vec::new();
+    // Paragraphs of the list item being read
+    let mut list_item: Vec<DocumentElement> = Vec::new();
+    let mut list_levels: Vec<u8> = Vec::new();
+    let mut list_style = OdtListStyle::default();
+    // svg:title/svg:desc inside a draw:frame hold the image's alt text
+    let mut image_alt: Option<String> = None;
+    // Annotations and notes are read separately and kept out of the body text,
+    // as are embedded objects (charts, spreadsheets) unless asked for
+    let mut skip_depth = 0usize;
+    let skipped = |name: &[u8]| match name {
+        b"annotation" | b"note" => true,
+        b"object" => !options.include_embedded,
+        _ => false,
+    };
+    let mut paragraph_background: Option<String> = None;
+    let mut paragraph_props = OdtStyleProps::default();
+    let mut current_link: Option<Link> = None;
+    let mut heading_level = 1u8;
+    // Whether the current paragraph holds a frame, whose image stands in for it
+    let mut paragraph_has_frame = false;
+    // Images anchored after text in a paragraph, placed once the paragraph is
+    let mut pending_images: Vec<DocumentElement> = Vec::new();
     
     loop {
         match reader.read_event_into(&mut buf) {
+            Ok(Event::Start(e)) if skipped(local_name(e.name().as_ref())) => skip_depth += 1,
+            Ok(Event::End(e)) if skipped(local_name(e.name().as_ref())) => skip_depth = skip_depth.saturating_sub(1),
+            _ if skip_depth > 0 => {}
             Ok(Event::Start(e)) => {
                 let element_name = e.name();
                 let name = String::from_utf8_lossy(local_name(element_name.as_ref()));
                 element_stack.push(name.to_string());
+                if name == "p" || name == "h" {
+                    space_preserved = xml_space_preserved(&e);
+                }
                 
+                let in_cell = element_stack
+                    .iter()
+                    .rev()
+                    .skip(1)
+                    .any(|n| n == "table-cell" || n == "covered-table-cell" || n == "list-item");
+                let list_depth = element_stack.iter().filter(|n| *n == "list").count();
                 match name.as_ref() {
                     "body" => in_body = true,
-                    "p" => {
-                        current_runs.clear();
+                    // Paragraphs within a cell are joined with line breaks
+                    "p" if in_cell => {
+                        if !current_runs.is_empty() {
+                            current_runs.push(TextRun {
+                                text: "\n".to_string(),
+                                style: TextStyle::default(),
+                                link: None,
+                                lang: None,
+                                bookmarks: Vec::new(),
+                            });
+                        }
                         current_style = TextStyle::default();
-                        text_buffer.clear();
+                        current_lang = None;
                     }
-                    "h" => {
+                    "p" | "h" => {
                         current_runs.clear();
-                        current_style = TextStyle::default();
                         text_buffer.clear();
+                        // Automatic paragraph styles often carry the text formatting
+                        paragraph_props = attr_value(&e, b"style-name")
+                            .and_then(|name| styles.get(&name))
+                            .cloned()
+                            .unwrap_or_default();
+                        current_style = paragraph_props.to_text_style();
+                        current_lang = paragraph_props.language.clone();
+                        paragraph_background = paragraph_props.background.clone();
+                        heading_level = odt_heading_level(&e);
+                        paragraph_has_frame = false;
+                    }
+                    "frame" => paragraph_has_frame = true,
+                    "a" => {
+                        if !text_buffer.is_empty() {
+                            current_runs.push(TextRun {
+                                text: std::mem::take(&mut text_buffer),
+                                style: current_style.clone(),
+                                link: None,
+                                lang: current_lang.clone(),
+                                bookmarks: Vec::new(),
+                            });
+                        }
+                        current_link = attr_value(&e, b"href").map(|href| Link {
+                            href,
+                            title: attr_value(&e, b"title"),
+                        });
                     }
                     "span" => {
-                        // Check for style attributes
-                        for attr in e.attributes() {
-                            if let Ok(attr) = attr {
-                                if attr.key.as_ref() == b"text:style-name" {
-                                    let style_str = String::from_utf8_lossy(&attr.value);
-                                    if let Some(style) = doc.styles.get(&style_str.to_string()) {
-                                        current_style = style.clone();
-                                    }
-                                }
-                            }
+                        // Text before the span keeps the style it was written in
+                        if !text_buffer.is_empty() {
+                            current_runs.push(TextRun {
+                                text: std::mem::take(&mut text_buffer),
+                                style: current_style.clone(),
+                                link: current_link.clone(),
+                                lang: current_lang.clone(),
+                                bookmarks: Vec::new(),
+                            });
+                        }
+                        // Span formatting layers over the paragraph's
+                        if let Some(props) = attr_value(&e, b"style-name").and_then(|name| styles.get(&name)) {
+                            let mut props = props.clone();
+                            props.inherit(&paragraph_props);
+                            current_style = props.to_text_style();
+                            current_lang = props.language;
+                        } else if let Some(style) = attr_value(&e, b"style-name").and_then(|name| doc.styles.get(&name)) {
+                            current_style = style.clone();
                         }
                     }
-                    "list" => {
+                    "list" if list_depth == 1 => {
                         list_items.clear();
+                        list_levels.clear();
+                        list_style = attr_value(&e, b"style-name")
+                            .and_then(|name| list_styles.get(&name).cloned())
+                            .unwrap_or_default();
+                    }
+                    // A nested list ends the text of the item that contains it
+                    "list" => {
+                        if !text_buffer.is_empty() {
+                            current_runs.push(TextRun {
+                                text: std::mem::take(&mut text_buffer),
+                                style: current_style.clone(),
+                                link: current_link.clone(),
+                                lang: current_lang.clone(),
+                                bookmarks: Vec::new(),
+                            });
+                        }
+                        if !current_runs.is_empty() {
+                            list_item.push(DocumentElement::Paragraph {
+                                runs: std::mem::take(&mut current_runs),
+                                background: None,
+                                indent: None,
+                                alignment: None,
+                                direction: None,
+                            });
+                        }
+                        if !list_item.is_empty() {
+                            list_items.push(std::mem::take(&mut list_item));
+                            list_levels.push((list_depth - 2) as u8);
+                        }
                     }
                     "list-item" => {
                         current_runs.clear();
+                        list_item.clear();
                     }
                     "table" => {
                         table_rows.clear();
@@ -370,8 +6254,14 @@ fn read_odt_structured(path: &str) -> std::io::Result<StructuredDocument> {
                     "table-row" => {
                         current_row.clear();
                     }
-                    "table-cell" => {
+                    "table-cell" | "covered-table-cell" => {
                         current_runs.clear();
+                        text_buffer.clear();
+                        cell_span = odt_cell_span(&e);
+                    }
+                    // Only svg:title/svg:desc; other vocabularies reuse the names
+                    "title" | "desc" if in_body && element_name.as_ref().starts_with(b"svg:") => {
+                        image_alt = Some(String::new());
                     }
                     _ => {}
                 }
@@ -380,24 +6270,75 @@ fn read_odt_structured(path: &str) -> std::io::Result<StructuredDocument> {
                 let element_name = e.name();
                 let name = String::from_utf8_lossy(local_name(element_name.as_ref()));
                 element_stack.pop();
+                if name == "p" || name == "h" {
+                    space_preserved = false;
+                }
                 
                 if !in_body {
                     buf.clear();
                     continue;
                 }
                 
+                let container = element_stack
+                    .iter()
+                    .rev()
+                    .find(|n| *n == "table-cell" || *n == "covered-table-cell" || *n == "list-item");
+                let in_cell = container.is_some();
+                let list_depth = element_stack.iter().filter(|n| *n == "list").count();
                 match name.as_ref() {
+                    // Each paragraph of a list item is kept as its own element
+                    "p" if container.is_some_and(|n| n == "list-item") => {
+                        if !text_buffer.is_empty() {
+                            current_runs.push(TextRun {
+                                text: std::mem::take(&mut text_buffer),
+                                style: current_style.clone(),
+                                link: current_link.clone(),
+                                lang: current_lang.clone(),
+                                bookmarks: Vec::new(),
+                            });
+                        }
+                        if !current_runs.is_empty() {
+                            list_item.push(DocumentElement::Paragraph {
+                                runs: std::mem::take(&mut current_runs),
+                                background: None,
+                                indent: None,
+                                alignment: None,
+                                direction: None,
+                            });
+                        }
+                    }
+                    "p" if in_cell && !text_buffer.is_empty() => {
+                        current_runs.push(TextRun {
+                            text: std::mem::take(&mut text_buffer),
+                            style: current_style.clone(),
+                            link: current_link.clone(),
+                            lang: current_lang.clone(),
+                            bookmarks: Vec::new(),
+                        });
+                    }
+                    "p" if in_cell => {}
                     "p" => {
                         if !text_buffer.is_empty() {
                             current_runs.push(TextRun {
                                 text: text_buffer.clone(),
                                 style: current_style.clone(),
+                                link: current_link.clone(),
+                                lang: current_lang.clone(),
+                                bookmarks: Vec::new(),
                             });
                             text_buffer.clear();
                         }
-                        if !current_runs.is_empty() {
-                            doc.elements.push(DocumentElement::Paragraph { runs: current_runs.clone() });
+                        // Empty paragraphs are kept, they are often there for spacing
+                        if !current_runs.is_empty() || !paragraph_has_frame {
+                            doc.elements.push(DocumentElement::Paragraph {
+                                runs: current_runs.clone(),
+                                background: paragraph_background.take(),
+                                indent: paragraph_props.margin_left,
+                                alignment: paragraph_props.alignment.clone(),
+                                direction: paragraph_props.direction.clone(),
+                            });
                         }
+                        doc.elements.append(&mut pending_images);
                         current_runs.clear();
                     }
                     "h" => {
@@ -405,14 +6346,21 @@ fn read_odt_structured(path: &str) -> std::io::Result<StructuredDocument> {
                             current_runs.push(TextRun {
                                 text: text_buffer.clone(),
                                 style: current_style.clone(),
+                                link: current_link.clone(),
+                                lang: current_lang.clone(),
+                                bookmarks: Vec::new(),
                             });
                             text_buffer.clear();
                         }
                         if !current_runs.is_empty() {
-                            // Try to get heading level from outline-level attribute
-                            let level = 1; // Default level, could be enhanced to parse from XML
-                            doc.elements.push(DocumentElement::Heading { level, runs: current_runs.clone() });
+                            doc.elements.push(DocumentElement::Heading {
+                                level: heading_level,
+                                runs: current_runs.clone(),
+                                background: paragraph_background.take(),
+                                direction: paragraph_props.direction.clone(),
+                            });
                         }
+                        doc.elements.append(&mut pending_images);
                         current_runs.clear();
                     }
                     "span" => {
@@ -420,38 +6368,82 @@ fn read_odt_structured(path: &str) -> std::io::Result<StructuredDocument> {
                             current_runs.push(TextRun {
                                 text: text_buffer.clone(),
                                 style: current_style.clone(),
+                                link: current_link.clone(),
+                                lang: current_lang.clone(),
+                                bookmarks: Vec::new(),
                             });
                             text_buffer.clear();
                         }
-                        current_style = TextStyle::default();
+                        current_style = paragraph_props.to_text_style();
+                        current_lang = paragraph_props.language.clone();
+                    }
+                    "a" => {
+                        if !text_buffer.is_empty() {
+                            current_runs.push(TextRun {
+                                text: std::mem::take(&mut text_buffer),
+                                style: current_style.clone(),
+                                link: current_link.clone(),
+                                lang: current_lang.clone(),
+                                bookmarks: Vec::new(),
+                            });
+                        }
+                        current_link = None;
                     }
                     "list-item" => {
                         if !text_buffer.is_empty() {
                             current_runs.push(TextRun {
                                 text: text_buffer.clone(),
                                 style: current_style.clone(),
+                                link: current_link.clone(),
+                                lang: current_lang.clone(),
+                                bookmarks: Vec::new(),
                             });
                             text_buffer.clear();
                         }
-                        list_items.push(current_runs.clone());
-                        current_runs.clear();
-                    }
-                    "list" => {
-                        if !list_items.is_empty() {
-                            doc.elements.push(DocumentElement::List { items: list_items.clone(), ordered: false });
+                        if !current_runs.is_empty() {
+                            list_item.push(DocumentElement::Paragraph {
+                                runs: std::mem::take(&mut current_runs),
+                                background: None,
+                                indent: None,
+                                alignment: None,
+                                direction: None,
+                            });
                         }
-                        list_items.clear();
+                        if !list_item.is_empty() {
+                            list_items.push(std::mem::take(&mut list_item));
+                            list_levels.push(list_depth.saturating_sub(1) as u8);
+                        }
+                    }
+                    "list" if list_depth == 0 && !list_items.is_empty() => {
+                        doc.elements.push(DocumentElement::List {
+                            items: std::mem::take(&mut list_items),
+                            ordered: list_style.ordered,
+                            levels: std::mem::take(&mut list_levels),
+                            display_levels: list_style.display_levels.clone(),
+                        });
                     }
                     "table-cell" => {
                         if !text_buffer.is_empty() {
                             current_runs.push(TextRun {
                                 text: text_buffer.clone(),
                                 style: current_style.clone(),
+                                link: current_link.clone(),
+                                lang: current_lang.clone(),
+                                bookmarks: Vec::new(),
                             });
                             text_buffer.clear();
                         }
-                        current_row.push(current_runs.clone());
+                        let (colspan, rowspan) = cell_span;
+                        current_row.push(TableCell {
+                            runs: std::mem::take(&mut current_runs),
+                            colspan,
+                            rowspan,
+                        });
+                    }
+                    // Cells hidden under a spanning cell carry no content of their own
+                    "covered-table-cell" => {
                         current_runs.clear();
+                        text_buffer.clear();
                     }
                     "table-row" => {
                         table_rows.push(current_row.clone());
@@ -463,91 +6455,514 @@ fn read_odt_structured(path: &str) -> std::io::Result<StructuredDocument> {
                         }
                         table_rows.clear();
                     }
-                    "line-break" => {
-                        doc.elements.push(DocumentElement::LineBreak);
+                    "line-break" => text_buffer.push('\n'),
+                    "title" | "desc" if element_name.as_ref().starts_with(b"svg:") => {
+                        let alt = image_alt.take().filter(|alt| !alt.is_empty());
+                        let image = pending_images.last_mut().or_else(|| doc.elements.last_mut());
+                        if let Some(DocumentElement::Image { alt: image_alt @ None, .. }) = image {
+                            *image_alt = alt;
+                        }
                     }
                     _ => {}
                 }
             }
-            Ok(Event::Text(t)) => {
-                if in_body {
-                    match t.unescape() {
-                        Ok(cow) => text_buffer.push_str(&cow),
-                        Err(_) => text_buffer.push_str(&String::from_utf8_lossy(t.as_ref())),
+            Ok(Event::Empty(e)) if in_body && text_out.is_none() && local_name(e.name().as_ref()) == b"image" => {
+                for attr in e.attributes().flatten() {
+                    if attr.key.as_ref() == b"xlink:href" {
+                        let href = String::from_utf8_lossy(&attr.value).to_string();
+                        // The data is read once the body is done with the package
+                        let image = DocumentElement::Image {
+                            mime_type: image_mime_type(&href).to_string(),
+                            name: href,
+                            data: Vec::new(),
+                            alt: None,
+                        };
+                        let in_container = element_stack
+                            .iter()
+                            .any(|n| n == "table-cell" || n == "covered-table-cell" || n == "list-item");
+                        if !in_container && (!current_runs.is_empty() || !text_buffer.is_empty()) {
+                            pending_images.push(image);
+                        } else {
+                            doc.elements.push(image);
+                        }
                     }
                 }
             }
-            Ok(Event::CData(t)) => {
-                if in_body {
-                    let txt = String::from_utf8_lossy(t.as_ref());
-                    text_buffer.push_str(&txt);
+            Ok(Event::Empty(e))
+                if in_body && matches!(local_name(e.name().as_ref()), b"bookmark" | b"bookmark-start") =>
+            {
+                if let Some(name) = attr_value(&e, b"name") {
+                    if !text_buffer.is_empty() {
+                        current_runs.push(TextRun {
+                            text: std::mem::take(&mut text_buffer),
+                            style: current_style.clone(),
+                            link: current_link.clone(),
+                            lang: current_lang.clone(),
+                            bookmarks: Vec::new(),
+                        });
+                    }
+                    // An empty run marks the spot until the text after it is known
+                    current_runs.push(TextRun { bookmarks: vec![name], ..TextRun::plain("") });
+                }
+            }
+            // Tabs, runs of spaces and line breaks within a paragraph are elements in ODT
+            Ok(Event::Empty(e)) if in_body && matches!(local_name(e.name().as_ref()), b"tab" | b"s" | b"line-break") => {
+                text_buffer.push_str(&odt_whitespace(&e));
+            }
+            Ok(Event::Empty(e))
+                if in_body
+                    && local_name(e.name().as_ref()) == b"p"
+                    && !element_stack
+                        .iter()
+                        .any(|n| n == "table-cell" || n == "covered-table-cell" || n == "list-item") =>
+            {
+                let props = attr_value(&e, b"style-name").and_then(|name| styles.get(&name));
+                doc.elements.push(DocumentElement::Paragraph {
+                    runs: Vec::new(),
+                    background: props.and_then(|props| props.background.clone()),
+                    indent: props.and_then(|props| props.margin_left),
+                    alignment: props.and_then(|props| props.alignment.clone()),
+                    direction: props.and_then(|props| props.direction.clone()),
+                });
+            }
+            Ok(Event::Empty(e)) if in_body && local_name(e.name().as_ref()) == b"table-cell" => {
+                let (colspan, rowspan) = odt_cell_span(&e);
+                current_row.push(TableCell {
+                    runs: Vec::new(),
+                    colspan,
+                    rowspan,
+                });
+            }
+            Ok(Event::Text(t)) if image_alt.is_some() => {
+                if let (Some(alt), Ok(cow)) = (image_alt.as_mut(), t.unescape()) {
+                    alt.push_str(&cow);
+                }
+            }
+            Ok(Event::Text(t)) if in_body => {
+                let text = match t.unescape() {
+                    Ok(cow) => cow,
+                    Err(_) => Cow::Owned(String::from_utf8_lossy(t.as_ref()).into_owned()),
+                };
+                if !options.preserve_whitespace || space_preserved {
+                    text_buffer.push_str(&text);
+                } else if element_stack.iter().any(|n| n == "p" || n == "h") {
+                    // Whitespace between elements outside paragraphs is only layout
+                    text_buffer.push_str(&collapse_whitespace(&text));
                 }
             }
+            Ok(Event::CData(t)) if in_body => {
+                let txt = String::from_utf8_lossy(t.as_ref());
+                text_buffer.push_str(&txt);
+            }
             Ok(Event::Eof) => break,
-            Err(e) => return Err(std::io::Error::new(std::io::ErrorKind::Other, e.to_string())),
+            Err(e) if options.lenient => {
+                let position = reader.buffer_position();
+                warnings.push(format!("content.xml at byte {}: {}", position, e));
+                // An error the reader can't get past would repeat forever
+                if last_error_position == Some(position) {
+                    break;
+                }
+                last_error_position = Some(position);
+            }
+            Err(e) => return Err(std::io::Error::other(e.to_string())),
             _ => {}
         }
         buf.clear();
+        if let Some(out) = text_out.as_deref_mut() {
+            flush_odt_text(&mut doc, options, out);
+        }
     }
     
+    // Image data is read now that the body is done with the package; images
+    // missing from it are left out
+    drop(reader);
+    doc.elements.retain_mut(|element| match element {
+        DocumentElement::Image { name, data, .. } if data.is_empty() => match read_zip_image(zip, name) {
+            Some(DocumentElement::Image { data: read, .. }) => {
+                *data = read;
+                true
+            }
+            _ => false,
+        },
+        _ => true,
+    });
+    doc.settle_bookmarks();
+    if !options.include_hidden {
+        doc.strip_hidden_runs();
+    }
+    if !options.raw_link_style {
+        doc.style_links();
+    }
+
     Ok(doc)
 }
 
-fn parse_odt_styles(styles_xml: &str) -> HashMap<String, TextStyle> {
+/// A reader over an ODT package's `content.xml`: `xml` when it has already
+/// been read, or else streamed from the package.
+fn odt_content_reader<'a, R: Read + std::io::Seek>(
+    zip: &'a mut ZipArchive<R>,
+    xml: Option<&'a str>,
+) -> std::io::Result<Reader<Box<dyn BufRead + 'a>>> {
+    let source: Box<dyn BufRead + 'a> = match xml {
+        Some(xml) => Box::new(xml.as_bytes()),
+        None => Box::new(BufReader::new(zip.by_name("content.xml")?)),
+    };
+    Ok(Reader::from_reader(source))
+}
+
+/// Moves the finished elements of a streaming read into `out` as plain text.
+fn flush_odt_text(doc: &mut StructuredDocument, options: &ReadOptions, out: &mut String) {
+    if doc.elements.is_empty() {
+        return;
+    }
+    if !options.include_hidden {
+        doc.strip_hidden_runs();
+    }
+    out.push_str(&doc.to_plain_text());
+    doc.elements.clear();
+}
+
+/// Collects `office:annotation` comments and `text:note` footnotes from ODT
+/// content. A named annotation's anchor is the text up to its matching
+/// `office:annotation-end`.
+fn parse_odt_annotations(xml: &str) -> (Vec<Comment>, Vec<String>) {
+    let mut comments: Vec<Comment> = Vec::new();
+    let mut footnotes = Vec::new();
+    let mut reader = Reader::from_str(xml);
+    reader.trim_text(true);
+    let mut buf = Vec::new();
+
+    let mut comment: Option<(Option<String>, Comment)> = None;
+    let mut footnote: Option<String> = None;
+    let mut in_creator = false;
+    let mut paragraph_depth = 0usize;
+    // Paragraph depth outside the current annotation, which may itself sit in a paragraph
+    let mut annotation_depth = 0usize;
+    let mut in_citation = false;
+    // Comment index and collected text for each open annotation range, by name
+    let mut open_ranges: HashMap<String, (usize, String)> = HashMap::new();
+
+    loop {
+        match reader.read_event_into(&mut buf) {
+            Ok(Event::Start(e)) => match local_name(e.name().as_ref()) {
+                b"annotation" => {
+                    annotation_depth = paragraph_depth;
+                    let empty = Comment {
+                        author: String::new(),
+                        text: String::new(),
+                        anchor: None,
+                        element_index: None,
+                        replies: Vec::new(),
+                        resolved: None,
+                    };
+                    comment = Some((attr_value(&e, b"name"), empty));
+                }
+                b"note" => {
+                    let class = attr_value(&e, b"note-class");
+                    footnote = class.is_none_or(|class| class == "footnote").then(String::new);
+                }
+                b"creator" => in_creator = true,
+                b"note-citation" => in_citation = true,
+                b"p" | b"h" => {
+                    paragraph_depth += 1;
+                    let text = match (comment.as_mut(), footnote.as_mut()) {
+                        (Some((_, comment)), _) => Some(&mut comment.text),
+                        (None, Some(note)) => Some(note),
+                        _ => None,
+                    };
+                    if let Some(text) = text.filter(|text| !text.is_empty()) {
+                        text.push('\n');
+                    }
+                }
+                _ => {}
+            },
+            Ok(Event::Empty(e)) if local_name(e.name().as_ref()) == b"annotation-end" => {
+                if let Some((index, text)) = attr_value(&e, b"name").and_then(|name| open_ranges.remove(&name)) {
+                    comments[index].anchor = Some(text);
+                }
+            }
+            Ok(Event::End(e)) => match local_name(e.name().as_ref()) {
+                b"annotation" => {
+                    if let Some((name, comment)) = comment.take() {
+                        if let Some(name) = name {
+                            open_ranges.insert(name, (comments.len(), String::new()));
+                        }
+                        comments.push(comment);
+                    }
+                }
+                b"note" => footnotes.extend(footnote.take()),
+                b"creator" => in_creator = false,
+                b"note-citation" => in_citation = false,
+                b"p" | b"h" => paragraph_depth = paragraph_depth.saturating_sub(1),
+                _ => {}
+            },
+            Ok(Event::Text(t)) => {
+                let Ok(text) = t.unescape() else {
+                    buf.clear();
+                    continue;
+                };
+                if let Some((_, comment)) = comment.as_mut() {
+                    if in_creator {
+                        comment.author.push_str(&text);
+                    } else if paragraph_depth > annotation_depth {
+                        comment.text.push_str(&text);
+                    }
+                } else if let Some(note) = footnote.as_mut() {
+                    if !in_citation {
+                        note.push_str(&text);
+                    }
+                } else {
+                    for (_, range) in open_ranges.values_mut() {
+                        range.push_str(&text);
+                    }
+                }
+            }
+            Ok(Event::Eof) | Err(_) => break,
+            _ => {}
+        }
+        buf.clear();
+    }
+
+    (comments, footnotes)
+}
+
+/// Numbering settings of an ODT `text:list-style`.
+#[derive(Debug, Clone, Default)]
+struct OdtListStyle {
+    /// Whether the top level is numbered rather than bulleted.
+    ordered: bool,
+    /// `text:display-levels` for each level, top level first.
+    display_levels: Vec<u8>,
+}
+
+fn parse_odt_list_styles<R: BufRead>(mut reader: Reader<R>) -> HashMap<String, OdtListStyle> {
+    let mut list_styles = HashMap::new();
+    reader.trim_text(true);
+    let mut buf = Vec::new();
+    let mut current: Option<(String, OdtListStyle)> = None;
+
+    loop {
+        match reader.read_event_into(&mut buf) {
+            Ok(Event::Start(e)) if local_name(e.name().as_ref()) == b"list-style" => {
+                current = attr_value(&e, b"name").map(|name| (name, OdtListStyle::default()));
+            }
+            Ok(Event::Start(e)) | Ok(Event::Empty(e)) => {
+                let numbered = match local_name(e.name().as_ref()) {
+                    b"list-level-style-number" => true,
+                    b"list-level-style-bullet" | b"list-level-style-image" => false,
+                    _ => {
+                        buf.clear();
+                        continue;
+                    }
+                };
+                if let Some((_, style)) = current.as_mut() {
+                    let level: usize = attr_value(&e, b"level").and_then(|v| v.parse().ok()).unwrap_or(1);
+                    let display = attr_value(&e, b"display-levels").and_then(|v| v.parse().ok()).unwrap_or(1);
+                    if level == 1 {
+                        style.ordered = numbered;
+                    }
+                    if level >= 1 {
+                        if style.display_levels.len() < level {
+                            style.display_levels.resize(level, 1);
+                        }
+                        style.display_levels[level - 1] = if numbered { display } else { 1 };
+                    }
+                }
+            }
+            Ok(Event::End(e)) if local_name(e.name().as_ref()) == b"list-style" => {
+                if let Some((name, style)) = current.take() {
+                    list_styles.insert(name, style);
+                }
+            }
+            Ok(Event::Eof) | Err(_) => break,
+            _ => {}
+        }
+        buf.clear();
+    }
+
+    list_styles
+}
+
+/// Most spaces a single `text:s` may stand for; real documents use a handful.
+const MAX_ODT_SPACES: usize = 1000;
+
+/// The text a `text:tab`, `text:s` (`text:c` spaces) or `text:line-break` stands for.
+fn odt_whitespace(e: &BytesStart) -> String {
+    match local_name(e.name().as_ref()) {
+        b"tab" => "\t".to_string(),
+        b"s" => " ".repeat(attr_value(e, b"c").and_then(|c| c.parse().ok()).unwrap_or(1).min(MAX_ODT_SPACES)),
+        b"line-break" => "\n".to_string(),
+        _ => String::new(),
+    }
+}
+
+/// A `text:h`'s `text:outline-level`, defaulting to 1.
+fn odt_heading_level(e: &BytesStart) -> u8 {
+    attr_value(e, b"outline-level").and_then(|v| v.parse().ok()).unwrap_or(1)
+}
+
+/// Reads `table:number-columns-spanned` / `table:number-rows-spanned` from a cell.
+fn odt_cell_span(e: &BytesStart) -> (usize, usize) {
+    let span = |attr: &[u8]| parse_span(attr_value(e, attr).as_deref());
+    (span(b"number-columns-spanned"), span(b"number-rows-spanned"))
+}
+
+/// Text properties set directly on an ODT style; `None` means "inherit".
+#[derive(Debug, Clone, Default)]
+struct OdtStyleProps {
+    parent: Option<String>,
+    bold: Option<bool>,
+    italic: Option<bool>,
+    underline: Option<bool>,
+    hidden: Option<bool>,
+    font_size: Option<String>,
+    font_family: Option<String>,
+    color: Option<String>,
+    highlight: Option<String>,
+    /// Paragraph shading from `style:paragraph-properties`.
+    background: Option<String>,
+    /// Paragraph `fo:margin-left` in points.
+    margin_left: Option<f64>,
+    /// BCP 47 tag from `style:text-properties`.
+    language: Option<String>,
+    /// `"rtl"` or `"ltr"` from the paragraph's `style:writing-mode`.
+    direction: Option<String>,
+    /// Paragraph `fo:text-align`.
+    alignment: Option<String>,
+}
+
+impl OdtStyleProps {
+    /// Fills every property this style leaves unset from `parent`.
+    fn inherit(&mut self, parent: &OdtStyleProps) {
+        self.bold = self.bold.or(parent.bold);
+        self.italic = self.italic.or(parent.italic);
+        self.underline = self.underline.or(parent.underline);
+        self.hidden = self.hidden.or(parent.hidden);
+        self.font_size = self.font_size.take().or_else(|| parent.font_size.clone());
+        self.font_family = self.font_family.take().or_else(|| parent.font_family.clone());
+        self.color = self.color.take().or_else(|| parent.color.clone());
+        self.highlight = self.highlight.take().or_else(|| parent.highlight.clone());
+        self.background = self.background.take().or_else(|| parent.background.clone());
+        self.margin_left = self.margin_left.or(parent.margin_left);
+        self.language = self.language.take().or_else(|| parent.language.clone());
+        self.direction = self.direction.take().or_else(|| parent.direction.clone());
+        self.alignment = self.alignment.take().or_else(|| parent.alignment.clone());
+    }
+
+    fn to_text_style(&self) -> TextStyle {
+        TextStyle {
+            bold: self.bold.unwrap_or(false),
+            italic: self.italic.unwrap_or(false),
+            underline: self.underline.unwrap_or(false),
+            font_size: self.font_size.clone(),
+            font_family: self.font_family.clone(),
+            color: self.color.clone(),
+            hidden: self.hidden.unwrap_or(false),
+            highlight: self.highlight.clone(),
+        }
+    }
+}
+
+/// Converts an ODF length such as `1.27cm` or `0.5in` to points.
+fn length_to_points(value: &str) -> Option<f64> {
+    let value = value.trim();
+    let split = value.find(|c: char| c.is_ascii_alphabetic()).unwrap_or(value.len());
+    let number: f64 = value[..split].parse().ok()?;
+    let per_unit = match &value[split..] {
+        "pt" | "" => 1.0,
+        "in" => 72.0,
+        "cm" => 72.0 / 2.54,
+        "mm" => 72.0 / 25.4,
+        "pc" => 12.0,
+        "px" => 0.75,
+        _ => return None,
+    };
+    Some(number * per_unit)
+}
+
+/// Reads the `style:style` definitions of an ODT part without resolving inheritance.
+fn parse_odt_style_props<R: BufRead>(mut reader: Reader<R>) -> HashMap<String, OdtStyleProps> {
     let mut styles = HashMap::new();
-    let mut reader = Reader::from_str(styles_xml);
     reader.trim_text(true);
     let mut buf = Vec::new();
     
     let mut current_style_name = String::new();
-    let mut current_style = TextStyle::default();
+    let mut current_style = OdtStyleProps::default();
     let mut in_style = false;
     
     loop {
-        match reader.read_event_into(&mut buf) {
-            Ok(Event::Start(e)) => {
+        let event = reader.read_event_into(&mut buf);
+        let is_empty = matches!(event, Ok(Event::Empty(_)));
+        match event {
+            // style:text-properties is normally an empty element
+            Ok(Event::Start(e)) | Ok(Event::Empty(e)) => {
                 let element_name = e.name();
                 let name = String::from_utf8_lossy(local_name(element_name.as_ref()));
                 match name.as_ref() {
                     "style" => {
-                        for attr in e.attributes() {
-                            if let Ok(attr) = attr {
-                                if attr.key.as_ref() == b"style:name" {
-                                    current_style_name = String::from_utf8_lossy(&attr.value).to_string();
-                                    current_style = TextStyle::default();
-                                    in_style = true;
-                                }
-                            }
+                        if let Some(style_name) = attr_value(&e, b"name") {
+                            current_style_name = style_name;
+                            current_style = OdtStyleProps {
+                                parent: attr_value(&e, b"parent-style-name"),
+                                ..Default::default()
+                            };
+                            in_style = true;
+                        }
+                        // A style with no properties of its own only names its parent
+                        if is_empty && in_style {
+                            styles.insert(std::mem::take(&mut current_style_name), std::mem::take(&mut current_style));
+                            in_style = false;
                         }
                     }
+                    "paragraph-properties" if in_style => {
+                        current_style.background =
+                            attr_value(&e, b"background-color").filter(|color| color != "transparent");
+                        current_style.margin_left = attr_value(&e, b"margin-left").and_then(|value| length_to_points(&value));
+                        current_style.alignment = attr_value(&e, b"text-align");
+                        // "page" follows the page's own direction
+                        current_style.direction = attr_value(&e, b"writing-mode").and_then(|mode| {
+                            if mode.starts_with("rl") {
+                                Some("rtl".to_string())
+                            } else if mode.starts_with("lr") {
+                                Some("ltr".to_string())
+                            } else {
+                                None
+                            }
+                        });
+                    }
                     "text-properties" if in_style => {
+                        current_style.language = odt_language(&e);
                         // Parse text formatting properties
-                        for attr in e.attributes() {
-                            if let Ok(attr) = attr {
-                                match attr.key.as_ref() {
-                                    b"fo:font-weight" => {
-                                        let weight_val = String::from_utf8_lossy(&attr.value);
-                                        current_style.bold = weight_val == "bold" || weight_val == "700";
-                                    }
-                                    b"fo:font-style" => {
-                                        let style_val = String::from_utf8_lossy(&attr.value);
-                                        current_style.italic = style_val == "italic";
-                                    }
-                                    b"style:text-underline-style" => {
-                                        let underline_val = String::from_utf8_lossy(&attr.value);
-                                        current_style.underline = underline_val != "none" && !underline_val.is_empty();
-                                    }
-                                    b"fo:font-size" => {
-                                        current_style.font_size = Some(String::from_utf8_lossy(&attr.value).to_string());
-                                    }
-                                    b"fo:font-family" => {
-                                        current_style.font_family = Some(String::from_utf8_lossy(&attr.value).to_string());
-                                    }
-                                    b"fo:color" => {
-                                        current_style.color = Some(String::from_utf8_lossy(&attr.value).to_string());
-                                    }
-                                    _ => {}
+                        for attr in e.attributes().flatten() {
+                            match attr.key.as_ref() {
+                                b"fo:font-weight" => {
+                                    let weight_val = String::from_utf8_lossy(&attr.value);
+                                    current_style.bold = Some(weight_val == "bold" || weight_val == "700");
+                                }
+                                b"fo:font-style" => {
+                                    let style_val = String::from_utf8_lossy(&attr.value);
+                                    current_style.italic = Some(style_val == "italic");
+                                }
+                                b"style:text-underline-style" => {
+                                    let underline_val = String::from_utf8_lossy(&attr.value);
+                                    current_style.underline = Some(underline_val != "none" && !underline_val.is_empty());
                                 }
+                                b"fo:font-size" => {
+                                    current_style.font_size = Some(String::from_utf8_lossy(&attr.value).to_string());
+                                }
+                                b"fo:font-family" => {
+                                    current_style.font_family = Some(String::from_utf8_lossy(&attr.value).to_string());
+                                }
+                                b"fo:color" => {
+                                    current_style.color = Some(String::from_utf8_lossy(&attr.value).to_string());
+                                }
+                                b"fo:background-color" if attr.value.as_ref() != b"transparent" => {
+                                    current_style.highlight = Some(String::from_utf8_lossy(&attr.value).to_string());
+                                }
+                                b"text:display" => {
+                                    current_style.hidden = Some(attr.value.as_ref() == b"none");
+                                }
+                                _ => {}
                             }
                         }
                     }
@@ -558,9 +6973,8 @@ fn parse_odt_styles(styles_xml: &str) -> HashMap<String, TextStyle> {
                 let element_name = e.name();
                 let name = String::from_utf8_lossy(local_name(element_name.as_ref()));
                 if name == "style" && in_style {
-                    styles.insert(current_style_name.clone(), current_style.clone());
+                    styles.insert(std::mem::take(&mut current_style_name), std::mem::take(&mut current_style));
                     in_style = false;
-                    current_style_name.clear();
                 }
             }
             Ok(Event::Eof) => break,
@@ -570,6 +6984,14 @@ fn parse_odt_styles(styles_xml: &str) -> HashMap<String, TextStyle> {
         buf.clear();
     }
     
+    styles
+}
+
+/// Resolves `style:parent-style-name` chains into complete text styles.
+fn resolve_odt_styles(resolved: &HashMap<String, OdtStyleProps>) -> HashMap<String, TextStyle> {
+    let mut styles: HashMap<String, TextStyle> =
+        resolved.iter().map(|(name, props)| (name.clone(), props.to_text_style())).collect();
+    
     // Add some common default styles
     styles.insert("Bold".to_string(), TextStyle { bold: true, ..Default::default() });
     styles.insert("Italic".to_string(), TextStyle { italic: true, ..Default::default() });
@@ -578,50 +7000,282 @@ fn parse_odt_styles(styles_xml: &str) -> HashMap<String, TextStyle> {
     styles
 }
 
-fn read_odt_text(path: &str) -> std::io::Result<String> {
-    let structured = read_odt_structured(path)?;
-    Ok(structured.to_plain_text())
+/// Fills every style's unset properties from its `style:parent-style-name`
+/// chain. A chain that loops back on itself stops at the first repeated style.
+fn resolve_odt_inheritance(raw: &HashMap<String, OdtStyleProps>) -> HashMap<String, OdtStyleProps> {
+    let mut styles = HashMap::new();
+    for (name, props) in raw {
+        let mut resolved = props.clone();
+        let mut seen = std::collections::HashSet::from([name.as_str()]);
+        let mut parent = props.parent.as_deref();
+        while let Some(parent_name) = parent {
+            if !seen.insert(parent_name) {
+                break;
+            }
+            let Some(parent_props) = raw.get(parent_name) else {
+                break;
+            };
+            resolved.inherit(parent_props);
+            parent = parent_props.parent.as_deref();
+        }
+        styles.insert(name.clone(), resolved);
+    }
+    styles
+}
+
+fn read_odt_text(path: &str, options: &ReadOptions) -> std::io::Result<String> {
+    read_odt_text_streaming(path, options)
 }
 
-fn write_odt_text(path: &str, text: &str) -> std::io::Result<()> {
+fn write_odt_text(path: &str, text: &str, compression: Option<u8>) -> std::io::Result<()> {
+    let mut body = String::new();
+    for line in text.split_terminator('\n') {
+        body.push_str("<text:p>");
+        body.push_str(&xml_escape(line));
+        body.push_str("</text:p>");
+    }
+    write_odt_package(path, &body, "", compression)
+}
+
+/// Writes headings as `text:h`, lists as (nested) `text:list`s with a
+/// bullet or numbering style and tables with bordered cells. Run formatting
+/// isn't written yet and images are left out.
+fn write_odt_structured(path: &str, doc: &StructuredDocument, compression: Option<u8>) -> std::io::Result<()> {
+    let mut body = String::new();
+    let mut list_styles = String::new();
+    let mut table_count = 0;
+    // Text is only split where a bookmark starts
+    let paragraph = |tag: &str, attributes: &str, runs: &[TextRun]| {
+        let mut content = String::new();
+        let mut text = String::new();
+        for run in runs {
+            if !run.bookmarks.is_empty() {
+                content.push_str(&odt_text(&std::mem::take(&mut text)));
+                for name in &run.bookmarks {
+                    content.push_str(&format!("<text:bookmark text:name=\"{}\"/>", xml_escape(name)));
+                }
+            }
+            text.push_str(&run.text);
+        }
+        content.push_str(&odt_text(&text));
+        format!("<text:{}{}>{}</text:{}>", tag, attributes, content, tag)
+    };
+    for element in &doc.elements {
+        match element {
+            DocumentElement::Paragraph { runs, .. } => body.push_str(&paragraph("p", "", runs)),
+            DocumentElement::Heading { level, runs, .. } => {
+                let attributes = format!(" text:outline-level=\"{}\"", level);
+                body.push_str(&paragraph("h", &attributes, runs));
+            }
+            DocumentElement::List { items, ordered, levels, display_levels } => {
+                let style_name = format!("L{}", list_styles.matches("<text:list-style ").count() + 1);
+                list_styles.push_str(&odt_list_style(&style_name, *ordered, display_levels));
+                body.push_str(&format!("<text:list text:style-name=\"{}\">", style_name));
+                // Deeper items go in a list nested in the preceding item
+                let mut depth = 0usize;
+                let mut item_open = false;
+                for (index, item) in items.iter().enumerate() {
+                    let level = levels.get(index).map_or(0, |level| usize::from(*level));
+                    if item_open && level <= depth {
+                        body.push_str("</text:list-item>");
+                        item_open = false;
+                    }
+                    while depth > level {
+                        body.push_str("</text:list></text:list-item>");
+                        depth -= 1;
+                    }
+                    while depth < level {
+                        if !item_open {
+                            body.push_str("<text:list-item>");
+                        }
+                        body.push_str("<text:list>");
+                        item_open = false;
+                        depth += 1;
+                    }
+                    body.push_str("<text:list-item>");
+                    let mut paragraphs = item_paragraphs(item).peekable();
+                    if paragraphs.peek().is_none() {
+                        body.push_str(&paragraph("p", "", &[]));
+                    }
+                    for runs in paragraphs {
+                        body.push_str(&paragraph("p", "", runs));
+                    }
+                    item_open = true;
+                }
+                if item_open {
+                    body.push_str("</text:list-item>");
+                }
+                body.push_str(&"</text:list></text:list-item>".repeat(depth));
+                body.push_str("</text:list>");
+            }
+            DocumentElement::Table { rows } => {
+                if table_count == 0 {
+                    list_styles.push_str(
+                        "<style:style style:name=\"TableCell\" style:family=\"table-cell\">\
+<style:table-cell-properties fo:border=\"0.5pt solid #000000\" fo:padding=\"0.04in\"/></style:style>",
+                    );
+                }
+                table_count += 1;
+                body.push_str(&odt_table(rows, &format!("Table{}", table_count)));
+            }
+            DocumentElement::LineBreak => body.push_str(&paragraph("p", "", &[])),
+            DocumentElement::Image { .. } => {}
+        }
+    }
+    write_odt_package(path, &body, &list_styles, compression)
+}
+
+/// A `table:table` whose cells use the `TableCell` style; positions covered
+/// by a span are written as `table:covered-table-cell`s.
+fn odt_table(rows: &[Vec<TableCell>], name: &str) -> String {
+    let slots = table_slots(rows);
+    let columns = slots.iter().map(|row| row.len()).max().unwrap_or(0).max(1);
+    let mut xml = format!(
+        "<table:table table:name=\"{}\"><table:table-column table:number-columns-repeated=\"{}\"/>",
+        xml_escape(name),
+        columns
+    );
+    for row in slots {
+        xml.push_str("<table:table-row>");
+        let width = row.len();
+        for slot in row {
+            match slot {
+                GridSlot::Cell(cell) => {
+                    xml.push_str("<table:table-cell table:style-name=\"TableCell\" office:value-type=\"string\"");
+                    if cell.colspan > 1 {
+                        xml.push_str(&format!(" table:number-columns-spanned=\"{}\"", cell.colspan));
+                    }
+                    if cell.rowspan > 1 {
+                        xml.push_str(&format!(" table:number-rows-spanned=\"{}\"", cell.rowspan));
+                    }
+                    xml.push('>');
+                    let text: String = cell.runs.iter().map(|run| run.text.as_str()).collect();
+                    for line in text.split('\n') {
+                        xml.push_str(&format!("<text:p>{}</text:p>", odt_text(line)));
+                    }
+                    xml.push_str("</table:table-cell>");
+                }
+                GridSlot::RowContinuation(_) | GridSlot::Covered => xml.push_str("<table:covered-table-cell/>"),
+            }
+        }
+        // Every row needs an entry for each column
+        for _ in width..columns {
+            xml.push_str("<table:table-cell table:style-name=\"TableCell\"><text:p/></table:table-cell>");
+        }
+        xml.push_str("</table:table-row>");
+    }
+    xml.push_str("</table:table>");
+    xml
+}
+
+/// An automatic list style for all ten ODT list levels, bulleted or numbered.
+fn odt_list_style(name: &str, ordered: bool, display_levels: &[u8]) -> String {
+    let mut style = format!("<text:list-style style:name=\"{}\">", name);
+    for level in 1..=10u8 {
+        let indent = format!(
+            "<style:list-level-properties text:space-before=\"{:.2}in\" text:min-label-width=\"0.25in\"/>",
+            f64::from(level - 1) * 0.25
+        );
+        if ordered {
+            let shown = display_levels.get(usize::from(level - 1)).copied().unwrap_or(1).max(1);
+            style.push_str(&format!(
+                "<text:list-level-style-number text:level=\"{}\" style:num-format=\"1\" style:num-suffix=\".\" text:display-levels=\"{}\">{}</text:list-level-style-number>",
+                level, shown, indent
+            ));
+        } else {
+            style.push_str(&format!(
+                "<text:list-level-style-bullet text:level=\"{}\" text:bullet-char=\"\u{2022}\">{}</text:list-level-style-bullet>",
+                level, indent
+            ));
+        }
+    }
+    style.push_str("</text:list-style>");
+    style
+}
+
+/// Escapes paragraph text for ODT, writing tabs, line breaks and runs of
+/// spaces as the elements ODT needs to keep them; the reverse of `odt_whitespace`.
+fn odt_text(text: &str) -> String {
+    let mut out = String::new();
+    let mut plain = String::new();
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            ' ' => {
+                let mut count = 1;
+                while chars.next_if_eq(&' ').is_some() {
+                    count += 1;
+                }
+                // A single space between words survives; leading, trailing and
+                // repeated spaces would be collapsed by readers
+                let keep_one = !(out.is_empty() && plain.is_empty()) && chars.peek().is_some();
+                if keep_one {
+                    plain.push(' ');
+                    count -= 1;
+                }
+                if count > 0 {
+                    out.push_str(&xml_escape(&std::mem::take(&mut plain)));
+                    out.push_str(&if count == 1 { "<text:s/>".to_string() } else { format!("<text:s text:c=\"{}\"/>", count) });
+                }
+            }
+            '\t' | '\n' => {
+                out.push_str(&xml_escape(&std::mem::take(&mut plain)));
+                out.push_str(if c == '\t' { "<text:tab/>" } else { "<text:line-break/>" });
+            }
+            c => plain.push(c),
+        }
+    }
+    out.push_str(&xml_escape(&plain));
+    out
+}
+
+/// Writes an ODT package around the `office:text` body, with any automatic
+/// styles the body refers to.
+fn write_odt_package(path: &str, body: &str, automatic_styles: &str, compression: Option<u8>) -> std::io::Result<()> {
     let mut f = File::create(path)?;
     let mut zip = ZipWriter::new(&mut f);
 
     // The mimetype entry MUST be the first entry and stored (no compression)
+    let mimetype = if ext_lower(path) == "ott" {
+        "application/vnd.oasis.opendocument.text-template"
+    } else {
+        "application/vnd.oasis.opendocument.text"
+    };
     let stored = FileOptions::default().compression_method(CompressionMethod::Stored);
     zip.start_file("mimetype", stored)?;
-    zip.write_all(b"application/vnd.oasis.opendocument.text")?;
+    zip.write_all(mimetype.as_bytes())?;
 
-    let deflated = FileOptions::default().compression_method(CompressionMethod::Deflated);
+    let deflated = entry_options(compression);
 
     // content.xml
-    let mut body = String::new();
-    for line in text.split_terminator('\n') {
-        body.push_str("<text:p>");
-        body.push_str(&xml_escape(line));
-        body.push_str("</text:p>");
-    }
-
     let content_xml = format!(
         "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\
 <office:document-content \
  xmlns:office=\"urn:oasis:names:tc:opendocument:xmlns:office:1.0\" \
- xmlns:text=\"urn:oasis:names:tc:opendocument:xmlns:text:1.0\">\
+ xmlns:style=\"urn:oasis:names:tc:opendocument:xmlns:style:1.0\" \
+ xmlns:text=\"urn:oasis:names:tc:opendocument:xmlns:text:1.0\" \
+ xmlns:table=\"urn:oasis:names:tc:opendocument:xmlns:table:1.0\" \
+ xmlns:fo=\"urn:oasis:names:tc:opendocument:xmlns:xsl-fo-compatible:1.0\" \
+ office:version=\"1.2\">\
+  <office:automatic-styles>{}</office:automatic-styles>\
   <office:body>\
     <office:text>{}</office:text>\
   </office:body>\
 </office:document-content>",
-        body
+        automatic_styles, body
     );
     zip.start_file("content.xml", deflated)?;
     zip.write_all(content_xml.as_bytes())?;
 
     // META-INF/manifest.xml
-    let manifest_xml = r#"<?xml version=\"1.0\" encoding=\"UTF-8\"?>
-<manifest:manifest xmlns:manifest=\"urn:oasis:names:tc:opendocument:xmlns:manifest:1.0\">
-  <manifest:file-entry manifest:media-type=\"application/vnd.oasis.opendocument.text\" manifest:full-path=\"/\"/>
-  <manifest:file-entry manifest:media-type=\"text/xml\" manifest:full-path=\"content.xml\"/>
-</manifest:manifest>"#;
+    let manifest_xml = format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<manifest:manifest xmlns:manifest="urn:oasis:names:tc:opendocument:xmlns:manifest:1.0">
+  <manifest:file-entry manifest:media-type="{}" manifest:full-path="/"/>
+  <manifest:file-entry manifest:media-type="text/xml" manifest:full-path="content.xml"/>
+</manifest:manifest>"#,
+        mimetype
+    );
     zip.start_file("META-INF/manifest.xml", deflated)?;
     zip.write_all(manifest_xml.as_bytes())?;
 
@@ -630,9 +7284,92 @@ fn write_odt_text(path: &str, text: &str) -> std::io::Result<()> {
 }
 
 #[pyclass]
+#[derive(Default)]
 pub struct Document {
     inner: Arc<Mutex<String>>, // plain text representation
     structured: Arc<Mutex<Option<StructuredDocument>>>, // structured representation
+    line_ending: Arc<Mutex<LineEnding>>, // terminator for text output, detected on open
+    container: Arc<Mutex<Option<Vec<u8>>>>, // original DOCX package, kept for saving back into
+    language: Arc<Mutex<Option<String>>>, // language of a file opened as plain text
+}
+
+impl Document {
+    /// Snapshot of the structured content, promoting plain text if needed.
+    fn to_structured(&self) -> StructuredDocument {
+        match self.structured.lock().unwrap().as_ref() {
+            Some(structured) => structured.clone(),
+            None => self.plain_structured(),
+        }
+    }
+
+    /// The plain text as a structured document, keeping the language of the
+    /// file it was opened from.
+    fn plain_structured(&self) -> StructuredDocument {
+        let mut doc = StructuredDocument::from_plain_text(&self.inner.lock().unwrap());
+        doc.language = self.language.lock().unwrap().clone();
+        doc
+    }
+
+    /// Stores opened text with `\n` line endings, remembering the file's own
+    /// ending as the default for output. Any package kept from an earlier
+    /// `open` is dropped, as it no longer belongs to the content, and so is
+    /// its language until `load_language` reads the new file's.
+    fn load_text(&self, text: String) {
+        *self.line_ending.lock().unwrap() = LineEnding::detect(&text).unwrap_or_default();
+        self.set_text(normalize_line_endings(&text, LineEnding::Lf));
+        *self.container.lock().unwrap() = None;
+        *self.language.lock().unwrap() = None;
+    }
+
+    /// Keeps the language declared by the DOCX or ODT file at `path`, whose
+    /// text was loaded without a structured model to hold it.
+    fn load_language(&self, path: &str, ext: &str) -> std::io::Result<()> {
+        *self.language.lock().unwrap() = read_package_language(path, ext)?;
+        Ok(())
+    }
+
+    /// Replaces the content with a loaded structured document, deriving the
+    /// plain text from it; like `load_text`, this drops any kept package.
+    fn load_structured(&self, doc: StructuredDocument) {
+        *self.inner.lock().unwrap() = doc.to_plain_text();
+        *self.structured.lock().unwrap() = Some(doc);
+        *self.container.lock().unwrap() = None;
+        *self.language.lock().unwrap() = None;
+    }
+
+    /// Loads a DOCX file keeping its package for the next save. The body is
+    /// written back from the structured model, so that is loaded too; plain
+    /// text would flatten lists, tables, images and run styles.
+    fn open_preserved(&self, path: &str) -> std::io::Result<()> {
+        let original = std::fs::read(path)?;
+        self.load_structured(read_docx_structured(path)?);
+        *self.container.lock().unwrap() = Some(original);
+        Ok(())
+    }
+
+    /// The content as a standalone HTML page, as saved to `.html` files. The
+    /// language comes from the structured model or, for text opened without
+    /// one, from the file.
+    fn html_document(&self, title: Option<&str>) -> String {
+        let (language, default_style) = match self.structured.lock().unwrap().as_ref() {
+            Some(doc) => (doc.language.clone(), doc.default_style.clone()),
+            None => (self.language.lock().unwrap().clone(), None),
+        };
+        html_page(title, language.as_deref(), default_style.as_ref(), true, &self.get_html())
+    }
+
+    /// Parses an explicit line ending name, or falls back to the document's.
+    fn resolve_line_ending(&self, name: Option<&str>) -> PyResult<LineEnding> {
+        match name {
+            Some(name) => LineEnding::parse(name).ok_or_else(|| {
+                PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                    "unknown line ending '{}', expected 'lf', 'crlf', 'cr' or 'native'",
+                    name
+                ))
+            }),
+            None => Ok(*self.line_ending.lock().unwrap()),
+        }
+    }
 }
 
 #[pymethods]
@@ -642,9 +7379,23 @@ impl Document {
         Self {
             inner: Arc::new(Mutex::new(String::new())),
             structured: Arc::new(Mutex::new(None)),
+            line_ending: Arc::new(Mutex::new(LineEnding::default())),
+            container: Arc::new(Mutex::new(None)),
+            language: Arc::new(Mutex::new(None)),
         }
     }
 
+    /// A document holding the structured tree in `json`, as returned by
+    /// `read_structured_json`, with its plain text derived from it.
+    #[staticmethod]
+    pub fn from_structured_json(json: String) -> PyResult<Self> {
+        let structured = parse_structured_json(&json)?;
+        let document = Self::new();
+        *document.inner.lock().unwrap() = structured.to_plain_text();
+        *document.structured.lock().unwrap() = Some(structured);
+        Ok(document)
+    }
+
     pub fn set_text(&self, text: String) {
         if let Ok(mut guard) = self.inner.lock() {
             *guard = text;
@@ -662,73 +7413,1066 @@ impl Document {
         Ok(())
     }
 
-    pub fn get_text(&self) -> String {
-        self.inner.lock().unwrap().clone()
+    pub fn apply_text_patch(&self, unified_diff: String) -> PyResult<()> {
+        let patched = apply_unified_diff(&self.inner.lock().unwrap(), &unified_diff)
+            .map_err(PyErr::new::<pyo3::exceptions::PyValueError, _>)?;
+        self.set_text(patched);
+        Ok(())
+    }
+
+    /// The plain text, with the document's line ending unless one is given.
+    #[pyo3(signature = (line_ending = None))]
+    pub fn get_text(&self, line_ending: Option<&str>) -> PyResult<String> {
+        let line_ending = self.resolve_line_ending(line_ending)?;
+        Ok(normalize_line_endings(&self.inner.lock().unwrap(), line_ending))
+    }
+
+    /// `get_text` with control characters that are unsafe in XML or JSON removed.
+    #[pyo3(signature = (line_ending = None))]
+    pub fn get_text_sanitized(&self, line_ending: Option<&str>) -> PyResult<String> {
+        Ok(sanitize_text(&self.get_text(line_ending)?))
+    }
+
+    /// Up to `count` lines of the plain text starting at line `start`; a range
+    /// past the end is clamped. A trailing newline does not start another line.
+    pub fn lines(&self, start: usize, count: usize) -> Vec<String> {
+        self.inner
+            .lock()
+            .unwrap()
+            .split_terminator('\n')
+            .skip(start)
+            .take(count)
+            .map(str::to_string)
+            .collect()
+    }
+
+    pub fn line_count(&self) -> usize {
+        self.inner.lock().unwrap().split_terminator('\n').count()
+    }
+
+    /// Name of the line ending used for text output: "lf", "crlf", "cr" or "native".
+    pub fn line_ending(&self) -> &'static str {
+        self.line_ending.lock().unwrap().name()
+    }
+
+    pub fn set_line_ending(&self, line_ending: &str) -> PyResult<()> {
+        let line_ending = self.resolve_line_ending(Some(line_ending))?;
+        *self.line_ending.lock().unwrap() = line_ending;
+        Ok(())
+    }
+
+    /// Estimated reading time at `wpm` words per minute, rounded up to whole
+    /// minutes.
+    #[pyo3(signature = (wpm = 200))]
+    pub fn reading_time_minutes(&self, wpm: u32) -> PyResult<usize> {
+        if wpm == 0 {
+            return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(
+                "Words per minute must be positive, got 0",
+            ));
+        }
+        Ok(self.to_structured().reading_time_minutes(wpm))
+    }
+
+    pub fn sentence_count(&self) -> usize {
+        sentence_count(&self.inner.lock().unwrap())
+    }
+
+    /// `words`, `sentences` and `reading_time_minutes`, the last rounded up
+    /// to whole minutes at `wpm` words per minute.
+    #[pyo3(signature = (wpm = 200))]
+    pub fn text_stats(&self, wpm: u32) -> PyResult<HashMap<String, usize>> {
+        if wpm == 0 {
+            return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(
+                "Words per minute must be positive, got 0",
+            ));
+        }
+        let doc = self.to_structured();
+        Ok(HashMap::from([
+            ("words".to_string(), doc.word_count(true, true)),
+            ("sentences".to_string(), sentence_count(&self.inner.lock().unwrap())),
+            ("reading_time_minutes".to_string(), doc.reading_time_minutes(wpm)),
+        ]))
+    }
+
+    #[pyo3(signature = (include_headings = true, include_tables = true))]
+    pub fn word_count(&self, include_headings: bool, include_tables: bool) -> usize {
+        self.to_structured().word_count(include_headings, include_tables)
+    }
+
+    #[pyo3(signature = (include_headings = true))]
+    pub fn paragraph_count(&self, include_headings: bool) -> usize {
+        self.to_structured().paragraph_count(include_headings)
+    }
+
+    pub fn clear(&self) {
+        if let Ok(mut guard) = self.inner.lock() {
+            guard.clear();
+        }
+        // Clear structured representation when text is cleared
+        *self.structured.lock().unwrap() = None;
+        *self.container.lock().unwrap() = None;
+        *self.language.lock().unwrap() = None;
+    }
+
+    /// With `link_style` off, links keep only their own run formatting;
+    /// `preserve_whitespace` keeps significant spaces at the edges of runs.
+    #[pyo3(signature = (path, link_style = true, preserve_whitespace = false))]
+    pub fn load_docx_structured(&self, path: String, link_style: bool, preserve_whitespace: bool) -> PyResult<()> {
+        let options = ReadOptions {
+            raw_link_style: !link_style,
+            preserve_whitespace,
+            ..ReadOptions::default()
+        };
+        match read_docx_structured_with(&path, &options) {
+            Ok(structured_doc) => {
+                self.load_structured(structured_doc);
+                Ok(())
+            }
+            Err(e) => Err(PyErr::new::<pyo3::exceptions::PyIOError, _>(format!(
+                "Failed to read DOCX file: {}",
+                e
+            ))),
+        }
+    }
+
+    pub fn alt_texts(&self) -> Vec<Option<String>> {
+        match self.structured.lock().unwrap().as_ref() {
+            Some(structured) => structured.alt_texts(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Element counts by kind; plain text counts as paragraphs.
+    pub fn counts(&self) -> HashMap<String, usize> {
+        match self.structured.lock().unwrap().as_ref() {
+            Some(structured) => structured.counts(),
+            None => StructuredDocument::from_plain_text(&self.inner.lock().unwrap()).counts(),
+        }
+    }
+
+    pub fn element_indices_of_kind(&self, kind: String) -> Vec<usize> {
+        match self.structured.lock().unwrap().as_ref() {
+            Some(structured) => structured.element_indices_of_kind(&kind),
+            None => StructuredDocument::from_plain_text(&self.inner.lock().unwrap()).element_indices_of_kind(&kind),
+        }
+    }
+
+    pub fn table_of_contents(&self) -> Vec<(u8, String, String)> {
+        match self.structured.lock().unwrap().as_ref() {
+            Some(structured) => structured.table_of_contents(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Replaces the structure with one inferred from the plain text: blocks
+    /// separated by blank lines become paragraphs and lines starting with one
+    /// of `heading_prefixes` (e.g. "# ", "## ") become headings.
+    pub fn text_to_structured(&self, heading_prefixes: Vec<String>) {
+        let mut structured = self.structured.lock().unwrap();
+        let mut inner = self.inner.lock().unwrap();
+        let mut doc = StructuredDocument::from_plain_text_blocks(&inner, &heading_prefixes);
+        doc.language = match structured.as_ref() {
+            Some(previous) => previous.language.clone(),
+            None => self.language.lock().unwrap().clone(),
+        };
+        *inner = doc.to_plain_text();
+        *structured = Some(doc);
+    }
+
+    /// Document properties, such as Markdown front matter, in source order.
+    pub fn get_metadata(&self) -> Vec<(String, String)> {
+        match self.structured.lock().unwrap().as_ref() {
+            Some(structured) => structured.metadata.iter().map(|(key, value)| (key.clone(), value.clone())).collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// The document's default language as a BCP 47 tag such as `en-US`, if
+    /// the file declares one.
+    pub fn get_language(&self) -> Option<String> {
+        match self.structured.lock().unwrap().as_ref() {
+            Some(structured) => structured.language.clone(),
+            None => self.language.lock().unwrap().clone(),
+        }
+    }
+
+    /// The heading outline as OPML, for outliner and mind-map tools.
+    pub fn get_opml(&self) -> String {
+        self.to_structured().to_opml()
+    }
+
+    /// Review comments as JSON, with replies nested under the comment they answer.
+    pub fn get_comments_json(&self) -> PyResult<String> {
+        let comments = match self.structured.lock().unwrap().as_ref() {
+            Some(structured) => structured.comments.clone(),
+            None => Vec::new(),
+        };
+        serde_json::to_string(&comments).map_err(|e| {
+            PyErr::new::<pyo3::exceptions::PyValueError, _>(format!("Failed to serialize comments: {}", e))
+        })
+    }
+
+    /// Hex XXH3-128 digest of the document's content: its structure as JSON
+    /// with sorted keys, or the plain text when there is no structure. It
+    /// doesn't depend on the file the content came from.
+    pub fn content_hash(&self) -> PyResult<String> {
+        let canonical = match self.structured.lock().unwrap().as_ref() {
+            // Going through a Value sorts the keys of every map, styles included
+            Some(structured) => serde_json::to_value(structured)
+                .map(|value| value.to_string())
+                .map_err(|e| PyErr::new::<pyo3::exceptions::PyValueError, _>(format!("Failed to serialize document: {}", e)))?,
+            None => self.inner.lock().unwrap().clone(),
+        };
+        Ok(format!("{:032x}", xxhash_rust::xxh3::xxh3_128(canonical.as_bytes())))
+    }
+
+    /// (author, text) of every review comment in the loaded document.
+    pub fn get_comments(&self) -> Vec<(String, String)> {
+        match self.structured.lock().unwrap().as_ref() {
+            Some(structured) => structured
+                .comments
+                .iter()
+                .map(|comment| (comment.author.clone(), comment.text.clone()))
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Attaches a comment to the element at `element_index`; it is written
+    /// as a comment range when the document is saved as DOCX.
+    pub fn add_comment(&self, element_index: usize, author: String, text: String) -> PyResult<()> {
+        let mut structured = self.structured.lock().unwrap();
+        let doc = structured.get_or_insert_with(|| self.plain_structured());
+        if element_index >= doc.elements.len() {
+            return Err(PyErr::new::<PyIndexError, _>(format!(
+                "element index {} out of range for {} elements",
+                element_index,
+                doc.elements.len()
+            )));
+        }
+        doc.comments.push(Comment {
+            author,
+            text,
+            anchor: None,
+            element_index: Some(element_index),
+            replies: Vec::new(),
+            resolved: None,
+        });
+        Ok(())
+    }
+
+    /// Sets the base font every run starts from; `size` is in points, e.g. "12".
+    #[pyo3(signature = (family, size = None))]
+    pub fn set_default_font(&self, family: String, size: Option<String>) {
+        let mut structured = self.structured.lock().unwrap();
+        let doc = structured.get_or_insert_with(|| self.plain_structured());
+        doc.default_style = Some(TextStyle {
+            font_family: Some(family),
+            font_size: size,
+            ..TextStyle::default()
+        });
+    }
+
+    pub fn get_footnotes(&self) -> Vec<String> {
+        match self.structured.lock().unwrap().as_ref() {
+            Some(structured) => structured.footnotes.clone(),
+            None => Vec::new(),
+        }
+    }
+
+    pub fn list_bookmarks(&self) -> Vec<String> {
+        match self.structured.lock().unwrap().as_ref() {
+            Some(structured) => structured.bookmarks(),
+            None => Vec::new(),
+        }
+    }
+
+    pub fn text_at_bookmark(&self, name: String) -> Option<String> {
+        self.structured.lock().unwrap().as_ref()?.text_at_bookmark(&name)
+    }
+
+    pub fn get_element_json(&self, index: usize) -> PyResult<String> {
+        let doc = self.to_structured();
+        let element = doc.elements.get(index).ok_or_else(|| {
+            PyErr::new::<PyIndexError, _>(format!(
+                "element index {} out of range for {} elements",
+                index,
+                doc.elements.len()
+            ))
+        })?;
+        serde_json::to_string(element).map_err(|e| {
+            PyErr::new::<pyo3::exceptions::PyValueError, _>(format!("Failed to serialize element: {}", e))
+        })
+    }
+
+    /// Replaces one element with its JSON form, as produced by `get_element_json`.
+    pub fn set_element_json(&self, index: usize, json: String) -> PyResult<()> {
+        let element: DocumentElement = serde_json::from_str(&json).map_err(|e| {
+            PyErr::new::<pyo3::exceptions::PyValueError, _>(format!("Invalid element JSON: {}", e))
+        })?;
+        let mut structured = self.structured.lock().unwrap();
+        let doc = structured.get_or_insert_with(|| self.plain_structured());
+        let count = doc.elements.len();
+        let slot = doc.elements.get_mut(index).ok_or_else(|| {
+            PyErr::new::<PyIndexError, _>(format!("element index {} out of range for {} elements", index, count))
+        })?;
+        *slot = element;
+        *self.inner.lock().unwrap() = doc.to_plain_text();
+        Ok(())
+    }
+
+    /// Inserts an element before `index`; `index` equal to the element count appends.
+    pub fn insert_element_json(&self, index: usize, json: String) -> PyResult<()> {
+        let element: DocumentElement = serde_json::from_str(&json).map_err(|e| {
+            PyErr::new::<pyo3::exceptions::PyValueError, _>(format!("Invalid element JSON: {}", e))
+        })?;
+        let mut structured = self.structured.lock().unwrap();
+        let doc = structured.get_or_insert_with(|| self.plain_structured());
+        if index > doc.elements.len() {
+            return Err(PyErr::new::<PyIndexError, _>(format!(
+                "insert index {} out of range for {} elements",
+                index,
+                doc.elements.len()
+            )));
+        }
+        doc.insert_element(index, element);
+        *self.inner.lock().unwrap() = doc.to_plain_text();
+        Ok(())
+    }
+
+    /// Inserts an empty `rows` x `cols` table before `index`; `index` equal to
+    /// the element count appends.
+    pub fn insert_table(&self, index: usize, rows: usize, cols: usize) -> PyResult<()> {
+        if rows == 0 || cols == 0 {
+            return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(
+                "a table needs at least one row and one column",
+            ));
+        }
+        let mut structured = self.structured.lock().unwrap();
+        let doc = structured.get_or_insert_with(|| self.plain_structured());
+        doc.insert_table(index, rows, cols).map_err(PyErr::new::<PyIndexError, _>)?;
+        *self.inner.lock().unwrap() = doc.to_plain_text();
+        Ok(())
+    }
+
+    /// Replaces a cell's content with a single unstyled run. `col` counts the
+    /// cells present in the row, so a merged cell counts once.
+    pub fn set_cell(&self, table_index: usize, row: usize, col: usize, text: String) -> PyResult<()> {
+        let mut structured = self.structured.lock().unwrap();
+        let doc = structured.get_or_insert_with(|| self.plain_structured());
+        doc.set_cell(table_index, row, col, text).map_err(PyErr::new::<PyIndexError, _>)?;
+        *self.inner.lock().unwrap() = doc.to_plain_text();
+        Ok(())
+    }
+
+    /// Splits a paragraph or heading in two at `char_offset`, as pressing Enter does.
+    pub fn split_paragraph(&self, element_index: usize, char_offset: usize) -> PyResult<()> {
+        let mut structured = self.structured.lock().unwrap();
+        let doc = structured.get_or_insert_with(|| self.plain_structured());
+        let element = doc.elements.get(element_index).ok_or_else(|| {
+            PyErr::new::<PyIndexError, _>(format!(
+                "element index {} out of range for {} elements",
+                element_index,
+                doc.elements.len()
+            ))
+        })?;
+        let length: usize = element.runs().iter().map(|run| run.text.chars().count()).sum();
+        if char_offset > length {
+            return Err(PyErr::new::<PyIndexError, _>(format!(
+                "offset {} is past the end of a {}-character element",
+                char_offset, length
+            )));
+        }
+        if !doc.split_paragraph(element_index, char_offset) {
+            return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                "element {} is a {}, only paragraphs and headings can be split",
+                element_index,
+                doc.elements[element_index].kind()
+            )));
+        }
+        *self.inner.lock().unwrap() = doc.to_plain_text();
+        Ok(())
+    }
+
+    /// Joins element `first_index + 1` onto `first_index`, as backspace at the
+    /// start of a paragraph does.
+    pub fn merge_paragraphs(&self, first_index: usize) -> PyResult<()> {
+        let mut structured = self.structured.lock().unwrap();
+        let doc = structured.get_or_insert_with(|| self.plain_structured());
+        if first_index >= doc.elements.len().saturating_sub(1) {
+            return Err(PyErr::new::<PyIndexError, _>(format!(
+                "element index {} has no following element to merge ({} elements)",
+                first_index,
+                doc.elements.len()
+            )));
+        }
+        if !doc.merge_paragraphs(first_index) {
+            return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                "cannot merge a {} with a {}, only paragraphs and headings can be merged",
+                doc.elements[first_index].kind(),
+                doc.elements[first_index + 1].kind()
+            )));
+        }
+        *self.inner.lock().unwrap() = doc.to_plain_text();
+        Ok(())
+    }
+
+    /// Returns `(text, bold, italic, underline)` for each run of a paragraph or heading.
+    pub fn get_runs(&self, element_index: usize) -> PyResult<Vec<(String, bool, bool, bool)>> {
+        let doc = self.to_structured();
+        let element = doc.elements.get(element_index).ok_or_else(|| {
+            PyErr::new::<PyIndexError, _>(format!(
+                "element index {} out of range for {} elements",
+                element_index,
+                doc.elements.len()
+            ))
+        })?;
+        Ok(element
+            .runs()
+            .iter()
+            .map(|run| (run.text.clone(), run.style.bold, run.style.italic, run.style.underline))
+            .collect())
+    }
+
+    pub fn set_run_style(
+        &self,
+        element_index: usize,
+        run_index: usize,
+        bold: bool,
+        italic: bool,
+        underline: bool,
+    ) -> PyResult<()> {
+        let mut structured = self.structured.lock().unwrap();
+        let doc = structured.get_or_insert_with(|| self.plain_structured());
+        let count = doc.elements.len();
+        let element = doc.elements.get_mut(element_index).ok_or_else(|| {
+            PyErr::new::<PyIndexError, _>(format!("element index {} out of range for {} elements", element_index, count))
+        })?;
+        let runs = element.runs_mut().map(|runs| runs.as_mut_slice()).unwrap_or_default();
+        let run_count = runs.len();
+        let run = runs.get_mut(run_index).ok_or_else(|| {
+            PyErr::new::<PyIndexError, _>(format!("run index {} out of range for {} runs", run_index, run_count))
+        })?;
+        run.style.bold = bold;
+        run.style.italic = italic;
+        run.style.underline = underline;
+        Ok(())
+    }
+
+    /// Appends a run to the end of a paragraph or heading, for streaming
+    /// text into a document without rebuilding the element.
+    pub fn append_run(
+        &self,
+        element_index: usize,
+        text: String,
+        bold: bool,
+        italic: bool,
+        underline: bool,
+    ) -> PyResult<()> {
+        let mut structured = self.structured.lock().unwrap();
+        let doc = structured.get_or_insert_with(|| self.plain_structured());
+        let count = doc.elements.len();
+        let element = doc.elements.get_mut(element_index).ok_or_else(|| {
+            PyErr::new::<PyIndexError, _>(format!("element index {} out of range for {} elements", element_index, count))
+        })?;
+        let kind = element.kind();
+        let runs = element.runs_mut().ok_or_else(|| {
+            PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                "element {} is a {}, only paragraphs and headings have runs to append to",
+                element_index, kind
+            ))
+        })?;
+        runs.push(TextRun {
+            style: TextStyle {
+                bold,
+                italic,
+                underline,
+                ..Default::default()
+            },
+            ..TextRun::plain(text)
+        });
+        *self.inner.lock().unwrap() = doc.to_plain_text();
+        Ok(())
+    }
+
+    pub fn remove_element(&self, index: usize) -> PyResult<()> {
+        let mut structured = self.structured.lock().unwrap();
+        let doc = structured.get_or_insert_with(|| self.plain_structured());
+        if index >= doc.elements.len() {
+            return Err(PyErr::new::<PyIndexError, _>(format!(
+                "element index {} out of range for {} elements",
+                index,
+                doc.elements.len()
+            )));
+        }
+        doc.remove_element(index);
+        *self.inner.lock().unwrap() = doc.to_plain_text();
+        Ok(())
+    }
+
+    pub fn transform(&self, steps_json: String) -> PyResult<()> {
+        let steps: Vec<String> = serde_json::from_str(&steps_json).map_err(|e| {
+            PyErr::new::<pyo3::exceptions::PyValueError, _>(format!("Invalid transform list: {}", e))
+        })?;
+        // Reject the whole pipeline up front rather than half-applying it
+        if let Some(unknown) = steps
+            .iter()
+            .find(|step| !StructuredDocument::TRANSFORMS.contains(&step.as_str()))
+        {
+            return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                "unknown transform '{}', expected one of: {}",
+                unknown,
+                StructuredDocument::TRANSFORMS.join(", ")
+            )));
+        }
+
+        let mut structured = self.structured.lock().unwrap();
+        let doc = structured.get_or_insert_with(|| self.plain_structured());
+        for step in &steps {
+            doc.apply_transform(step)
+                .map_err(PyErr::new::<pyo3::exceptions::PyValueError, _>)?;
+        }
+        *self.inner.lock().unwrap() = doc.to_plain_text();
+        Ok(())
+    }
+
+    pub fn validate(&self) -> Vec<String> {
+        match self.structured.lock().unwrap().as_ref() {
+            Some(structured) => structured.validate(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Applies the safe fixes from `StructuredDocument::repair` and lists them.
+    pub fn repair(&self) -> Vec<String> {
+        let mut structured = self.structured.lock().unwrap();
+        let Some(doc) = structured.as_mut() else {
+            return Vec::new();
+        };
+        let repairs = doc.repair();
+        if !repairs.is_empty() {
+            *self.inner.lock().unwrap() = doc.to_plain_text();
+        }
+        repairs
+    }
+
+    #[pyo3(signature = (separator = "\n"))]
+    pub fn collapse_adjacent_headings(&self, separator: &str) -> usize {
+        let mut structured = self.structured.lock().unwrap();
+        let Some(doc) = structured.as_mut() else {
+            return 0;
+        };
+        let merged = doc.collapse_adjacent_headings(separator);
+        if merged > 0 {
+            *self.inner.lock().unwrap() = doc.to_plain_text();
+        }
+        merged
+    }
+
+    /// Table `table_index` as a JSON array of objects keyed by its header row.
+    pub fn table_to_records(&self, table_index: usize) -> PyResult<String> {
+        let records = match self.structured.lock().unwrap().as_ref() {
+            Some(structured) => structured.table_records(table_index),
+            None => None,
+        };
+        let records = records.ok_or_else(|| {
+            PyErr::new::<PyIndexError, _>(format!("table index {} out of range", table_index))
+        })?;
+        serde_json::to_string(&records).map_err(|e| {
+            PyErr::new::<pyo3::exceptions::PyValueError, _>(format!("Failed to serialize records: {}", e))
+        })
+    }
+
+    /// The cell texts of every table, as a list of rows of cells per table.
+    pub fn get_tables(&self) -> Vec<Vec<Vec<String>>> {
+        match self.structured.lock().unwrap().as_ref() {
+            Some(structured) => structured.table_texts(),
+            None => Vec::new(),
+        }
+    }
+
+    pub fn get_table_csv(&self, index: usize) -> PyResult<String> {
+        let tables = match self.structured.lock().unwrap().as_ref() {
+            Some(structured) => structured.tables_to_csv(),
+            None => Vec::new(),
+        };
+        let count = tables.len();
+        tables.into_iter().nth(index).ok_or_else(|| {
+            PyErr::new::<PyIndexError, _>(format!("table index {} out of range for {} tables", index, count))
+        })
+    }
+
+    /// Estimated pages as JSON: a list of `{start, elements}` objects.
+    #[pyo3(signature = (line_height = 14.0, page_width = 595.0, page_height = 842.0, margin = 72.0))]
+    pub fn paginate(&self, line_height: f64, page_width: f64, page_height: f64, margin: f64) -> PyResult<String> {
+        let page_setup = PageSetup {
+            width: page_width,
+            height: page_height,
+            margin_top: margin,
+            margin_bottom: margin,
+            margin_left: margin,
+            margin_right: margin,
+        };
+        let pages = self.to_structured().paginate(&page_setup, line_height);
+        serde_json::to_string(&pages).map_err(|e| {
+            PyErr::new::<pyo3::exceptions::PyValueError, _>(format!("Failed to serialize pages: {}", e))
+        })
+    }
+
+    pub fn style_statistics(&self) -> PyResult<String> {
+        let stats = match self.structured.lock().unwrap().as_ref() {
+            Some(structured) => structured.style_statistics(),
+            None => StyleStatistics {
+                total: self.inner.lock().unwrap().chars().count(),
+                ..Default::default()
+            },
+        };
+        serde_json::to_string(&stats).map_err(|e| {
+            PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                "Failed to serialize style statistics: {}",
+                e
+            ))
+        })
+    }
+
+    pub fn diff_json(&self, other: PyRef<'_, Document>) -> PyResult<String> {
+        let ops = self.to_structured().diff(&other.to_structured());
+        serde_json::to_string(&ops).map_err(|e| {
+            PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                "Failed to serialize diff: {}",
+                e
+            ))
+        })
+    }
+
+    pub fn append_document(&self, other: PyRef<'_, Document>) {
+        // Snapshot first: `other` may be this very document
+        let other_doc = other.to_structured();
+        let mut structured = self.structured.lock().unwrap();
+        let doc = structured.get_or_insert_with(|| self.plain_structured());
+        doc.merge(other_doc);
+        *self.inner.lock().unwrap() = doc.to_plain_text();
+    }
+
+    /// With `link_style` off, links keep only their own run formatting;
+    /// `preserve_whitespace` keeps significant spaces at the edges of runs.
+    /// With `lenient`, malformed XML is skipped rather than failing the whole
+    /// file; the errors skipped are returned as warnings. `include_embedded`
+    /// reads embedded objects, such as spreadsheet tables, as body content.
+    #[pyo3(signature = (path, link_style = true, preserve_whitespace = false, lenient = false, include_embedded = false))]
+    pub fn load_odt_structured(
+        &self,
+        path: String,
+        link_style: bool,
+        preserve_whitespace: bool,
+        lenient: bool,
+        include_embedded: bool,
+    ) -> PyResult<Vec<String>> {
+        let options = ReadOptions {
+            raw_link_style: !link_style,
+            preserve_whitespace,
+            lenient,
+            include_embedded,
+            ..ReadOptions::default()
+        };
+        match read_odt_structured_warnings(&path, &options) {
+            Ok((structured_doc, warnings)) => {
+                self.load_structured(structured_doc);
+                Ok(warnings)
+            }
+            Err(e) => Err(PyErr::new::<pyo3::exceptions::PyIOError, _>(format!(
+                "Failed to read ODT file: {}",
+                e
+            ))),
+        }
+    }
+
+    pub fn load_html_str(&self, html: String) {
+        let structured_doc = StructuredDocument::from_html(&html);
+        self.load_structured(structured_doc);
+    }
+
+    pub fn get_html(&self) -> String {
+        if let Some(structured) = self.structured.lock().unwrap().as_ref() {
+            structured.to_html()
+        } else {
+            // Fallback to plain text wrapped in <p> tags
+            let text = self.inner.lock().unwrap().clone();
+            if text.is_empty() {
+                String::new()
+            } else {
+                format!("<p>{}</p>", xml_escape(&text).replace('\n', "<br/>"))
+            }
+        }
+    }
+
+    /// `unordered_bullet` and `ordered_start` pick the list markers instead of
+    /// leaving them to the browser; `ordered_format` is `decimal`,
+    /// `lower-alpha` or `lower-roman`.
+    #[pyo3(signature = (max_image_bytes = DEFAULT_MAX_EMBEDDED_IMAGE_BYTES, unordered_bullet = None, ordered_start = 1, ordered_format = "decimal"))]
+    pub fn get_html_opts(
+        &self,
+        max_image_bytes: usize,
+        unordered_bullet: Option<String>,
+        ordered_start: usize,
+        ordered_format: &str,
+    ) -> PyResult<String> {
+        let options = render_options(max_image_bytes, unordered_bullet, ordered_start, ordered_format)?;
+        // The lock must be released before falling back to get_html, which takes it again
+        let html = self.structured.lock().unwrap().as_ref().map(|structured| structured.render_html(&options));
+        Ok(html.unwrap_or_else(|| self.get_html()))
+    }
+
+    /// HTML fragment or, with `standalone`, a complete page. `include_styles`
+    /// off drops the document's inline CSS and the page stylesheet.
+    #[pyo3(signature = (standalone = false, title = None, include_styles = true, heading_anchors = true))]
+    pub fn render_html(
+        &self,
+        standalone: bool,
+        title: Option<String>,
+        include_styles: bool,
+        heading_anchors: bool,
+    ) -> String {
+        let options = RenderOptions {
+            standalone,
+            title,
+            include_styles,
+            heading_anchors,
+            ..RenderOptions::default()
+        };
+        match self.structured.lock().unwrap().as_ref() {
+            Some(structured) => structured.render_html(&options),
+            None => StructuredDocument::from_plain_text(&self.inner.lock().unwrap()).render_html(&options),
+        }
+    }
+
+    pub fn get_markdown(&self) -> String {
+        match self.structured.lock().unwrap().as_ref() {
+            Some(structured) => structured.to_markdown(),
+            None => StructuredDocument::from_plain_text(&self.inner.lock().unwrap()).to_markdown(),
+        }
+    }
+
+    /// Markdown with the given list markers; Markdown numbers are always decimal.
+    #[pyo3(signature = (unordered_bullet = None, ordered_start = 1))]
+    pub fn get_markdown_opts(&self, unordered_bullet: Option<String>, ordered_start: usize) -> PyResult<String> {
+        let options = render_options(DEFAULT_MAX_EMBEDDED_IMAGE_BYTES, unordered_bullet, ordered_start, "decimal")?;
+        Ok(match self.structured.lock().unwrap().as_ref() {
+            Some(structured) => structured.to_markdown_opts(&options),
+            None => StructuredDocument::from_plain_text(&self.inner.lock().unwrap()).to_markdown_opts(&options),
+        })
+    }
+
+    pub fn optimize(&self) {
+        if let Some(structured) = self.structured.lock().unwrap().as_mut() {
+            structured.optimize();
+        }
+    }
+
+    /// Merges consecutive runs with identical styles and drops empty runs;
+    /// the text is unchanged.
+    pub fn coalesce(&self) {
+        if let Some(structured) = self.structured.lock().unwrap().as_mut() {
+            structured.coalesce_runs();
+        }
+    }
+
+    /// `soft_break` replaces manual line breaks inside paragraphs, which
+    /// otherwise read the same as paragraph breaks.
+    #[pyo3(signature = (separator = String::new(), bullet = None, include_headings = true, line_ending = None, soft_break = None))]
+    pub fn to_plain_text_opts(
+        &self,
+        separator: String,
+        bullet: Option<String>,
+        include_headings: bool,
+        line_ending: Option<&str>,
+        soft_break: Option<String>,
+    ) -> PyResult<String> {
+        let line_ending = self.resolve_line_ending(line_ending)?;
+        let options = PlainTextOptions {
+            run_separator: separator,
+            bullet,
+            include_headings,
+            soft_break,
+        };
+        let text = match self.structured.lock().unwrap().as_ref() {
+            Some(structured) => structured.to_plain_text_opts(&options),
+            None => self.inner.lock().unwrap().clone(),
+        };
+        Ok(normalize_line_endings(&text, line_ending))
+    }
+
+    /// Plain text with section markers from headings and, with `page_breaks`,
+    /// a form feed line where each page begins. The documents' own page breaks
+    /// aren't kept, so those pages are `paginate`'s estimate for A4 at
+    /// `line_height` and move whenever the text does; they are off by default.
+    #[pyo3(signature = (page_breaks = false, line_height = 14.0))]
+    pub fn to_plain_text_with_markers(&self, page_breaks: bool, line_height: f64) -> String {
+        let doc = self.to_structured();
+        let page_starts: Vec<usize> = if page_breaks {
+            doc.paginate(&PageSetup::default(), line_height).iter().map(|page| page.start).collect()
+        } else {
+            Vec::new()
+        };
+        doc.to_plain_text_with_markers(&page_starts)
+    }
+
+    pub fn runs_with_offsets(&self) -> PyResult<String> {
+        let spans = match self.structured.lock().unwrap().as_ref() {
+            Some(structured) => structured.runs_with_offsets(),
+            None => {
+                let text = self.inner.lock().unwrap().clone();
+                vec![RunSpan {
+                    element_index: 0,
+                    start: 0,
+                    end: text.chars().count(),
+                    text,
+                    style: Some(TextStyle::default()),
+                }]
+            }
+        };
+        serde_json::to_string(&spans).map_err(|e| {
+            PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                "Failed to serialize run offsets: {}",
+                e
+            ))
+        })
+    }
+
+    /// JSON `{element_index, run_index, offset_in_run}` for a plain-text char offset.
+    pub fn locate(&self, char_offset: usize) -> PyResult<String> {
+        let location = match self.structured.lock().unwrap().as_ref() {
+            Some(structured) => structured.locate(char_offset),
+            None => StructuredDocument::from_plain_text(&self.inner.lock().unwrap()).locate(char_offset),
+        };
+        let location = location.ok_or_else(|| {
+            PyErr::new::<PyIndexError, _>(format!("offset {} is past the end of the text", char_offset))
+        })?;
+        serde_json::to_string(&location).map_err(|e| {
+            PyErr::new::<pyo3::exceptions::PyValueError, _>(format!("Failed to serialize location: {}", e))
+        })
+    }
+
+    /// Index of the element whose text (or trailing separator) holds the
+    /// plain-text char `offset`; `None` past the end of the text.
+    pub fn element_at_offset(&self, offset: usize) -> Option<usize> {
+        let ranges = match self.structured.lock().unwrap().as_ref() {
+            Some(structured) => structured.offset_map(),
+            None => StructuredDocument::from_plain_text(&self.inner.lock().unwrap()).offset_map(),
+        };
+        ranges.iter().position(|(start, end)| (*start..*end).contains(&offset))
+    }
+
+    pub fn has_structured_content(&self) -> bool {
+        self.structured.lock().unwrap().is_some()
     }
 
-    pub fn clear(&self) {
-        if let Ok(mut guard) = self.inner.lock() {
-            guard.clear();
+    /// With `preserve_container`, a DOCX file's package is kept in memory
+    /// until the next `open`, and saving as DOCX rewrites only the body text
+    /// inside it, keeping styles, theme, fonts, numbering and page setup.
+    #[pyo3(signature = (path, preserve_container = false))]
+    pub fn open(&self, path: String, preserve_container: bool) -> PyResult<()> {
+        if preserve_container && format_ext(&path) == "docx" {
+            self.open_preserved(&path).map_err(|e| PyErr::new::<PyIOError, _>(format!("{}", e)))
+        } else {
+            self.open_opts(path, false, false, false, false)
         }
-        // Clear structured representation when text is cleared
-        *self.structured.lock().unwrap() = None;
     }
 
-    pub fn load_odt_structured(&self, path: String) -> PyResult<()> {
-        match read_odt_structured(&path) {
-            Ok(structured_doc) => {
-                *self.inner.lock().unwrap() = structured_doc.to_plain_text();
-                *self.structured.lock().unwrap() = Some(structured_doc);
-                Ok(())
+    /// Opens a DOCX or ODT file through a read-only memory map, keeping peak
+    /// memory low for very large packages. Needs the `mmap` feature; without
+    /// it, and for other formats, this is `open`.
+    pub fn open_mmap(&self, path: String) -> PyResult<()> {
+        #[cfg(feature = "mmap")]
+        {
+            let text = read_text_mapped(&path, &ReadOptions::default())
+                .map_err(|e| PyErr::new::<PyIOError, _>(format!("{}", e)))?;
+            if let Some(text) = text {
+                self.load_text(text);
+                return self
+                    .load_language(&path, &format_ext(&path))
+                    .map_err(|e| PyErr::new::<PyIOError, _>(format!("{}", e)));
             }
-            Err(e) => Err(PyErr::new::<pyo3::exceptions::PyIOError, _>(format!(
-                "Failed to read ODT file: {}",
-                e
-            ))),
         }
+        self.open(path, false)
     }
 
-    pub fn get_html(&self) -> String {
-        if let Some(structured) = self.structured.lock().unwrap().as_ref() {
-            structured.to_html()
-        } else {
-            // Fallback to plain text wrapped in <p> tags
-            let text = self.inner.lock().unwrap().clone();
-            if text.is_empty() {
-                String::new()
-            } else {
-                format!("<p>{}</p>", text.replace('\n', "<br/>"))
-            }
+    /// Loads only the plain text, without building the element tree, so
+    /// large files stay cheap when formatting isn't needed.
+    pub fn open_text_only(&self, path: String) -> PyResult<()> {
+        let options = ReadOptions::default();
+        let ext = format_ext(&path);
+        let text = match ext.as_str() {
+            "docx" => read_docx_text_streaming(&path, &options),
+            "odt" => read_odt_text_streaming(&path, &options),
+            "pdf" => read_pdf_text(&path),
+            "doc" => read_doc_text(&path),
+            _ => return self.open(path, false),
         }
+        .map_err(|e| PyErr::new::<PyIOError, _>(format!("{}", e)))?;
+        self.load_text(text);
+        self.load_language(&path, &ext).map_err(|e| PyErr::new::<PyIOError, _>(format!("{}", e)))
+
     }
 
-    pub fn has_structured_content(&self) -> bool {
-        self.structured.lock().unwrap().is_some()
+    /// Opens `path` keeping only elements `start_element..end_element` of its
+    /// structure; bounds past the end are clamped. Plain text formats count
+    /// one element per line.
+    pub fn open_range(&self, path: String, start_element: usize, end_element: usize) -> PyResult<()> {
+        let mut doc = read_structured_any(&path)
+            .map_err(|e| PyErr::new::<PyIOError, _>(format!("Failed to read {}: {}", path, e)))?;
+        doc.keep_range(start_element, end_element);
+        self.load_structured(doc);
+        Ok(())
+    }
+
+    /// Opens several files as one document, in order. Plain text inputs are
+    /// joined with `separator`; if any input has structure (DOCX, ODT, EPUB,
+    /// HTML, Markdown) all of them are merged into one structured document
+    /// instead, with the separator's text between them.
+    #[pyo3(signature = (paths, separator = String::from("\n")))]
+    pub fn open_many(&self, paths: Vec<String>, separator: String) -> PyResult<()> {
+        let read = |path: &str| -> std::io::Result<(String, Option<StructuredDocument>)> {
+            match format_ext(path).as_str() {
+                "docx" | "odt" | "epub" | "html" | "htm" | "md" | "markdown" => {
+                    let structured = read_structured_any(path)?;
+                    Ok((structured.to_plain_text(), Some(structured)))
+                }
+                "doc" => Ok((read_doc_text(path)?, None)),
+                "pdf" => Ok((read_pdf_text(path)?, None)),
+                _ => Ok((read_text_file(path)?, None)),
+            }
+        };
+        let mut parts = Vec::with_capacity(paths.len());
+        for path in &paths {
+            parts.push(read(path).map_err(|e| PyErr::new::<PyIOError, _>(format!("Failed to read {}: {}", path, e)))?);
+        }
+        if parts.iter().all(|(_, structured)| structured.is_none()) {
+            let texts: Vec<String> = parts.into_iter().map(|(text, _)| text).collect();
+            self.load_text(texts.join(&separator));
+            return Ok(());
+        }
+        // Line breaks around the separator are implied by the paragraphs;
+        // anything else in it goes between the documents as text
+        let divider = separator.trim_matches(['\r', '\n']);
+        let mut combined = StructuredDocument::new();
+        for (index, (text, structured)) in parts.into_iter().enumerate() {
+            if index > 0 && !divider.is_empty() {
+                combined.merge(StructuredDocument::from_plain_text(&normalize_line_endings(divider, LineEnding::Lf)));
+            }
+            combined.merge(structured.unwrap_or_else(|| {
+                StructuredDocument::from_plain_text(&normalize_line_endings(&text, LineEnding::Lf))
+            }));
+        }
+        self.load_structured(combined);
+        Ok(())
     }
 
-    pub fn open(&self, path: String) -> PyResult<()> {
-        let ext = ext_lower(&path);
+    /// With `strict`, DOCX and ODT packages are checked for damage and missing
+    /// parts first, failing instead of returning whatever text survived.
+    /// `preserve_whitespace` keeps significant spaces at the edges of runs,
+    /// and `include_embedded` reads objects embedded in ODT text as body content.
+    #[pyo3(signature = (path, include_hidden = false, strict = false, preserve_whitespace = false, include_embedded = false))]
+    pub fn open_opts(
+        &self,
+        path: String,
+        include_hidden: bool,
+        strict: bool,
+        preserve_whitespace: bool,
+        include_embedded: bool,
+    ) -> PyResult<()> {
+        let options = ReadOptions {
+            include_hidden,
+            include_embedded,
+            preserve_whitespace,
+            ..ReadOptions::default()
+        };
+        let ext = format_ext(&path);
+        if strict {
+            verify_package(&path, &ext).map_err(|e| PyErr::new::<PyIOError, _>(format!("{}", e)))?;
+        }
+        // EPUB chapters carry headings, lists and tables worth keeping
+        if ext == "epub" {
+            let doc = read_epub_structured(&path).map_err(|e| PyErr::new::<PyIOError, _>(format!("{}", e)))?;
+            self.load_structured(doc);
+            return Ok(());
+        }
         let text = match ext.as_str() {
-            "docx" => read_docx_text(&path)
+            "docx" => read_docx_text_streaming(&path, &options)
                 .map_err(|e| PyErr::new::<PyIOError, _>(format!("{}", e)))?,
-            "odt" => read_odt_text(&path)
+            "odt" => read_odt_text(&path, &options)
                 .map_err(|e| PyErr::new::<PyIOError, _>(format!("{}", e)))?,
-            _ => std::fs::read_to_string(&path)
+            "doc" => read_doc_text(&path)
+                .map_err(|e| PyErr::new::<PyIOError, _>(format!("{}", e)))?,
+            "pdf" => read_pdf_text(&path)
+                .map_err(|e| PyErr::new::<PyIOError, _>(format!("{}", e)))?,
+            _ => read_text_file(&path)
                 .map_err(|e| PyErr::new::<PyIOError, _>(format!("{}", e)))?,
         };
-        self.set_text(text);
+        self.load_text(text);
+        self.load_language(&path, &ext).map_err(|e| PyErr::new::<PyIOError, _>(format!("{}", e)))?;
+        // Front matter belongs in the metadata, not the text
+        if matches!(ext.as_str(), "md" | "markdown") {
+            let normalized = self.inner.lock().unwrap().clone();
+            if split_front_matter(&normalized).is_some() {
+                self.load_structured(StructuredDocument::from_markdown(&normalized));
+            }
+        }
         Ok(())
     }
 
     pub fn save(&self, path: String) -> PyResult<()> {
-        let ext = ext_lower(&path);
-        let content = self.get_text();
+        self.save_with_options(path, None, None)
+    }
+
+    /// Saves like `save_with_options` and reports `uncompressed_bytes`,
+    /// `compressed_bytes` and `entry_count` for the written file.
+    #[pyo3(signature = (path, compression = None, line_ending = None))]
+    pub fn save_with_stats(
+        &self,
+        path: String,
+        compression: Option<u8>,
+        line_ending: Option<&str>,
+    ) -> PyResult<HashMap<String, u64>> {
+        self.save_with_options(path.clone(), compression, line_ending)?;
+        package_stats(&path).map_err(|e| PyErr::new::<PyIOError, _>(format!("{}", e)))
+    }
+
+    /// Saves with the document's line ending unless `line_ending` overrides it.
+    #[pyo3(signature = (path, compression = None, line_ending = None))]
+    pub fn save_with_options(&self, path: String, compression: Option<u8>, line_ending: Option<&str>) -> PyResult<()> {
+        if compression.is_some_and(|level| level > 9) {
+            return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(
+                "compression must be 0 (stored) or a deflate level from 1 to 9",
+            ));
+        }
+        let line_ending = self.resolve_line_ending(line_ending)?;
+        let ext = format_ext(&path);
+        let content = self.inner.lock().unwrap().clone();
         match ext.as_str() {
-            "docx" => write_docx_text(&path, &content)
-                .map_err(|e| PyErr::new::<PyIOError, _>(format!("{}", e)))?,
-            "odt" => write_odt_text(&path, &content)
-                .map_err(|e| PyErr::new::<PyIOError, _>(format!("{}", e)))?,
-            _ => std::fs::write(&path, content)
+            // The archive writers split paragraphs on '\n', so stray '\r' must go first
+            "docx" => match self.structured.lock().unwrap().as_ref() {
+                Some(structured) => write_docx_structured(&path, structured, compression),
+                None => write_docx_text(&path, &normalize_line_endings(&content, LineEnding::Lf), compression),
+            }
+            .and_then(|_| match self.container.lock().unwrap().as_deref() {
+                Some(original) => write_docx_into_container(&path, original, compression),
+                None => Ok(()),
+            })
+            .map_err(|e| PyErr::new::<PyIOError, _>(format!("{}", e)))?,
+            "odt" => match self.structured.lock().unwrap().as_ref() {
+                Some(structured) => write_odt_structured(&path, structured, compression),
+                None => write_odt_text(&path, &normalize_line_endings(&content, LineEnding::Lf), compression),
+            }
+            .map_err(|e| PyErr::new::<PyIOError, _>(format!("{}", e)))?,
+            // Without structured content this is get_html's paragraph-wrapped text
+            "html" | "htm" => {
+                let title = Path::new(&path).file_stem().map(|stem| stem.to_string_lossy().to_string());
+                let html = self.html_document(title.as_deref());
+                std::fs::write(&path, normalize_line_endings(&html, line_ending))
+                    .map_err(|e| PyErr::new::<PyIOError, _>(format!("{}", e)))?
+            }
+            _ => std::fs::write(&path, normalize_line_endings(&content, line_ending))
                 .map_err(|e| PyErr::new::<PyIOError, _>(format!("{}", e)))?,
         }
         Ok(())
@@ -737,7 +8481,7 @@ impl Document {
 
 #[pyfunction]
 fn read_odt(path: String) -> PyResult<String> {
-    match read_odt_text(&path) {
+    match read_odt_text(&path, &ReadOptions::default()) {
         Ok(content) => Ok(content),
         Err(e) => Err(PyErr::new::<pyo3::exceptions::PyIOError, _>(format!(
             "Failed to read ODT file: {}",
@@ -746,6 +8490,17 @@ fn read_odt(path: String) -> PyResult<String> {
     }
 }
 
+#[pyfunction]
+fn read_docx(path: String) -> PyResult<String> {
+    match read_docx_text(&path, &ReadOptions::default()) {
+        Ok(content) => Ok(content),
+        Err(e) => Err(PyErr::new::<pyo3::exceptions::PyIOError, _>(format!(
+            "Failed to read DOCX file: {}",
+            e
+        ))),
+    }
+}
+
 #[pyfunction]
 fn read_odt_structured_json(path: String) -> PyResult<String> {
     match read_odt_structured(&path) {
@@ -765,10 +8520,1274 @@ fn read_odt_structured_json(path: String) -> PyResult<String> {
     }
 }
 
+#[pyfunction]
+fn read_docx_structured_json(path: String) -> PyResult<String> {
+    let structured_doc = read_docx_structured(&path)
+        .map_err(|e| PyErr::new::<PyIOError, _>(format!("Failed to read DOCX file: {}", e)))?;
+    serde_json::to_string(&structured_doc).map_err(|e| {
+        PyErr::new::<pyo3::exceptions::PyValueError, _>(format!("Failed to serialize structured document: {}", e))
+    })
+}
+
+/// Structured JSON for any supported file, chosen by extension. Formats
+/// without structure (plain text, .doc, PDF) become one paragraph per line.
+#[pyfunction]
+fn read_structured_json(path: String) -> PyResult<String> {
+    let structured_doc = read_structured_any(&path)
+        .map_err(|e| PyErr::new::<PyIOError, _>(format!("Failed to read {}: {}", path, e)))?;
+    serde_json::to_string(&structured_doc).map_err(|e| {
+        PyErr::new::<pyo3::exceptions::PyValueError, _>(format!("Failed to serialize structured document: {}", e))
+    })
+}
+
+/// Converts every readable file under `input_dir` to `target_format`
+/// (docx, odt, html, txt or md), mirroring subdirectories in `output_dir`.
+/// Returns the written paths and the skipped inputs mapped to the reason
+/// each was skipped; a file that fails doesn't stop the batch, and one whose
+/// output name was already taken by another input is skipped.
+#[pyfunction]
+fn convert_dir_report(
+    input_dir: String,
+    output_dir: String,
+    target_format: String,
+) -> PyResult<(Vec<String>, HashMap<String, String>)> {
+    let target = target_format.trim_start_matches('.').to_lowercase();
+    if !matches!(target.as_str(), "docx" | "odt" | "html" | "htm" | "txt" | "md") {
+        return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+            "unsupported target format '{}', expected docx, odt, html, txt or md",
+            target_format
+        )));
+    }
+    std::fs::create_dir_all(&output_dir)
+        .map_err(|e| PyErr::new::<PyIOError, _>(format!("Failed to create {}: {}", output_dir, e)))?;
+    let input = Path::new(&input_dir);
+    // Outputs written inside the input directory must not be converted again
+    let exclude = Path::new(&output_dir).canonicalize().ok();
+    let mut files = Vec::new();
+    files_under(input, exclude.as_deref(), &mut files)
+        .map_err(|e| PyErr::new::<PyIOError, _>(format!("Failed to list {}: {}", input_dir, e)))?;
+
+    let mut written = Vec::new();
+    let mut skipped = HashMap::new();
+    // Inputs differing only by extension (a.docx, a.odt) map to the same
+    // output; the first one converted keeps it
+    let mut sources: HashMap<std::path::PathBuf, String> = HashMap::new();
+    for file in files {
+        let source = file.to_string_lossy().to_string();
+        if !CONVERTIBLE_EXTENSIONS.contains(&ext_lower(&source).as_str()) {
+            skipped.insert(source, "unsupported format".to_string());
+            continue;
+        }
+        let relative = file.strip_prefix(input).unwrap_or(&file);
+        let destination = Path::new(&output_dir).join(relative).with_extension(&target);
+        if let Some(other) = sources.get(&destination) {
+            skipped.insert(
+                source,
+                format!("output {} is already written from {}", destination.to_string_lossy(), other),
+            );
+            continue;
+        }
+        let result = read_structured_any(&source).and_then(|doc| {
+            if let Some(parent) = destination.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            let document = Document::default();
+            *document.inner.lock().unwrap() = doc.to_plain_text();
+            *document.structured.lock().unwrap() = Some(doc);
+            document
+                .save(destination.to_string_lossy().to_string())
+                .map_err(|e| std::io::Error::other(e.to_string()))
+        });
+        match result {
+            Ok(()) => {
+                written.push(destination.to_string_lossy().to_string());
+                sources.insert(destination, source);
+            }
+            Err(e) => {
+                skipped.insert(source, e.to_string());
+            }
+        }
+    }
+    Ok((written, skipped))
+}
+
+/// Like `convert_dir_report`, returning only the written paths.
+#[pyfunction]
+fn convert_dir(input_dir: String, output_dir: String, target_format: String) -> PyResult<Vec<String>> {
+    convert_dir_report(input_dir, output_dir, target_format).map(|(written, _)| written)
+}
+
+#[pyfunction]
+fn read_docx_form_fields_json(path: String) -> PyResult<String> {
+    let fields = read_docx_form_fields(&path)
+        .map_err(|e| PyErr::new::<PyIOError, _>(format!("Failed to read DOCX file: {}", e)))?;
+    serde_json::to_string(&fields).map_err(|e| {
+        PyErr::new::<pyo3::exceptions::PyValueError, _>(format!("Failed to serialize form fields: {}", e))
+    })
+}
+
+fn render_options(
+    max_image_bytes: usize,
+    unordered_bullet: Option<String>,
+    ordered_start: usize,
+    ordered_format: &str,
+) -> PyResult<RenderOptions> {
+    let ordered_format = ListNumbering::parse(ordered_format).ok_or_else(|| {
+        PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+            "unknown list numbering '{}', expected decimal, lower-alpha or lower-roman",
+            ordered_format
+        ))
+    })?;
+    Ok(RenderOptions {
+        max_image_bytes,
+        unordered_bullet,
+        ordered_start,
+        ordered_format,
+        ..RenderOptions::default()
+    })
+}
+
+fn parse_structured_json(json: &str) -> PyResult<StructuredDocument> {
+    serde_json::from_str(json).map_err(|e| {
+        PyErr::new::<pyo3::exceptions::PyValueError, _>(format!("Invalid structured document JSON: {}", e))
+    })
+}
+
+/// Renders structured document JSON as HTML without building a `Document`.
+#[pyfunction]
+fn structured_json_to_html(json: String) -> PyResult<String> {
+    Ok(parse_structured_json(&json)?.to_html())
+}
+
+#[pyfunction]
+fn structured_json_to_plain_text(json: String) -> PyResult<String> {
+    Ok(parse_structured_json(&json)?.to_plain_text())
+}
+
+/// (level, text) of each heading in a DOCX or ODT file, read without
+/// parsing the body text.
+#[pyfunction]
+fn read_outline_fast(path: String) -> PyResult<Vec<(u8, String)>> {
+    read_outline(&path).map_err(|e| PyErr::new::<PyIOError, _>(format!("Failed to read outline: {}", e)))
+}
+
+/// Entry names inside a DOCX, ODT or EPUB package, for diagnosing damaged files.
+#[pyfunction]
+fn list_archive_entries(path: String) -> PyResult<Vec<String>> {
+    archive_entries(&path).map_err(|e| PyErr::new::<PyIOError, _>(format!("Failed to list archive: {}", e)))
+}
+
+/// Decompressed text of one part of a DOCX, ODT or EPUB package, e.g. `word/styles.xml`.
+#[pyfunction]
+fn read_archive_part(path: String, part_name: String) -> PyResult<String> {
+    archive_part(&path, &part_name)
+        .map_err(|e| PyErr::new::<PyIOError, _>(format!("Failed to read {}: {}", part_name, e)))?
+        .ok_or_else(|| PyErr::new::<PyIndexError, _>(format!("{} has no part named {}", path, part_name)))
+}
+
 #[pymodule]
 fn word_core(_py: Python, m: &Bound<'_, pyo3::types::PyModule>) -> PyResult<()> {
     m.add_class::<Document>()?;
     m.add_function(wrap_pyfunction!(read_odt, m)?)?;
+    m.add_function(wrap_pyfunction!(read_docx, m)?)?;
     m.add_function(wrap_pyfunction!(read_odt_structured_json, m)?)?;
+    m.add_function(wrap_pyfunction!(read_docx_structured_json, m)?)?;
+    m.add_function(wrap_pyfunction!(read_structured_json, m)?)?;
+    m.add_function(wrap_pyfunction!(convert_dir, m)?)?;
+    m.add_function(wrap_pyfunction!(convert_dir_report, m)?)?;
+    m.add_function(wrap_pyfunction!(read_docx_form_fields_json, m)?)?;
+    m.add_function(wrap_pyfunction!(read_outline_fast, m)?)?;
+    m.add_function(wrap_pyfunction!(list_archive_entries, m)?)?;
+    m.add_function(wrap_pyfunction!(read_archive_part, m)?)?;
+    m.add_function(wrap_pyfunction!(structured_json_to_html, m)?)?;
+    m.add_function(wrap_pyfunction!(structured_json_to_plain_text, m)?)?;
     Ok(())
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unified_diff_hunk_ends_by_header_counts() {
+        let text = "Regards\n-- \nAnn\nP.S.";
+        let diff = "--- a\n+++ b\n@@ -1,4 +1,3 @@\n Regards\n--- \n Ann\n-P.S.\n+PS";
+        assert_eq!(apply_unified_diff(text, diff).unwrap(), "Regards\nAnn\nPS");
+        let short = "@@ -1,4 +1,3 @@\n Regards\n--- \n Ann";
+        assert!(apply_unified_diff(text, short).unwrap_err().contains("ends after"));
+    }
+
+    #[test]
+    fn table_spans_are_clamped() {
+        let doc = StructuredDocument::from_html(
+            "<table><tr><td colspan=\"1000000000\" rowspan=\"18446744073709551615\">x</td></tr></table>",
+        );
+        let DocumentElement::Table { rows } = &doc.elements[0] else { panic!("expected a table") };
+        assert_eq!((rows[0][0].colspan, rows[0][0].rowspan), (MAX_TABLE_SPAN, MAX_TABLE_SPAN));
+        assert_eq!(doc.to_plain_text().matches('\t').count(), MAX_TABLE_SPAN - 1);
+        assert_eq!(parse_span(Some("0")), 1);
+        assert_eq!(parse_span(Some("junk")), 1);
+    }
+
+    #[test]
+    fn odt_space_count_is_clamped() {
+        let mut reader = Reader::from_str("<text:s text:c=\"4000000000\"/>");
+        let Ok(Event::Empty(e)) = reader.read_event() else { panic!("expected an empty element") };
+        assert_eq!(odt_whitespace(&e).len(), MAX_ODT_SPACES);
+    }
+
+    fn temp_path(name: &str) -> String {
+        std::env::temp_dir()
+            .join(format!("word_core_test_{}_{}", std::process::id(), name))
+            .to_string_lossy()
+            .to_string()
+    }
+
+    #[test]
+    fn loaders_drop_a_preserved_container() {
+        let path = temp_path("container.docx");
+        let doc = StructuredDocument::paragraph(vec![TextRun::bold("kept"), TextRun::plain(" plain")]);
+        write_docx_structured(&path, &doc, None).unwrap();
+
+        let document = Document::new();
+        document.open_preserved(&path).unwrap();
+        assert!(document.container.lock().unwrap().is_some());
+        assert!(document.structured.lock().unwrap().as_ref().unwrap().elements[0].runs()[0].style.bold);
+        document.load_html_str("<p>other</p>".to_string());
+        assert!(document.container.lock().unwrap().is_none());
+        std::fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn paginate_keeps_a_bookmarked_first_paragraph() {
+        let mut doc = StructuredDocument::builder().paragraph("one").paragraph("two").build();
+        doc.run_lists_mut()[0][0].bookmarks.push("_Toc1".to_string());
+        let pages = doc.paginate(&PageSetup::default(), 14.0);
+        assert_eq!(pages.len(), 1);
+        assert_eq!(pages[0].start, 0);
+        assert_eq!(pages[0].elements.len(), 2);
+
+        let long = StructuredDocument::builder().paragraph("x".repeat(20_000)).paragraph("after").build();
+        let pages = long.paginate(&PageSetup::default(), 14.0);
+        assert_eq!(pages.iter().map(|page| page.elements.len()).sum::<usize>(), 2);
+        assert_eq!(pages.iter().map(|page| page.start).collect::<Vec<_>>(), vec![0, 1]);
+    }
+
+    #[test]
+    fn doc_rows_end_at_row_marks_not_empty_cells() {
+        // Two rows: "a" | "" and "c" | "d", then a paragraph
+        let text = b"a\x07\x07\x07c\x07d\x07\x07end\r";
+        let text_at = 0x300;
+        let mut word_document = vec![0u8; 1536];
+        let put32 = |bytes: &mut [u8], at: usize, value: u32| bytes[at..at + 4].copy_from_slice(&value.to_le_bytes());
+        word_document[0x0A..0x0C].copy_from_slice(&0x0200u16.to_le_bytes());
+        put32(&mut word_document, 0x4C, text.len() as u32);
+        put32(&mut word_document, 0x102, 32);
+        put32(&mut word_document, 0x106, 12);
+        put32(&mut word_document, 0x1A2, 0);
+        put32(&mut word_document, 0x1A6, 21);
+        word_document[text_at..text_at + text.len()].copy_from_slice(text);
+        // FKP page 2: one run per table paragraph, the row marks with sprmPFTtp
+        let page = &mut word_document[1024..1536];
+        let ends = [0x300, 0x302, 0x303, 0x304, 0x306, 0x308, 0x309, 0x30D];
+        for (index, end) in ends.iter().enumerate() {
+            put32(page, index * 4, *end);
+        }
+        let runs = ends.len() - 1;
+        for run in [2, 5] {
+            page[ends.len() * 4 + run * 13] = 200;
+        }
+        page[400..406].copy_from_slice(&[3, 0, 0, 0x17, 0x24, 1]);
+        page[511] = runs as u8;
+
+        let mut table = vec![0u8; 44];
+        table[0] = 0x02;
+        put32(&mut table, 1, 16);
+        put32(&mut table, 5, 0);
+        put32(&mut table, 9, text.len() as u32);
+        put32(&mut table, 15, 0x4000_0000 | (text_at as u32 * 2));
+        put32(&mut table, 32, 0x300);
+        put32(&mut table, 36, 0x30D);
+        put32(&mut table, 40, 2);
+
+        let path = temp_path("rows.doc");
+        let mut compound = cfb::create(&path).unwrap();
+        compound.create_stream("/WordDocument").unwrap().write_all(&word_document).unwrap();
+        compound.create_stream("/1Table").unwrap().write_all(&table).unwrap();
+        compound.flush().unwrap();
+        drop(compound);
+        assert_eq!(read_doc_text(&path).unwrap(), "a\t\nc\td\nend\n");
+        std::fs::remove_file(&path).ok();
+
+        let missing = read_doc_text(&temp_path("missing.doc")).unwrap_err();
+        assert_eq!(missing.kind(), std::io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn structured_docx_keeps_lists_images_and_comment_anchors() {
+        let mut doc = StructuredDocument::builder().list(["one", "two"], true).paragraph("noted").build();
+        if let DocumentElement::List { levels, display_levels, .. } = &mut doc.elements[0] {
+            *levels = vec![0, 1];
+            *display_levels = vec![1, 2];
+        }
+        let mut png = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR".to_vec();
+        png.extend_from_slice(&[0, 0, 0, 4, 0, 0, 0, 2]);
+        doc.elements.push(DocumentElement::Image {
+            name: "chart.png".to_string(),
+            mime_type: "image/png".to_string(),
+            data: png.clone(),
+            alt: Some("Chart".to_string()),
+        });
+        doc.elements.push(DocumentElement::Table { rows: vec![vec![TableCell::new(vec![TextRun::plain("cell")])]] });
+        for (text, element_index) in [("on the paragraph", 1), ("on the table", 3)] {
+            doc.comments.push(Comment {
+                author: "Ann".to_string(),
+                text: text.to_string(),
+                anchor: None,
+                element_index: Some(element_index),
+                replies: Vec::new(),
+                resolved: None,
+            });
+        }
+
+        let path = temp_path("structured.docx");
+        write_docx_structured(&path, &doc, None).unwrap();
+        let read = read_docx_structured(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+        let DocumentElement::List { items, ordered, levels, display_levels } = &read.elements[0] else {
+            panic!("expected a list, got {:?}", read.elements[0])
+        };
+        assert_eq!((items.len(), *ordered, levels.as_slice()), (2, true, [0, 1].as_slice()));
+        assert_eq!(display_levels[..2], [1, 2]);
+        let DocumentElement::Image { data, alt, mime_type, .. } = &read.elements[2] else {
+            panic!("expected an image, got {:?}", read.elements[2])
+        };
+        assert_eq!((data, alt.as_deref(), mime_type.as_str()), (&png, Some("Chart"), "image/png"));
+        let anchors: Vec<_> = read.comments.iter().map(|comment| comment.element_index).collect();
+        assert_eq!(anchors, [Some(1), Some(3)]);
+        assert_eq!(read.comments[0].anchor.as_deref(), Some("noted"));
+    }
+
+    #[test]
+    fn container_save_keeps_new_lists_and_images_apart() {
+        let image = |data: &[u8]| DocumentElement::Image {
+            name: "word/media/image1.png".to_string(),
+            mime_type: "image/png".to_string(),
+            data: data.to_vec(),
+            alt: None,
+        };
+        let mut original = StructuredDocument::builder().list(["first"], true).build();
+        original.elements.push(image(b"old"));
+        let original_path = temp_path("container_original.docx");
+        write_docx_structured(&original_path, &original, None).unwrap();
+        let original_bytes = std::fs::read(&original_path).unwrap();
+        std::fs::remove_file(&original_path).ok();
+
+        let mut edited = StructuredDocument::builder().list(["bullet"], false).build();
+        edited.elements.push(image(b"new"));
+        let path = temp_path("container_edited.docx");
+        write_docx_structured(&path, &edited, None).unwrap();
+        write_docx_into_container(&path, &original_bytes, None).unwrap();
+        let read = read_docx_structured(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+        assert!(matches!(read.elements[0], DocumentElement::List { ordered: false, .. }));
+        let DocumentElement::Image { data, .. } = &read.elements[1] else { panic!("expected an image") };
+        assert_eq!(data, b"new");
+    }
+
+    #[test]
+    fn front_matter_needs_key_value_lines() {
+        let (metadata, body) = split_front_matter("---\ntitle: \"Notes\"\ntags:\n  - a\n- b\n# comment\n---\nBody\n").unwrap();
+        assert_eq!(metadata.get("title").map(String::as_str), Some("Notes"));
+        assert!(!metadata.contains_key("tags"));
+        assert_eq!(body, "Body\n");
+        assert!(split_front_matter("---\nJust a sentence\n---\nBody\n").is_none());
+        assert!(split_front_matter("---\n  indented\n---\n").is_none());
+        let (metadata, body) = split_front_matter("---\n---\nBody").unwrap();
+        assert!(metadata.is_empty());
+        assert_eq!(body, "Body");
+    }
+
+    #[test]
+    fn reading_time_rounds_up_at_200_wpm() {
+        let words = vec!["word"; 450].join(" ");
+        let doc = StructuredDocument::builder().heading(1, "Two words").paragraph(words).build();
+        assert_eq!(doc.word_count(true, true), 452);
+        assert_eq!(doc.reading_time_minutes(200), 3);
+        assert_eq!(StructuredDocument::builder().paragraph("one").build().reading_time_minutes(200), 1);
+        assert_eq!(StructuredDocument::new().reading_time_minutes(200), 0);
+    }
+
+    /// Writes a zip at `path` holding each (name, content) entry.
+    fn write_test_zip(path: &str, entries: &[(&str, &str)]) {
+        let mut zip = ZipWriter::new(File::create(path).unwrap());
+        for (name, content) in entries {
+            zip.start_file(*name, FileOptions::default()).unwrap();
+            zip.write_all(content.as_bytes()).unwrap();
+        }
+        zip.finish().unwrap();
+    }
+
+    #[test]
+    fn plain_text_open_keeps_the_file_language() {
+        let path = temp_path("language.docx");
+        write_test_zip(
+            &path,
+            &[
+                (
+                    "word/styles.xml",
+                    "<w:styles xmlns:w=\"w\"><w:docDefaults><w:rPrDefault><w:rPr><w:lang w:val=\"fr-FR\"/></w:rPr>\
+</w:rPrDefault></w:docDefaults></w:styles>",
+                ),
+                ("word/document.xml", "<w:document xmlns:w=\"w\"><w:body><w:p><w:r><w:t>Bonjour</w:t></w:r></w:p></w:body></w:document>"),
+            ],
+        );
+        let document = Document::new();
+        document.load_text(read_docx_text_streaming(&path, &ReadOptions::default()).unwrap());
+        document.load_language(&path, "docx").unwrap();
+        std::fs::remove_file(&path).ok();
+        assert_eq!(document.get_language().as_deref(), Some("fr-FR"));
+        assert_eq!(document.to_structured().language.as_deref(), Some("fr-FR"));
+        document.load_text("other".to_string());
+        assert_eq!(document.get_language(), None);
+    }
+
+    #[test]
+    fn html_save_declares_the_opened_file_language() {
+        let path = temp_path("language.odt");
+        write_test_zip(
+            &path,
+            &[
+                (
+                    "styles.xml",
+                    "<office:document-styles xmlns:office=\"o\" xmlns:style=\"s\" xmlns:fo=\"f\"><office:styles>\
+<style:default-style style:family=\"paragraph\"><style:text-properties fo:language=\"fr\" fo:country=\"none\"/>\
+</style:default-style></office:styles></office:document-styles>",
+                ),
+                ("content.xml", "<office:document-content xmlns:office=\"o\" xmlns:text=\"t\"><office:body><office:text>\
+<text:p>Bonjour</text:p></office:text></office:body></office:document-content>"),
+            ],
+        );
+        let document = Document::new();
+        document.load_text(read_odt_text(&path, &ReadOptions::default()).unwrap());
+        document.load_language(&path, "odt").unwrap();
+        std::fs::remove_file(&path).ok();
+        assert!(document.html_document(Some("page")).contains("<html lang=\"fr\">"));
+    }
+
+    #[test]
+    fn odt_embedded_objects_stay_out_of_the_body() {
+        let path = temp_path("embedded.odt");
+        write_test_zip(
+            &path,
+            &[(
+                "content.xml",
+                "<office:document-content xmlns:office=\"o\" xmlns:text=\"t\" xmlns:draw=\"d\" xmlns:table=\"tb\">\
+<office:body><office:text><text:p>Sales</text:p><text:p><draw:frame><draw:object><office:document>\
+<office:body><office:spreadsheet><table:table><table:table-row><table:table-cell><text:p>42</text:p>\
+</table:table-cell></table:table-row></table:table></office:spreadsheet></office:body></office:document>\
+</draw:object></draw:frame></text:p></office:text></office:body></office:document-content>",
+            )],
+        );
+        let doc = read_odt_structured(&path).unwrap();
+        let embedded = ReadOptions { include_embedded: true, ..ReadOptions::default() };
+        let with_embedded = read_odt_structured_with(&path, &embedded).unwrap();
+        std::fs::remove_file(&path).ok();
+        assert!(!doc.elements.iter().any(|element| matches!(element, DocumentElement::Table { .. })));
+        assert_eq!(doc.to_plain_text().trim(), "Sales");
+        assert!(with_embedded.elements.iter().any(|element| matches!(element, DocumentElement::Table { .. })));
+    }
+
+    #[test]
+    fn adjacent_heading_chains_collapse() {
+        let mut doc = StructuredDocument::builder()
+            .heading(1, "Title")
+            .heading(2, "Subtitle")
+            .heading(3, "Tagline")
+            .heading(3, "Section")
+            .paragraph("body")
+            .build();
+        if let DocumentElement::Heading { runs, .. } = &mut doc.elements[0] {
+            runs[0].style.bold = true;
+        }
+        for element_index in [2, 4] {
+            doc.comments.push(Comment {
+                author: "Ann".to_string(),
+                text: "note".to_string(),
+                anchor: None,
+                element_index: Some(element_index),
+                replies: Vec::new(),
+                resolved: None,
+            });
+        }
+        assert_eq!(doc.collapse_adjacent_headings(": "), 2);
+        assert_eq!(doc.elements.len(), 3);
+        let runs = doc.elements[0].runs();
+        let text: String = runs.iter().map(|run| run.text.as_str()).collect();
+        assert_eq!(text, "Title: Subtitle: Tagline");
+        assert!(runs[1].style.bold);
+        assert!(matches!(&doc.elements[1], DocumentElement::Heading { level: 3, .. }));
+        let anchors: Vec<_> = doc.comments.iter().map(|comment| comment.element_index).collect();
+        assert_eq!(anchors, [Some(0), Some(2)]);
+    }
+
+    #[test]
+    fn html_links_only_keep_safe_schemes() {
+        let link = |href: &str| TextRun {
+            text: "go".to_string(),
+            style: TextStyle::default(),
+            link: Some(Link { href: href.to_string(), title: None }),
+            lang: None,
+            bookmarks: Vec::new(),
+        };
+        let doc = StructuredDocument::paragraph(vec![
+            link("https://example.com/?a=1&b=2"),
+            link("mailto:ann@example.com"),
+            link("#intro"),
+            link(" JavaScript:alert(1)"),
+            link("data:text/html,hi"),
+        ]);
+        let html = doc.to_html();
+        assert_eq!(html.matches("<a href=").count(), 3);
+        assert!(html.contains("href=\"https://example.com/?a=1&amp;b=2\""));
+        assert!(!html.to_ascii_lowercase().contains("javascript") && !html.contains("data:"));
+    }
+
+    #[test]
+    fn html_import_skips_bad_markup_and_nests_lists() {
+        for html in ["<p>one</p><!bad><p>two</p>", "<p>one</p><p a=\"1>two</p>"] {
+            assert_eq!(StructuredDocument::from_html(html).to_plain_text(), "one\ntwo\n", "{}", html);
+        }
+
+        let doc = StructuredDocument::from_html(
+            "<ul><li>a<ol><li>a1</li><li>a2<ul><li>deep</li></ul></li></ol></li><li>b</li></ul>",
+        );
+        let DocumentElement::List { items, levels, .. } = &doc.elements[0] else { panic!("expected a list") };
+        assert_eq!(items.len(), 5);
+        assert_eq!(levels, &[0, 1, 1, 2, 0]);
+        let flat = StructuredDocument::from_html("<ol><li>x</li><li>y</li></ol>");
+        assert!(matches!(&flat.elements[0], DocumentElement::List { levels, .. } if levels.is_empty()));
+    }
+
+    #[test]
+    fn hidden_text_is_skipped_unless_requested() {
+        let path = temp_path("hidden.docx");
+        write_test_zip(
+            &path,
+            &[(
+                "word/document.xml",
+                "<w:document xmlns:w=\"w\"><w:body><w:p><w:r><w:t>shown</w:t></w:r>\
+<w:r><w:rPr><w:vanish/></w:rPr><w:t> secret</w:t></w:r></w:p>\
+<w:p><w:r><w:rPr><w:vanish w:val=\"false\"/></w:rPr><w:t>unhidden</w:t></w:r></w:p>\
+<w:p><w:r><w:rPr><w:vanish/></w:rPr><w:t>all hidden</w:t></w:r></w:p></w:body></w:document>",
+            )],
+        );
+        let hidden = ReadOptions { include_hidden: true, ..ReadOptions::default() };
+        let text = read_docx_text_streaming(&path, &ReadOptions::default()).unwrap();
+        let all_text = read_docx_text_streaming(&path, &hidden).unwrap();
+        let doc = read_docx_structured(&path).unwrap();
+        let all_doc = read_docx_structured_with(&path, &hidden).unwrap();
+        std::fs::remove_file(&path).ok();
+        assert!(!text.contains("secret") && !text.contains("all hidden") && text.contains("unhidden"));
+        assert!(all_text.contains("secret") && all_text.contains("all hidden"));
+        assert_eq!(doc.to_plain_text(), "shown\nunhidden\n");
+        assert_eq!(all_doc.elements.len(), 3);
+    }
+
+    #[test]
+    fn docx_text_comes_only_from_text_elements() {
+        let path = temp_path("markers.docx");
+        write_test_zip(
+            &path,
+            &[(
+                "word/document.xml",
+                "<w:document xmlns:w=\"w\"><w:body>\n  <w:p>\n    <w:bookmarkStart w:id=\"0\" w:name=\"start\"/>\n    \
+<w:r><w:t>Hel</w:t></w:r><w:proofErr w:type=\"spellStart\"/><w:r><w:lastRenderedPageBreak/><w:t>lo</w:t></w:r>\
+<w:proofErr w:type=\"spellEnd\"/><w:bookmarkEnd w:id=\"0\"/>\n    \
+<w:r><w:instrText xml:space=\"preserve\"> PAGE </w:instrText></w:r><w:r><w:t>, world</w:t></w:r>\n  \
+</w:p>\n</w:body></w:document>",
+            )],
+        );
+        let preserving = ReadOptions { preserve_whitespace: true, ..ReadOptions::default() };
+        let text = read_docx_text_streaming(&path, &ReadOptions::default()).unwrap();
+        let preserved = read_docx_text_streaming(&path, &preserving).unwrap();
+        std::fs::remove_file(&path).ok();
+        assert_eq!(text, "Hello, world\n");
+        assert_eq!(preserved, "Hello, world\n");
+    }
+
+    #[test]
+    fn bookmarks_inside_list_items_keep_the_list_whole() {
+        let mut doc = StructuredDocument::builder().list(["one", "two", "three"], true).build();
+        if let DocumentElement::List { items, .. } = &mut doc.elements[0] {
+            let DocumentElement::Paragraph { runs, .. } = &mut items[1][0] else { panic!("expected a paragraph") };
+            runs[0].bookmarks.push("target".to_string());
+        }
+        type Write = fn(&str, &StructuredDocument, Option<u8>) -> std::io::Result<()>;
+        type Read = fn(&str) -> std::io::Result<StructuredDocument>;
+        for (name, write, read) in [
+            ("bookmarks.docx", write_docx_structured as Write, read_docx_structured as Read),
+            ("bookmarks.odt", write_odt_structured, read_odt_structured),
+        ] {
+            let path = temp_path(name);
+            write(&path, &doc, None).unwrap();
+            let read = read(&path).unwrap();
+            std::fs::remove_file(&path).ok();
+            assert_eq!(read.elements.len(), 1, "{}: {:?}", name, read.elements);
+            let DocumentElement::List { items, .. } = &read.elements[0] else {
+                panic!("{}: expected a list, got {:?}", name, read.elements[0])
+            };
+            assert_eq!(items.len(), 3, "{}", name);
+            assert_eq!(read.bookmarks(), ["target"], "{}", name);
+            assert_eq!(read.text_at_bookmark("target").as_deref(), Some("two"), "{}", name);
+        }
+    }
+
+    #[test]
+    fn nested_docx_tables_stay_in_their_cell() {
+        let path = temp_path("nested.docx");
+        write_test_zip(
+            &path,
+            &[(
+                "word/document.xml",
+                "<w:document xmlns:w=\"w\"><w:body><w:p><w:r><w:t>before</w:t></w:r></w:p>\
+<w:tbl><w:tr><w:tc><w:p><w:r><w:t>outer</w:t></w:r></w:p>\
+<w:tbl><w:tr><w:tc><w:p><w:r><w:t>a</w:t></w:r></w:p></w:tc><w:tc><w:p><w:r><w:t>b</w:t></w:r></w:p></w:tc></w:tr>\
+<w:tr><w:tc><w:p><w:r><w:t>c</w:t></w:r></w:p></w:tc><w:tc><w:p><w:r><w:t>d</w:t></w:r></w:p></w:tc></w:tr></w:tbl>\
+<w:p/></w:tc><w:tc><w:p><w:r><w:t>next</w:t></w:r></w:p></w:tc></w:tr></w:tbl>\
+<w:p><w:r><w:t>after</w:t></w:r></w:p></w:body></w:document>",
+            )],
+        );
+        let doc = read_docx_structured(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+        let kinds: Vec<_> = doc.elements.iter().map(|element| element.kind()).collect();
+        assert_eq!(kinds, ["paragraph", "table", "paragraph"]);
+        let DocumentElement::Table { rows } = &doc.elements[1] else { unreachable!() };
+        let texts: Vec<String> = rows[0]
+            .iter()
+            .map(|cell| cell.runs.iter().map(|run| run.text.as_str()).collect())
+            .collect();
+        assert_eq!(texts, ["outer\na\tb\nc\td", "next"]);
+    }
+
+    #[test]
+    fn odt_title_fields_are_not_image_alt_text() {
+        let path = temp_path("title-field.odt");
+        write_test_zip(
+            &path,
+            &[(
+                "content.xml",
+                "<office:document-content xmlns:office=\"o\" xmlns:text=\"t\"><office:body><office:text>\
+<text:p>Report:<text:title>Annual</text:title></text:p></office:text></office:body></office:document-content>",
+            )],
+        );
+        let doc = read_odt_structured(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+        assert_eq!(doc.to_plain_text().trim(), "Report:Annual");
+    }
+
+    #[test]
+    fn plain_text_numbers_nested_items_by_their_display_levels() {
+        let mut doc = StructuredDocument::builder().list(["one", "two", "three", "deep"], true).build();
+        if let DocumentElement::List { levels, display_levels, .. } = &mut doc.elements[0] {
+            *levels = vec![0, 1, 0, 200];
+            *display_levels = vec![1, 2];
+        }
+        assert_eq!(doc.to_plain_text(), "1. one\n  1.1. two\n2. three\n                1. deep\n");
+    }
+
+    #[test]
+    fn diff_finds_edits_and_moves_without_a_full_table() {
+        let doc = |texts: &[&str]| {
+            texts.iter().fold(StructuredDocument::builder(), |builder, text| builder.paragraph(*text)).build()
+        };
+        let ops = doc(&["a", "b", "c", "d"]).diff(&doc(&["b", "x", "d", "a"]));
+        assert_eq!(
+            ops,
+            [
+                DiffOp::Equal { old_index: 1, new_index: 0 },
+                DiffOp::Replace { old_index: 2, new_index: 1 },
+                DiffOp::Equal { old_index: 3, new_index: 2 },
+                DiffOp::Move { old_index: 0, new_index: 3 },
+            ]
+        );
+
+        let old = [1, 2, 3, 4, 5, 6, 7];
+        let new = [2, 9, 3, 4, 7, 1, 6];
+        let script = myers_diff(&old, &new, 100).unwrap();
+        let edits = script.iter().filter(|op| !matches!(op, DiffOp::Equal { .. })).count();
+        // LCS is 2, 3, 4, 7 (or 6), so 3 deletes and 3 inserts
+        assert_eq!(edits, 6);
+        for op in &script {
+            if let DiffOp::Equal { old_index, new_index } = op {
+                assert_eq!(old[*old_index], new[*new_index]);
+            }
+        }
+        assert!(myers_diff(&old, &new, 5).is_none());
+
+        // Past the edit cap the middle is replaced wholesale
+        let old: Vec<String> = (0..3000).map(|i| format!("old {}", i)).collect();
+        let new: Vec<String> = (0..3000).map(|i| format!("new {}", i)).collect();
+        let old_refs: Vec<&str> = old.iter().map(String::as_str).collect();
+        let new_refs: Vec<&str> = new.iter().map(String::as_str).collect();
+        let ops = doc(&old_refs).diff(&doc(&new_refs));
+        assert_eq!(ops.len(), 3000);
+        assert!(ops.iter().all(|op| matches!(op, DiffOp::Replace { .. })));
+    }
+
+    #[test]
+    fn optimize_merges_runs_and_drops_unused_styles() {
+        let bold = TextStyle { bold: true, ..TextStyle::default() };
+        let mut doc = StructuredDocument::builder()
+            .paragraph_runs(vec![
+                TextRun { style: bold.clone(), ..TextRun::plain("Bold ") },
+                TextRun { style: bold.clone(), ..TextRun::plain("text") },
+                TextRun::plain(", plain"),
+            ])
+            .build();
+        doc.styles.insert("Strong".to_string(), bold.clone());
+        doc.styles.insert("Heavy".to_string(), bold.clone());
+        doc.styles.insert("Normal".to_string(), TextStyle::default());
+        doc.styles.insert("Emphasis".to_string(), TextStyle { italic: true, ..TextStyle::default() });
+
+        doc.optimize();
+        let runs: Vec<(&str, bool)> = doc.run_lists()[0].iter().map(|run| (run.text.as_str(), run.style.bold)).collect();
+        assert_eq!(runs, [("Bold text", true), (", plain", false)]);
+        let mut names: Vec<&str> = doc.styles.keys().map(String::as_str).collect();
+        names.sort();
+        // Styles are matched by value, so both bold definitions stay
+        assert_eq!(names, ["Heavy", "Normal", "Strong"]);
+    }
+
+    #[test]
+    fn runs_with_offsets_cover_the_plain_text() {
+        let mut doc = StructuredDocument::builder()
+            .heading(1, "Título")
+            .paragraph_runs(vec![
+                TextRun { style: TextStyle { bold: true, ..TextStyle::default() }, ..TextRun::plain("Bold") },
+                TextRun::plain(" and plain"),
+            ])
+            .list(["one", "two"], true)
+            .build();
+        let cell = |text: &str| TableCell::new(vec![TextRun::plain(text)]);
+        doc.elements.push(DocumentElement::Table { rows: vec![vec![cell("a"), cell("b")], vec![cell("c"), cell("d")]] });
+        let text = doc.to_plain_text();
+        let spans = doc.runs_with_offsets();
+        assert_eq!(spans.first().map(|span| span.start), Some(0));
+        assert_eq!(spans.last().map(|span| span.end), Some(text.chars().count()));
+        for pair in spans.windows(2) {
+            assert_eq!(pair[0].end, pair[1].start);
+        }
+        let chars: Vec<char> = text.chars().collect();
+        for span in &spans {
+            assert_eq!(chars[span.start..span.end].iter().collect::<String>(), span.text);
+        }
+        let bold = spans.iter().find(|span| span.text == "Bold").unwrap();
+        assert!(bold.style.as_ref().is_some_and(|style| style.bold));
+        assert!(spans.iter().any(|span| span.style.is_none() && span.text == "\n"));
+    }
+
+    #[test]
+    fn odt_images_follow_the_text_they_are_anchored_after() {
+        let path = temp_path("anchored.odt");
+        write_test_zip(
+            &path,
+            &[
+                (
+                    "content.xml",
+                    "<office:document-content xmlns:office=\"o\" xmlns:text=\"t\" xmlns:draw=\"d\" xmlns:svg=\"s\" \
+xmlns:xlink=\"x\"><office:body><office:text><text:p>Sales by region<draw:frame>\
+<draw:image xlink:href=\"Pictures/chart.png\"/><svg:title>Chart</svg:title></draw:frame></text:p>\
+<text:p><draw:frame><draw:image xlink:href=\"Pictures/logo.png\"/></draw:frame>Logo first</text:p>\
+</office:text></office:body></office:document-content>",
+                ),
+                ("Pictures/chart.png", "chart"),
+                ("Pictures/logo.png", "logo"),
+            ],
+        );
+        let doc = read_odt_structured(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+        let order: Vec<String> = doc
+            .elements
+            .iter()
+            .map(|element| match element {
+                DocumentElement::Image { name, alt, .. } => format!("{} {:?}", name, alt),
+                other => StructuredDocument { elements: vec![other.clone()], ..StructuredDocument::new() }
+                    .to_plain_text()
+                    .trim()
+                    .to_string(),
+            })
+            .collect();
+        assert_eq!(
+            order,
+            [
+                "Sales by region",
+                "Pictures/chart.png Some(\"Chart\")",
+                "Pictures/logo.png None",
+                "Logo first",
+            ]
+        );
+
+        let small = StructuredDocument { elements: vec![doc.elements[1].clone()], ..StructuredDocument::new() };
+        let inlined = small.render_html(&RenderOptions { max_image_bytes: 5, ..RenderOptions::default() });
+        let linked = small.render_html(&RenderOptions { max_image_bytes: 4, ..RenderOptions::default() });
+        assert!(inlined.contains("src=\"data:image/png;base64,"));
+        assert!(linked.contains("src=\"Pictures/chart.png\""));
+
+        // A type that isn't a plain image/* one is linked, never put in the data URI
+        let mut hostile = small.clone();
+        if let DocumentElement::Image { mime_type, .. } = &mut hostile.elements[0] {
+            *mime_type = "image/png\" onerror=\"alert(1)".to_string();
+        }
+        let html = hostile.render_html(&RenderOptions { max_image_bytes: 5, ..RenderOptions::default() });
+        assert!(!html.contains("onerror") && html.contains("src=\"Pictures/chart.png\""), "{}", html);
+    }
+
+    #[test]
+    fn templates_are_written_and_read_as_their_document_formats() {
+        let doc = StructuredDocument::builder().heading(1, "Letter").paragraph("Dear reader").build();
+        for (name, marker) in [
+            ("template.dotx", "wordprocessingml.template.main+xml"),
+            ("template.ott", "opendocument.text-template"),
+        ] {
+            let path = temp_path(name);
+            match format_ext(&path).as_str() {
+                "docx" => write_docx_structured(&path, &doc, None).unwrap(),
+                _ => write_odt_structured(&path, &doc, None).unwrap(),
+            }
+            let mut zip = ZipArchive::new(File::open(&path).unwrap()).unwrap();
+            let types_part = if name.ends_with("dotx") { "[Content_Types].xml" } else { "mimetype" };
+            let types = read_zip_file_to_string(&mut zip.by_name(types_part).unwrap()).unwrap();
+            verify_package(&path, &format_ext(&path)).unwrap();
+            let read = read_structured_any(&path).unwrap();
+            std::fs::remove_file(&path).ok();
+            assert!(types.contains(marker), "{}: {}", name, types);
+            assert_eq!(read.to_plain_text(), "Letter\n\nDear reader\n", "{}", name);
+        }
+    }
+
+    #[test]
+    fn style_statistics_count_characters_per_flag() {
+        let style = |bold: bool, italic: bool| TextStyle { bold, italic, ..TextStyle::default() };
+        let doc = StructuredDocument::builder()
+            .paragraph_runs(vec![
+                TextRun { style: style(true, false), ..TextRun::plain("bold") },
+                TextRun { style: style(true, true), ..TextRun::plain("both") },
+                TextRun { style: style(false, true), ..TextRun::plain("it") },
+                TextRun::plain(" plain"),
+            ])
+            .list(["é"], false)
+            .build();
+        let stats = doc.style_statistics();
+        assert_eq!((stats.total, stats.bold, stats.italic, stats.underline), (17, 8, 6, 0));
+    }
+
+    #[test]
+    fn transforms_apply_in_order_and_reject_unknown_names() {
+        let mut doc = StructuredDocument::builder()
+            .heading(1, "Title")
+            .heading(4, "Too deep")
+            .paragraph_runs(vec![TextRun::plain("one "), TextRun::plain("run")])
+            .build();
+        for step in ["coalesce_runs", "normalize_headings"] {
+            doc.apply_transform(step).unwrap();
+        }
+        let levels: Vec<u8> = doc
+            .elements
+            .iter()
+            .filter_map(|element| match element {
+                DocumentElement::Heading { level, .. } => Some(*level),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(levels, [1, 2]);
+        assert_eq!(doc.run_lists()[2].len(), 1);
+        let error = doc.apply_transform("shout").unwrap_err();
+        assert!(error.contains("unknown transform 'shout'"), "{}", error);
+    }
+
+    #[test]
+    fn docx_form_fields_report_text_and_checkbox_values() {
+        let path = temp_path("form.docx");
+        write_test_zip(
+            &path,
+            &[(
+                "word/document.xml",
+                "<w:document xmlns:w=\"w\" xmlns:w14=\"w14\"><w:body>\
+<w:sdt><w:sdtPr><w:alias w:val=\"Full name\"/><w:tag w:val=\"name\"/><w:text/></w:sdtPr>\
+<w:sdtContent><w:p><w:r><w:t>Ada Lovelace</w:t></w:r></w:p></w:sdtContent></w:sdt>\
+<w:sdt><w:sdtPr><w:tag w:val=\"agree\"/><w14:checkbox><w14:checked w14:val=\"1\"/></w14:checkbox></w:sdtPr>\
+<w:sdtContent><w:r><w:t>☒</w:t></w:r></w:sdtContent></w:sdt>\
+<w:sdt><w:sdtPr><w:tag w:val=\"city\"/><w:showingPlcHdr/><w:text/></w:sdtPr>\
+<w:sdtContent><w:r><w:t>Click to enter a city</w:t></w:r></w:sdtContent></w:sdt>\
+</w:body></w:document>",
+            )],
+        );
+        let fields = read_docx_form_fields(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+        let summary: Vec<(Option<&str>, Option<&str>, &str, &str)> = fields
+            .iter()
+            .map(|field| (field.tag.as_deref(), field.alias.as_deref(), field.kind.as_str(), field.value.as_str()))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (Some("name"), Some("Full name"), "text", "Ada Lovelace"),
+                (Some("agree"), None, "checkbox", "true"),
+                (Some("city"), None, "text", ""),
+            ]
+        );
+    }
+
+    #[test]
+    fn shaded_paragraphs_render_a_background_color() {
+        let path = temp_path("shaded.docx");
+        write_test_zip(
+            &path,
+            &[(
+                "word/document.xml",
+                "<w:document xmlns:w=\"w\"><w:body>\
+<w:p><w:pPr><w:shd w:val=\"clear\" w:color=\"auto\" w:fill=\"FFF2CC\"/></w:pPr><w:r><w:t>Note</w:t></w:r></w:p>\
+<w:p><w:pPr><w:shd w:val=\"clear\" w:fill=\"auto\"/></w:pPr><w:r><w:t>Plain</w:t></w:r></w:p>\
+</w:body></w:document>",
+            )],
+        );
+        let doc = read_docx_structured(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+        let html = doc.to_html();
+        assert!(html.contains("<p style=\"background-color:#FFF2CC\">Note</p>"), "{}", html);
+        assert!(html.contains("<p>Plain</p>"), "{}", html);
+    }
+
+    #[test]
+    fn repair_fixes_and_reports_a_broken_model() {
+        let mut doc = StructuredDocument::builder()
+            .heading(9, "Deep")
+            .paragraph_runs(vec![
+                TextRun {
+                    style: TextStyle { color: Some("red".to_string()), ..TextStyle::default() },
+                    ..TextRun::plain("text")
+                },
+                TextRun::plain(""),
+                TextRun { bookmarks: vec!["end".to_string()], ..TextRun::plain("") },
+            ])
+            .build();
+        let cell = |text: &str| TableCell::new(vec![TextRun::plain(text)]);
+        doc.elements.push(DocumentElement::Table { rows: vec![vec![cell("a"), cell("b")], vec![cell("c")]] });
+        doc.styles.insert("Marked".to_string(), TextStyle { highlight: Some("#ff0".to_string()), ..TextStyle::default() });
+        doc.comments.push(Comment {
+            author: "Ann".to_string(),
+            text: "gone".to_string(),
+            anchor: None,
+            element_index: Some(7),
+            replies: Vec::new(),
+            resolved: None,
+        });
+        assert_eq!(doc.validate().len(), 5, "{:?}", doc.validate());
+
+        let repairs = doc.repair();
+        assert_eq!(
+            repairs,
+            [
+                "element 0: heading level 9 clamped to 6",
+                "element 2: padded 1 table rows to 2 columns",
+                "removed 1 empty runs",
+                "reset 2 colors that are not #rrggbb to the default",
+                "comment 0: detached from missing element 7",
+            ]
+        );
+        assert!(doc.validate().is_empty(), "{:?}", doc.validate());
+        assert_eq!(doc.bookmarks(), ["end"]);
+        assert_eq!(doc.styles["Marked"].highlight, None);
+    }
+
+    #[test]
+    fn streamed_odt_text_matches_the_structured_text() {
+        let path = temp_path("streamed.odt");
+        write_test_zip(
+            &path,
+            &[(
+                "content.xml",
+                "<office:document-content xmlns:office=\"o\" xmlns:style=\"s\" xmlns:text=\"t\" xmlns:table=\"tb\" \
+xmlns:fo=\"f\"><office:automatic-styles>\
+<style:style style:name=\"Hidden\" style:family=\"text\"><style:text-properties text:display=\"none\"/></style:style>\
+<text:list-style style:name=\"L1\"><text:list-level-style-number text:level=\"1\"/>\
+<text:list-level-style-number text:level=\"2\" text:display-levels=\"2\"/></text:list-style>\
+</office:automatic-styles><office:body><office:text>\
+<text:h text:outline-level=\"1\">Report</text:h>\
+<text:p>Shown<text:span text:style-name=\"Hidden\"> secret</text:span><text:s text:c=\"2\"/>end</text:p>\
+<text:list text:style-name=\"L1\"><text:list-item><text:p>one</text:p><text:list><text:list-item>\
+<text:p>nested</text:p></text:list-item></text:list></text:list-item></text:list>\
+<table:table><table:table-row><table:table-cell><text:p>a</text:p></table:table-cell>\
+<table:table-cell><text:p>b</text:p></table:table-cell></table:table-row></table:table>\
+<text:p>Note<office:annotation><text:p>comment</text:p></office:annotation>, here</text:p>\
+</office:text></office:body></office:document-content>",
+            )],
+        );
+        let streamed = read_odt_text_streaming(&path, &ReadOptions::default()).unwrap();
+        let structured = read_odt_structured(&path).unwrap().to_plain_text();
+        std::fs::remove_file(&path).ok();
+        assert_eq!(streamed, structured);
+        assert_eq!(streamed, "Report\n\nShown  end\n1. one\n  1.1. nested\na\tb\nNote, here\n");
+    }
+
+    #[test]
+    fn list_items_keep_several_paragraphs() {
+        let path = temp_path("item-paragraphs.odt");
+        write_test_zip(
+            &path,
+            &[(
+                "content.xml",
+                "<office:document-content xmlns:office=\"o\" xmlns:text=\"t\"><office:body><office:text>\
+<text:list><text:list-item><text:p>First point</text:p><text:p>More on it</text:p></text:list-item>\
+<text:list-item><text:p>Second point</text:p></text:list-item></text:list>\
+</office:text></office:body></office:document-content>",
+            )],
+        );
+        let doc = read_odt_structured(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+        let DocumentElement::List { items, .. } = &doc.elements[0] else {
+            panic!("expected a list, got {:?}", doc.elements[0])
+        };
+        let paragraphs: Vec<usize> = items.iter().map(|item| item_paragraphs(item).count()).collect();
+        assert_eq!(paragraphs, [2, 1]);
+        assert_eq!(doc.to_html(), "<ul><li><p>First point</p><p>More on it</p></li><li>Second point</li></ul>");
+    }
+
+    #[test]
+    fn crlf_text_round_trips_with_its_line_ending() {
+        let path = temp_path("windows.txt");
+        std::fs::write(&path, "First line\r\nSecond line\r\n\r\nThird\r\n").unwrap();
+        let document = Document::new();
+        document.load_text(read_text_file(&path).unwrap());
+        std::fs::remove_file(&path).ok();
+        assert_eq!(*document.line_ending.lock().unwrap(), LineEnding::CrLf);
+        let text = document.inner.lock().unwrap().clone();
+        assert_eq!(text, "First line\nSecond line\n\nThird\n");
+        let ending = *document.line_ending.lock().unwrap();
+        assert_eq!(normalize_line_endings(&text, ending), "First line\r\nSecond line\r\n\r\nThird\r\n");
+
+        let doc = StructuredDocument::builder().paragraph("one").paragraph("two").build();
+        assert_eq!(normalize_line_endings(&doc.to_plain_text(), LineEnding::CrLf), "one\r\ntwo\r\n");
+    }
+
+    #[test]
+    fn locate_finds_the_run_under_an_offset() {
+        let doc = StructuredDocument::builder()
+            .paragraph("Intro")
+            .paragraph_runs(vec![TextRun::plain("Hello "), TextRun::plain("world")])
+            .build();
+        let at = |element_index, run_index, offset_in_run| Some(TextLocation { element_index, run_index, offset_in_run });
+        // "Intro\n" takes offsets 0-5, so the second run starts at 12
+        assert_eq!(doc.locate(14), at(1, Some(1), 2));
+        assert_eq!(doc.locate(0), at(0, Some(0), 0));
+        // A caret after a run's last character stays in that run
+        assert_eq!(doc.locate(17), at(1, Some(1), 5));
+        assert_eq!(doc.locate(18), at(1, None, 1));
+        assert_eq!(doc.locate(19), None);
+    }
+
+    #[test]
+    fn default_font_reaches_the_html_body_and_docx_defaults() {
+        let document = Document::new();
+        document.load_text("Body text".to_string());
+        document.set_default_font("Georgia".to_string(), Some("12".to_string()));
+        let html = document.html_document(Some("Letter"));
+        assert!(html.contains("body { font-family: \"Georgia\"; font-size: 12pt; }"), "{}", html);
+
+        let path = temp_path("default_font.docx");
+        let doc = document.to_structured();
+        write_docx_structured(&path, &doc, None).unwrap();
+        let mut zip = ZipArchive::new(File::open(&path).unwrap()).unwrap();
+        let styles = read_zip_file_to_string(&mut zip.by_name("word/styles.xml").unwrap()).unwrap();
+        std::fs::remove_file(&path).ok();
+        assert!(
+            styles.contains("<w:docDefaults><w:rPrDefault><w:rPr><w:rFonts w:ascii=\"Georgia\" w:hAnsi=\"Georgia\"/>"),
+            "{}",
+            styles
+        );
+        assert!(styles.contains("<w:sz w:val=\"24\"/>"), "{}", styles);
+    }
+
+    #[test]
+    fn table_records_key_each_data_row_by_the_header_row() {
+        let cell = |text: &str| TableCell::new(vec![TextRun::plain(text)]);
+        let mut doc = StructuredDocument::builder().paragraph("Staff").build();
+        doc.elements.push(DocumentElement::Table {
+            rows: vec![
+                vec![cell("Name"), cell("Role"), cell("Name")],
+                vec![cell("Ada"), cell("Engineer"), cell("Lovelace")],
+                vec![cell("Alan"), cell("Analyst")],
+            ],
+        });
+        let records = doc.table_records(0).unwrap();
+        let keys: Vec<Vec<&str>> = records.iter().map(|record| record.keys().map(String::as_str).collect()).collect();
+        assert_eq!(keys, vec![vec!["Name", "Role", "Name_2"]; 2]);
+        assert_eq!(records[0]["Role"], "Engineer");
+        assert_eq!(records[0]["Name_2"], "Lovelace");
+        assert_eq!(records[1]["Name"], "Alan");
+        // The short row is padded with an empty value
+        assert_eq!(records[1]["Name_2"], "");
+        assert_eq!(doc.table_records(1), None);
+    }
+
+    #[test]
+    fn fast_outline_matches_the_full_table_of_contents() {
+        let doc = StructuredDocument::builder()
+            .heading(1, "Intro")
+            .paragraph("Body text that the fast reader skips")
+            .heading(2, "Part\tOne  with   spaces")
+            .paragraph_runs(vec![TextRun::bold("Not"), TextRun::plain(" a heading")])
+            .heading(3, "Line\nbreak")
+            .build();
+        for name in ["outline.docx", "outline.odt"] {
+            let path = temp_path(name);
+            match format_ext(&path).as_str() {
+                "docx" => write_docx_structured(&path, &doc, None).unwrap(),
+                _ => write_odt_structured(&path, &doc, None).unwrap(),
+            }
+            let fast = read_outline(&path).unwrap();
+            let full: Vec<(u8, String)> = read_structured_any(&path)
+                .unwrap()
+                .table_of_contents()
+                .into_iter()
+                .map(|(level, text, _)| (level, text))
+                .collect();
+            std::fs::remove_file(&path).ok();
+            assert_eq!(fast, full, "{}", name);
+            assert_eq!(fast.len(), 3, "{}", name);
+            assert!(fast[1].1.starts_with("Part\tOne"), "{}: {:?}", name, fast[1]);
+        }
+    }
+
+    #[test]
+    fn sanitize_text_drops_control_characters_and_xml_escape_keeps_separators() {
+        assert_eq!(sanitize_text("a\u{0000}b\tc\r\nd\u{2028}e\u{0085}\u{FFFF}"), "ab\tc\r\nd\ne");
+        // Writers only lose what XML can't hold; C1 controls and separators stay
+        assert_eq!(xml_escape("a\u{0000}<b>\u{2028}\u{0085}\u{FFFE}"), "a&lt;b&gt;\u{2028}\u{0085}");
+    }
+
+    #[test]
+    fn docx_comment_replies_are_written_and_read_as_a_thread() {
+        let comment = |author: &str, text: &str, resolved| Comment {
+            author: author.to_string(),
+            text: text.to_string(),
+            anchor: None,
+            element_index: Some(0),
+            replies: Vec::new(),
+            resolved,
+        };
+        let mut doc = StructuredDocument::builder().paragraph("Reviewed text").build();
+        doc.comments.push(Comment { replies: vec![comment("Bob", "Done", Some(false))], ..comment("Ann", "Check\nthis", Some(true)) });
+        let path = temp_path("comment_thread.docx");
+        write_docx_structured(&path, &doc, None).unwrap();
+        let original_bytes = std::fs::read(&path).unwrap();
+        let read = read_docx_structured(&path).unwrap();
+        assert_eq!(read.comments.len(), 1);
+        let (parent, reply) = (&read.comments[0], &read.comments[0].replies[0]);
+        assert_eq!((parent.author.as_str(), parent.text.as_str(), parent.resolved), ("Ann", "Check\nthis", Some(true)));
+        assert_eq!((reply.author.as_str(), reply.text.as_str(), reply.resolved), ("Bob", "Done", Some(false)));
+        assert_eq!(read.comments[0].replies.len(), 1);
+
+        // Saving flat comments into the package leaves none of its old threads behind
+        doc.comments = vec![comment("Ann", "Check\nthis", None), comment("Cy", "New", None)];
+        write_docx_structured(&path, &doc, None).unwrap();
+        write_docx_into_container(&path, &original_bytes, None).unwrap();
+        let read = read_docx_structured(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+        assert_eq!(read.comments.len(), 2);
+        assert!(read.comments.iter().all(|comment| comment.replies.is_empty() && comment.resolved.is_none()));
+    }
+
+    #[test]
+    fn inserted_tables_render_every_cell() {
+        let mut doc = StructuredDocument::builder().paragraph("Before").paragraph("After").build();
+        doc.insert_table(1, 2, 3).unwrap();
+        assert!(matches!(&doc.elements[1], DocumentElement::Table { rows } if rows.len() == 2 && rows[0].len() == 3));
+        let html = doc.to_html();
+        assert_eq!(html.matches("<tr").count(), 2, "{}", html);
+        assert_eq!(html.matches("<td").count(), 6, "{}", html);
+        assert!(doc.insert_table(4, 1, 1).is_err());
+    }
+
+    #[test]
+    fn set_cell_text_reads_back_through_get_tables() {
+        let document = Document::new();
+        document.load_text("Intro".to_string());
+        {
+            let mut structured = document.structured.lock().unwrap();
+            let doc = structured.get_or_insert_with(|| document.plain_structured());
+            doc.insert_table(1, 2, 2).unwrap();
+            doc.set_cell(0, 1, 0, "Total".to_string()).unwrap();
+            assert!(doc.set_cell(0, 2, 0, "past the end".to_string()).is_err());
+            assert!(doc.set_cell(1, 0, 0, "no such table".to_string()).is_err());
+        }
+        let empty = String::new();
+        assert_eq!(
+            document.get_tables(),
+            vec![vec![vec![empty.clone(), empty.clone()], vec!["Total".to_string(), empty]]]
+        );
+    }
+
+    #[test]
+    fn opml_nests_headings_by_level() {
+        let doc = StructuredDocument::builder()
+            .heading(1, "Guide")
+            .paragraph("Body text is left out")
+            .heading(2, "Setup & install")
+            .heading(2, "Usage")
+            .heading(1, "Appendix")
+            .build();
+        assert_eq!(
+            doc.to_opml(),
+            concat!(
+                "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<opml version=\"2.0\">\n<head/>\n<body>\n",
+                "  <outline text=\"Guide\">\n",
+                "    <outline text=\"Setup &amp; install\"/>\n",
+                "    <outline text=\"Usage\"/>\n",
+                "  </outline>\n",
+                "  <outline text=\"Appendix\"/>\n",
+                "</body>\n</opml>\n",
+            )
+        );
+    }
+
+    #[test]
+    fn html_lists_nest_by_item_level() {
+        let mut doc = StructuredDocument::builder().list(["a", "b", "c"], true).build();
+        if let DocumentElement::List { levels, .. } = &mut doc.elements[0] {
+            *levels = vec![0, 1, 0];
+        }
+        assert_eq!(doc.to_html(), "<ol><li>a<ol><li>b</li></ol></li><li>c</li></ol>");
+
+        let html = "<ul><li>a<ul><li>a1<ul><li>deep</li></ul></li></ul></li><li>b</li></ul>";
+        assert_eq!(StructuredDocument::from_html(html).to_html(), html);
+        // A jump of several levels goes only one list deeper
+        let mut deep = StructuredDocument::builder().list(["a", "b"], false).build();
+        if let DocumentElement::List { levels, .. } = &mut deep.elements[0] {
+            *levels = vec![0, 2];
+        }
+        assert_eq!(deep.to_html(), "<ul><li>a<ul><li>b</li></ul></li></ul>");
+    }
+
+    #[test]
+    fn plain_text_markers_clamp_heading_levels() {
+        let mut doc = StructuredDocument::builder().heading(0, "Zero").heading(9, "Nine").paragraph("Body").build();
+        assert_eq!(doc.to_plain_text_with_markers(&[]), "# Zero\n\n###### Nine\n\nBody\n");
+        doc.elements.remove(0);
+        assert_eq!(doc.to_plain_text_with_markers(&[0, 1]), "###### Nine\n\n\x0c\nBody\n");
+    }
+
+    #[test]
+    fn plain_text_markers_default_to_the_plain_text() {
+        let document = Document::new();
+        document.load_text("First paragraph\nSecond paragraph\n".to_string());
+        let plain = document.to_structured().to_plain_text();
+        assert_eq!(document.to_plain_text_with_markers(false, 14.0), plain);
+
+        // Estimated pages: with a line height near the page height every
+        // paragraph starts a page of its own
+        let paged = document.to_plain_text_with_markers(true, 600.0);
+        assert_eq!(paged, "First paragraph\n\x0c\nSecond paragraph\n");
+    }
+}